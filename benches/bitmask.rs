@@ -0,0 +1,43 @@
+use arby::signal_graph::bitmask::{ByteMask, PackedMask, VALID_MASK, WRITTEN_MASK};
+use arby::signal_graph::graph_index::GraphIndex;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// Roughly the size of a large production graph
+const OUTPUTS: usize = 4096;
+
+macro_rules! bench_mask {
+    ($c:expr, $name:expr, $mask:ty) => {{
+        let mask = <$mask>::new(OUTPUTS);
+        let blocks: Vec<GraphIndex> = (0..(OUTPUTS / <$mask>::BLOCK_SIZE) as GraphIndex).collect();
+        let touched: Vec<GraphIndex> = (0..OUTPUTS as GraphIndex).step_by(7).collect();
+
+        $c.bench_function(concat!($name, " set and get"), |b| {
+            b.iter(|| {
+                let mut valid = 0;
+                for index in &touched {
+                    mask.set(*index, WRITTEN_MASK | VALID_MASK);
+                    valid += mask.get(*index, VALID_MASK) as usize;
+                }
+                black_box(valid)
+            })
+        });
+
+        $c.bench_function(concat!($name, " clear written"), |b| {
+            b.iter(|| {
+                for index in &touched {
+                    mask.set(*index, WRITTEN_MASK);
+                }
+                mask.clear_written(black_box(&blocks));
+            })
+        });
+    }};
+}
+
+fn bitmask_benchmark(c: &mut Criterion) {
+    bench_mask!(c, "byte mask", ByteMask);
+    bench_mask!(c, "packed mask", PackedMask);
+}
+
+criterion_group!(benches, bitmask_benchmark);
+criterion_main!(benches);
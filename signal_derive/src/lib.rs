@@ -0,0 +1,608 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::token::Comma;
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, Lit, Meta, NestedMeta, Path};
+
+// Entries of every #[attr_name(...)] attribute, as `key` or `key = "value"`.
+// A bare #[attr_name] gives no entries
+fn attr_entries(
+    attrs: &[syn::Attribute],
+    attr_name: &str,
+) -> syn::Result<Vec<(syn::Ident, Option<Lit>)>> {
+    let mut entries = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path.is_ident(attr_name)) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list.nested,
+            Meta::Path(_) => continue,
+            other => {
+                return Err(syn::Error::new(
+                    other.span(),
+                    format!("expected #[{}(...)]", attr_name),
+                ))
+            }
+        };
+        for nested in list {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.get_ident().is_some() => {
+                    entries.push((nv.path.get_ident().unwrap().clone(), Some(nv.lit)))
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.get_ident().is_some() => {
+                    entries.push((path.get_ident().unwrap().clone(), None))
+                }
+                other => {
+                    return Err(syn::Error::new(
+                        other.span(),
+                        format!("unknown {} attribute", attr_name),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+// Whether ident appears anywhere in tokens, including inside groups
+fn mentions(tokens: TokenStream2, ident: &syn::Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(i) => i == *ident,
+        proc_macro2::TokenTree::Group(g) => mentions(g.stream(), ident),
+        _ => false,
+    })
+}
+
+fn has_attr(attrs: &[syn::Attribute], attr_name: &str) -> bool {
+    attrs.iter().any(|a| a.path.is_ident(attr_name))
+}
+
+fn lit_str(lit: &Option<Lit>, key: &syn::Ident) -> syn::Result<syn::LitStr> {
+    match lit {
+        Some(Lit::Str(s)) => Ok(s.clone()),
+        _ => Err(syn::Error::new(
+            key.span(),
+            format!("{} needs a string value", key),
+        )),
+    }
+}
+
+// Finds `key = "value"` inside #[attr_name(...)] attributes
+fn string_attr(
+    attrs: &[syn::Attribute],
+    attr_name: &str,
+    key: &str,
+) -> syn::Result<Option<syn::LitStr>> {
+    let mut found = None;
+    for (ident, lit) in attr_entries(attrs, attr_name)? {
+        if ident == key {
+            found = Some(lit_str(&lit, &ident)?);
+        } else {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!("unknown {} attribute {}", attr_name, ident),
+            ));
+        }
+    }
+    Ok(found)
+}
+
+// Path to the crate holding signal_graph, `crate` unless overridden with
+// something like #[signal_inputs(crate = "arby")] for use outside of it
+fn crate_path(krate: Option<syn::LitStr>) -> syn::Result<Path> {
+    match krate {
+        Some(s) => s.parse(),
+        None => Ok(syn::parse_quote!(crate)),
+    }
+}
+
+fn named_fields<'a>(
+    input: &'a DeriveInput,
+    derive: &str,
+) -> syn::Result<&'a Punctuated<Field, Comma>> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(&fields.named),
+            _ => Err(syn::Error::new(
+                input.span(),
+                format!("{} needs a struct with named fields", derive),
+            )),
+        },
+        _ => Err(syn::Error::new(
+            input.span(),
+            format!("{} can only be derived for structs", derive),
+        )),
+    }
+}
+
+// The text of the doc comments on an item, for the params schema
+fn doc_string(attrs: &[syn::Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|a| a.path.is_ident("doc"))
+        .filter_map(|a| match a.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(s) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+fn derive_signal_inputs(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = crate_path(string_attr(&input.attrs, "signal_inputs", "crate")?)?;
+    let name = &input.ident;
+    let fields = named_fields(&input, "SignalInputs")?;
+
+    let mut idents = Vec::new();
+    let mut types = Vec::new();
+    let mut input_names = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let input_name = match string_attr(&field.attrs, "input", "name")? {
+            Some(s) => s.value(),
+            None => ident.to_string(),
+        };
+        idents.push(ident);
+        types.push(&field.ty);
+        input_names.push(input_name);
+    }
+
+    let registrar = quote!(#krate::signal_graph::graph_registrar);
+    let graph = quote!(#krate::signal_graph::graph::GraphInnerMem);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #registrar::SignalInputs for #name #ty_generics #where_clause {
+            fn get_inputs() -> ::std::collections::HashMap<&'static str, #registrar::SignalType> {
+                let mut inputs = ::std::collections::HashMap::new();
+                #(
+                    inputs.insert(
+                        #input_names,
+                        <#types as #registrar::SignalInput>::signal_type(),
+                    );
+                )*
+                inputs
+            }
+
+            fn load(inputs: &mut #registrar::InputLoader) -> Result<Self, ::anyhow::Error> {
+                Ok(#name {
+                    #(
+                        #idents: <#types as #registrar::SignalInput>::load(inputs, #input_names)?,
+                    )*
+                })
+            }
+
+            #[inline]
+            fn all_valid(&self, graph: &#graph) -> bool {
+                true #(&& #registrar::SignalInput::is_valid(&self.#idents, graph))*
+            }
+
+            #[inline]
+            fn any_changed(&self, graph: &#graph) -> bool {
+                false #(|| #registrar::SignalInput::was_written(&self.#idents, graph))*
+            }
+        }
+    })
+}
+
+fn derive_signal_params(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = crate_path(string_attr(&input.attrs, "signal_params", "crate")?)?;
+    let name = &input.ident;
+    let fields = named_fields(&input, "SignalParams")?;
+
+    let mut parse_fields = Vec::new();
+    let mut checks = Vec::new();
+    let mut schema_fields = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let ty = &field.ty;
+        let param_name = ident.to_string();
+
+        let mut default = None;
+        let mut validate = None;
+        for (key, lit) in attr_entries(&field.attrs, "param")? {
+            let value: syn::Expr = lit_str(&lit, &key)?.parse()?;
+            if key == "default" {
+                default = Some(value);
+            } else if key == "validate" {
+                validate = Some((lit_str(&lit, &key)?.value(), value));
+            } else {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("unknown param attribute {}", key),
+                ));
+            }
+        }
+
+        let missing = match &default {
+            Some(default) => quote!(#default),
+            None => quote!(::anyhow::bail!("Missing parameter {}", #param_name)),
+        };
+        parse_fields.push(quote! {
+            #ident: match fields.remove(#param_name) {
+                Some(value) => ::serde_json::from_value::<#ty>(value)
+                    .map_err(|e| ::anyhow::anyhow!("Parameter {}: {}", #param_name, e))?,
+                None => #missing,
+            },
+        });
+
+        let constraint = match &validate {
+            Some((text, check)) => {
+                checks.push(quote! {
+                    {
+                        let #ident = params.#ident.clone();
+                        if !(#check) {
+                            ::anyhow::bail!("Parameter {} must satisfy {}", #param_name, #text);
+                        }
+                    }
+                });
+                quote!(Some(#text))
+            }
+            None => quote!(None),
+        };
+        let default_json = match &default {
+            Some(default) => quote! {
+                {
+                    let default: #ty = #default;
+                    ::serde_json::to_value(&default).ok()
+                }
+            },
+            None => quote!(None),
+        };
+        let type_name = quote!(#ty).to_string().replace(' ', "");
+        let description = match doc_string(&field.attrs) {
+            Some(doc) => quote!(Some(#doc)),
+            None => quote!(None),
+        };
+        schema_fields.push(quote! {
+            #krate::signal_graph::params::ParamField {
+                name: #param_name,
+                type_name: #type_name,
+                default: #default_json,
+                constraint: #constraint,
+                description: #description,
+            }
+        });
+    }
+
+    let params = quote!(#krate::signal_graph::params);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #params::SignalParams for #name #ty_generics #where_clause {
+            fn parse(json: &str) -> Result<Self, ::anyhow::Error> {
+                let mut fields: ::serde_json::Map<String, ::serde_json::Value> =
+                    ::serde_json::from_str(json)?;
+                let params = #name {
+                    #(#parse_fields)*
+                };
+                if let Some(unknown) = fields.keys().next() {
+                    ::anyhow::bail!("Unknown parameter {}", unknown);
+                }
+                #(#checks)*
+                Ok(params)
+            }
+
+            fn schema() -> #params::ParamSchema {
+                #params::ParamSchema {
+                    fields: vec![#(#schema_fields),*],
+                }
+            }
+        }
+    })
+}
+
+fn derive_signal(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut krate = None;
+    let mut cleanup = false;
+    let mut update_params = None;
+    let mut checkpoint = false;
+    let mut validate = None;
+    for (key, lit) in attr_entries(&input.attrs, "signal")? {
+        match (key.to_string().as_str(), &lit) {
+            ("crate", _) => krate = Some(lit_str(&lit, &key)?),
+            ("validate", _) => validate = Some(lit_str(&lit, &key)?.parse::<syn::Expr>()?),
+            ("cleanup", None) => cleanup = true,
+            ("update_params", None) => update_params = Some(None),
+            ("update_params", _) => {
+                update_params = Some(Some(lit_str(&lit, &key)?.parse::<syn::Expr>()?))
+            }
+            ("checkpoint", None) => checkpoint = true,
+            _ => {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("unknown signal attribute {}", key),
+                ))
+            }
+        }
+    }
+    let krate = crate_path(krate)?;
+    let name = &input.ident;
+    let fields = named_fields(&input, "Signal")?;
+
+    let registrar = quote!(#krate::signal_graph::graph_registrar);
+    let params_mod = quote!(#krate::signal_graph::params);
+
+    let mut inputs_ty = None;
+    let mut params_ty = None;
+    let mut params_field = None;
+    let mut checkpoint_field = None;
+    let mut output_names = Vec::new();
+    let mut output_types = Vec::new();
+    let mut loads = Vec::new();
+    let mut idents = Vec::new();
+    let mut inits = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let ty = &field.ty;
+        let kinds = ["inputs", "output", "params", "state"]
+            .iter()
+            .filter(|kind| has_attr(&field.attrs, kind))
+            .count();
+        if kinds > 1 {
+            return Err(syn::Error::new(
+                ident.span(),
+                "fields can only be one of inputs, output, params or state",
+            ));
+        }
+
+        let load = if has_attr(&field.attrs, "inputs") {
+            if inputs_ty.replace(ty).is_some() {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "only one inputs field allowed",
+                ));
+            }
+            quote!(<#ty as #registrar::SignalInputs>::load(&mut __inputs)?)
+        } else if has_attr(&field.attrs, "output") {
+            let output_name = match string_attr(&field.attrs, "output", "name")? {
+                Some(s) => s.value(),
+                None => ident.to_string(),
+            };
+            output_names.push(output_name.clone());
+            output_types.push(ty);
+            quote!(<#ty as #registrar::SignalOutput>::load(&mut __outputs, &mut __inputs, #output_name)?)
+        } else if has_attr(&field.attrs, "params") {
+            if params_ty.replace(ty).is_some() {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "only one params field allowed",
+                ));
+            }
+            params_field = Some(ident.clone());
+            quote! {
+                <#ty as #params_mod::SignalParams>::parse(
+                    __json.ok_or_else(|| ::anyhow::anyhow!("Missing parameters"))?
+                )?
+            }
+        } else {
+            let mut init = None;
+            for (key, lit) in attr_entries(&field.attrs, "state")? {
+                match (key.to_string().as_str(), &lit) {
+                    ("init", _) => init = Some(lit_str(&lit, &key)?.parse::<syn::Expr>()?),
+                    ("checkpoint", None) => {
+                        if checkpoint_field.replace(ident.clone()).is_some() {
+                            return Err(syn::Error::new(
+                                key.span(),
+                                "only one checkpoint field allowed",
+                            ));
+                        }
+                    }
+                    _ => {
+                        return Err(syn::Error::new(
+                            key.span(),
+                            format!("unknown state attribute {}", key),
+                        ))
+                    }
+                }
+            }
+            match init {
+                Some(init) => {
+                    inits.push(init.clone());
+                    quote!(#init)
+                }
+                None => quote!(::std::default::Default::default()),
+            }
+        };
+        loads.push(load);
+        idents.push(ident);
+    }
+
+    let get_inputs = match inputs_ty {
+        Some(ty) => quote!(<#ty as #registrar::SignalInputs>::get_inputs()),
+        None => quote!(::std::collections::HashMap::new()),
+    };
+    let has_params = params_ty.is_some();
+    let params_schema = match params_ty {
+        Some(ty) => quote!(Some(<#ty as #params_mod::SignalParams>::schema())),
+        None => quote!(None),
+    };
+    // Without a path, new parameters are parsed and replace the #[params] field,
+    // and are put back if the validator rejects them
+    let update_child_params = match &update_params {
+        Some(Some(path)) => Some(quote!(#path(child, json))),
+        Some(None) => match (&params_field, params_ty) {
+            (Some(field), Some(ty)) => {
+                if let Some(init) = inits.iter().find(|init| mentions(quote!(#init), field)) {
+                    return Err(syn::Error::new(
+                        init.span(),
+                        "state built from params isn't rebuilt by update_params, \
+                         use #[signal(update_params = \"path\")] instead",
+                    ));
+                }
+                let check = validate.as_ref().map(|validate| {
+                    quote! {
+                        if let Err(err) = #validate(child) {
+                            child.#field = __old;
+                            return Err(err);
+                        }
+                    }
+                });
+                Some(quote! {
+                    let __params = <#ty as #params_mod::SignalParams>::parse(json)?;
+                    let __old = ::std::mem::replace(&mut child.#field, __params);
+                    #check
+                    Ok(())
+                })
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "update_params needs a #[params] field or a path to call",
+                ))
+            }
+        },
+        None => None,
+    }
+    .map(|body| {
+        quote! {
+            fn update_child_params(
+                child: &mut Self,
+                json: &str,
+            ) -> Result<(), ::anyhow::Error> {
+                #body
+            }
+        }
+    });
+    let update_params = update_params.is_some();
+    let validate = validate.map(|validate| quote!(#validate(&signal)?;));
+
+    // Signals without a #[state(checkpoint)] field implement CallSignal::save_state instead
+    let checkpoint_state = match (&checkpoint_field, checkpoint) {
+        (Some(field), true) => Some(quote! {
+            fn save_child_state(child: &Self) -> Result<::serde_json::Value, ::anyhow::Error> {
+                Ok(::serde_json::to_value(&child.#field)?)
+            }
+
+            fn load_child_state(
+                child: &mut Self,
+                state: ::serde_json::Value,
+            ) -> Result<(), ::anyhow::Error> {
+                child.#field = ::serde_json::from_value(state)?;
+                Ok(())
+            }
+        }),
+        (Some(field), false) => {
+            return Err(syn::Error::new(
+                field.span(),
+                "checkpoint fields need #[signal(checkpoint)]",
+            ))
+        }
+        (None, _) => None,
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #registrar::RegisterSignal for #name #ty_generics #where_clause {
+            type Child = Self;
+            const PARAMS: bool = #has_params;
+            const CLEANUP: bool = #cleanup;
+            const UPDATE_PARAMS: bool = #update_params;
+            const CHECKPOINT: bool = #checkpoint;
+
+            fn get_inputs() -> ::std::collections::HashMap<&'static str, #registrar::SignalType> {
+                #get_inputs
+            }
+
+            fn get_outputs() -> ::std::collections::HashSet<&'static str> {
+                let mut outputs = ::std::collections::HashSet::new();
+                #(
+                    if <#output_types as #registrar::SignalOutput>::output_type().is_none() {
+                        outputs.insert(#output_names);
+                    }
+                )*
+                outputs
+            }
+
+            fn get_typed_outputs(
+            ) -> ::std::collections::HashMap<&'static str, #registrar::OutputType> {
+                let mut outputs = ::std::collections::HashMap::new();
+                #(
+                    if let Some(output_type) =
+                        <#output_types as #registrar::SignalOutput>::output_type()
+                    {
+                        outputs.insert(#output_names, output_type);
+                    }
+                )*
+                outputs
+            }
+
+            fn params_schema() -> Option<#params_mod::ParamSchema> {
+                #params_schema
+            }
+
+            #update_child_params
+
+            #checkpoint_state
+
+            #[allow(unused_mut, unused_variables)]
+            fn create(
+                mut __outputs: ::std::collections::HashMap<
+                    &'static str,
+                    #krate::signal_graph::interface_types::ConsumerOutput,
+                >,
+                mut __inputs: #registrar::InputLoader,
+                __json: Option<&str>,
+            ) -> Result<Self, ::anyhow::Error> {
+                // Loaded in declaration order so state can be built from earlier fields
+                #(let #idents = #loads;)*
+                let signal = #name {
+                    #(#idents,)*
+                };
+                #validate
+                Ok(signal)
+            }
+        }
+    })
+}
+
+// Implements SignalInputs for a struct whose fields are all signal inputs.
+// Each field is registered under its own name unless given #[input(name = "...")]
+#[proc_macro_derive(SignalInputs, attributes(signal_inputs, input))]
+pub fn signal_inputs(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_signal_inputs(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+// Implements SignalParams, parsing each field from the json object of the same name.
+// #[param(default = "expr")] fills in missing fields, and #[param(validate = "expr")]
+// rejects parameters where expr, with the field bound to its own name, is false
+#[proc_macro_derive(SignalParams, attributes(signal_params, param))]
+pub fn signal_params(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_signal_params(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+// Implements RegisterSignal from the signal's fields. Fields are marked as one of
+// #[inputs] (a SignalInputs struct), #[output] or #[output(name = "...")],
+// #[params] (a SignalParams struct) or #[state(init = "expr")].
+// Fields are created in order, and init expressions can use earlier fields by name,
+// and ? to fail creation.
+// Unmarked fields start out as Default::default().
+// #[signal(cleanup, update_params, checkpoint)] set the matching RegisterSignal consts.
+// update_params replaces the #[params] field with the parsed update and runs the
+// validator, unless given as #[signal(update_params = "path")] where path is called
+// with the signal and json. Without a path, init expressions can't use the params.
+// checkpoint saves and restores the field marked #[state(checkpoint)] with serde, and
+// without one falls back to CallSignal::save_state and load_state.
+// #[signal(validate = "path")] is called with the created signal
+#[proc_macro_derive(Signal, attributes(signal, inputs, output, params, state))]
+pub fn signal(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_signal(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
@@ -4,4 +4,97 @@ use structopt::StructOpt;
 pub struct Arguments {
     #[structopt(long, help = "HTML summary file output", default_value = "index.html")]
     pub html: String,
+    #[structopt(
+        long,
+        help = "Json file of signal name to parameters, watched and applied to the live graph"
+    )]
+    pub params_file: Option<String>,
+    #[structopt(
+        long,
+        help = "Signal state checkpoint file, restored on startup and after resets"
+    )]
+    pub checkpoint: Option<String>,
+    #[structopt(long, help = "Core to pin the signal graph thread to")]
+    pub graph_core: Option<usize>,
+    #[structopt(
+        long,
+        help = "Busy poll for market data on the signal graph thread instead of sleeping"
+    )]
+    pub busy_poll: bool,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    #[structopt(about = "Build a signal graph and write out a description of it")]
+    Describe {
+        #[structopt(
+            long,
+            help = "Graph spec file, the built-in graph is used if not given"
+        )]
+        spec: Option<String>,
+        #[structopt(long, help = "JSON description output")]
+        json: Option<String>,
+        #[structopt(long, help = "Graphviz DOT output")]
+        dot: Option<String>,
+    },
+    #[structopt(about = "Write out the parameter schema of every registered signal")]
+    Schema {
+        #[structopt(long, help = "JSON schema output, printed if not given")]
+        json: Option<String>,
+    },
+    #[structopt(
+        about = "Replay recorded market data through a signal graph and fit a linear model of signal outputs to forward returns"
+    )]
+    Train {
+        #[structopt(
+            long,
+            help = "Graph spec file, the built-in graph is used if not given"
+        )]
+        spec: Option<String>,
+        #[structopt(long, required = true, help = "Recorded market data files")]
+        data: Vec<String>,
+        #[structopt(
+            long,
+            required = true,
+            parse(try_from_str = parse_output),
+            help = "Signal output to use as a feature, as signal:output"
+        )]
+        feature: Vec<(String, String)>,
+        #[structopt(
+            long,
+            parse(try_from_str = parse_output),
+            help = "Fair price output whose forward return is fitted, as signal:output"
+        )]
+        target: (String, String),
+        #[structopt(
+            long,
+            help = "Seconds ahead of each sample that the return is taken over"
+        )]
+        horizon: f64,
+        #[structopt(long, help = "Seconds between samples", default_value = "1")]
+        interval: f64,
+        #[structopt(
+            long,
+            help = "Ridge penalty on the standardized features",
+            default_value = "0"
+        )]
+        ridge: f64,
+        #[structopt(
+            long,
+            help = "Seconds without data after which sampling restarts",
+            default_value = "60"
+        )]
+        max_gap: f64,
+        #[structopt(long, help = "Fitted model output, printed if not given")]
+        out: Option<String>,
+    },
+}
+
+fn parse_output(output: &str) -> Result<(String, String), String> {
+    match output.find(':') {
+        Some(split) => Ok((output[..split].to_string(), output[split + 1..].to_string())),
+        None => Err(format!("{} is not of the form signal:output", output)),
+    }
 }
@@ -0,0 +1,288 @@
+use crate::exchange::normalized::{FundingRate, MarketUpdates};
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::time_decay::{DecayedStats, MICROS_PER_SECOND};
+use serde::{Deserialize, Serialize};
+
+// Signals that take the basis of a derivative to a reference price out of its fair,
+// so venues trading different instruments can be aggregated on the same footing.
+// Basis is tracked relative to the reference so it doesn't move with the price level.
+// Times are graph trigger times, which are microseconds since the epoch
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+fn funding(updates: &MarketUpdates) -> Option<&FundingRate> {
+    match updates {
+        MarketUpdates::Funding(funding) => Some(funding),
+        _ => None,
+    }
+}
+
+#[derive(SignalInputs)]
+struct FundingInputs {
+    book: BookViewer,
+}
+
+// The latest funding rate of a perpetual, and seconds until it's paid.
+// Both are invalid until a rate arrives, and once its funding time has passed
+#[derive(Signal)]
+pub struct Funding {
+    #[inputs]
+    inputs: FundingInputs,
+    #[output(name = "rate")]
+    rate: ConsumerOutput,
+    #[output(name = "until_funding")]
+    until_funding: ConsumerOutput,
+    latest: Option<(f64, u64)>,
+}
+
+impl CallSignal for Funding {
+    fn call_signal(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        if let Some(funding) = funding(updates) {
+            self.latest = Some((funding.rate, funding.funding_time));
+        }
+        match self.latest.filter(|(_, funding_time)| *funding_time > time) {
+            Some((rate, funding_time)) => {
+                self.rate.set(rate, graph);
+                self.until_funding
+                    .set((funding_time - time) as f64 / MICROS_PER_SECOND, graph);
+            }
+            None => {
+                self.rate.mark_invalid(graph);
+                self.until_funding.mark_invalid(graph);
+            }
+        }
+    }
+}
+
+#[derive(SignalInputs)]
+struct BasisInputs {
+    fair: ConsumerInput,
+    reference: ConsumerInput,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct BasisState {
+    stats: DecayedStats,
+}
+
+#[derive(SignalParams)]
+struct BasisParams {
+    /// Half-life in seconds of the average basis
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+}
+
+// Average of the fair's premium over the reference, as a fraction of the reference.
+// Outputs that premium in dollars at the current reference, and the fair less it
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct Basis {
+    #[inputs]
+    inputs: BasisInputs,
+    #[output(name = "basis")]
+    basis: ConsumerOutput,
+    #[output(name = "adjusted")]
+    adjusted: ConsumerOutput,
+    #[params]
+    params: BasisParams,
+    #[state(checkpoint)]
+    state: BasisState,
+}
+
+impl CallSignal for Basis {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let prices = self
+            .inputs
+            .fair
+            .and(&self.inputs.reference, graph)
+            .get()
+            .filter(|(_, reference)| *reference > 0.0);
+        let premium = prices.map(|(fair, reference)| fair / reference - 1.0);
+        let stats = &mut self.state.stats;
+        stats.update(premium, time, self.params.half_life);
+        let basis = prices
+            .and_then(|(_, reference)| stats.mean().or(premium).map(|premium| premium * reference));
+        self.basis.set_from(basis, graph);
+        self.adjusted
+            .set_from(prices.and_then(|(fair, _)| basis.map(|b| fair - b)), graph);
+    }
+}
+
+// Annualised log basis of a future expiring in until_expiry seconds.
+// None with less than min_expiry seconds left, where it blows up
+pub fn annualised_basis(
+    fair: f64,
+    reference: f64,
+    until_expiry: f64,
+    min_expiry: f64,
+) -> Option<f64> {
+    if until_expiry < min_expiry || fair <= 0.0 || reference <= 0.0 {
+        return None;
+    }
+    Some((fair / reference).ln() * SECONDS_PER_YEAR / until_expiry)
+}
+
+// Premium over the reference a future with that annualised basis should trade at
+pub fn expected_basis(rate: f64, reference: f64, until_expiry: f64) -> f64 {
+    reference * ((rate * until_expiry / SECONDS_PER_YEAR).exp() - 1.0)
+}
+
+#[derive(SignalParams)]
+struct DatedBasisParams {
+    /// Expiry of the future, in seconds since the epoch
+    #[param(validate = "expiry > 0.0")]
+    expiry: f64,
+    /// Half-life in seconds of the average annualised basis
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+    /// Seconds before expiry at which the basis is no longer tracked
+    #[param(default = "3600.0", validate = "min_expiry > 0.0")]
+    min_expiry: f64,
+}
+
+// Basis of a dated future, averaged as an annualised rate so the average holds up as the
+// basis converges towards expiry. Outputs that rate, the basis it implies now in dollars,
+// and the fair less that basis. Everything is invalid within min_expiry of expiry
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct DatedBasis {
+    #[inputs]
+    inputs: BasisInputs,
+    #[output(name = "rate")]
+    rate: ConsumerOutput,
+    #[output(name = "basis")]
+    basis: ConsumerOutput,
+    #[output(name = "adjusted")]
+    adjusted: ConsumerOutput,
+    #[params]
+    params: DatedBasisParams,
+    #[state(checkpoint)]
+    state: BasisState,
+}
+
+impl CallSignal for DatedBasis {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let until_expiry = self.params.expiry - time as f64 / MICROS_PER_SECOND;
+        let prices = self.inputs.fair.and(&self.inputs.reference, graph).get();
+        let current = prices.and_then(|(fair, reference)| {
+            annualised_basis(fair, reference, until_expiry, self.params.min_expiry)
+        });
+        let stats = &mut self.state.stats;
+        stats.update(current, time, self.params.half_life);
+        let rate = current.and(stats.mean().or(current));
+        let basis = rate.and_then(|rate| {
+            prices.map(|(_, reference)| expected_basis(rate, reference, until_expiry))
+        });
+        self.rate.set_from(rate, graph);
+        self.basis.set_from(basis, graph);
+        self.adjusted
+            .set_from(prices.and_then(|(fair, _)| basis.map(|b| fair - b)), graph);
+    }
+}
+
+// Premium over the reference, as a fraction of it, that the funding rate still supports.
+// Funding pays for holding over the whole period, so this runs down to nothing as the
+// payment approaches
+pub fn funding_carry(rate: f64, until_funding: f64, interval: f64) -> f64 {
+    rate * (until_funding / interval).min(1.0)
+}
+
+#[derive(SignalInputs)]
+struct PerpBasisInputs {
+    fair: ConsumerInput,
+    reference: ConsumerInput,
+    funding_rate: ConsumerInput,
+    until_funding: ConsumerInput,
+}
+
+#[derive(SignalParams)]
+struct PerpBasisParams {
+    /// Half-life in seconds of the average premium beyond funding
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+    /// Seconds between funding payments
+    #[param(default = "28800.0", validate = "interval > 0.0")]
+    interval: f64,
+}
+
+// Basis of a perpetual, split into the funding still to be paid this period and an
+// average of the premium beyond it. Without a funding rate the carry is taken as zero.
+// Outputs the carry and basis in dollars, and the fair less the basis
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct PerpBasis {
+    #[inputs]
+    inputs: PerpBasisInputs,
+    #[output(name = "carry")]
+    carry: ConsumerOutput,
+    #[output(name = "basis")]
+    basis: ConsumerOutput,
+    #[output(name = "adjusted")]
+    adjusted: ConsumerOutput,
+    #[params]
+    params: PerpBasisParams,
+    #[state(checkpoint)]
+    state: BasisState,
+}
+
+impl CallSignal for PerpBasis {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let inputs = &self.inputs;
+        let carry = inputs
+            .funding_rate
+            .and(&inputs.until_funding, graph)
+            .get()
+            .map_or(0.0, |(rate, until)| {
+                funding_carry(rate, until, self.params.interval)
+            });
+        let prices = inputs
+            .fair
+            .and(&inputs.reference, graph)
+            .get()
+            .filter(|(_, reference)| *reference > 0.0);
+        let premium = prices.map(|(fair, reference)| fair / reference - 1.0 - carry);
+        let stats = &mut self.state.stats;
+        stats.update(premium, time, self.params.half_life);
+        let basis = prices.and_then(|(_, reference)| {
+            stats
+                .mean()
+                .or(premium)
+                .map(|premium| (premium + carry) * reference)
+        });
+        self.carry
+            .set_from(prices.map(|(_, reference)| carry * reference), graph);
+        self.basis.set_from(basis, graph);
+        self.adjusted
+            .set_from(prices.and_then(|(fair, _)| basis.map(|b| fair - b)), graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dated_basis_round_trips() {
+        let until_expiry = 90.0 * 24.0 * 60.0 * 60.0;
+        let rate = annualised_basis(10100.0, 10000.0, until_expiry, 3600.0).unwrap();
+        // One percent over a quarter is about four a year
+        assert!((rate - 0.0404).abs() < 1e-3);
+        assert!((expected_basis(rate, 10000.0, until_expiry) - 100.0).abs() < 1e-9);
+        // The same rate implies half the log basis with half the time left
+        let halfway = expected_basis(rate, 10000.0, until_expiry / 2.0);
+        assert!((halfway - 10000.0 * (1.01f64.sqrt() - 1.0)).abs() < 1e-9);
+
+        assert_eq!(annualised_basis(10100.0, 10000.0, 1800.0, 3600.0), None);
+        assert_eq!(annualised_basis(10100.0, 0.0, until_expiry, 3600.0), None);
+    }
+
+    #[test]
+    fn carry_runs_down_to_funding() {
+        assert_eq!(funding_carry(0.001, 28800.0, 28800.0), 0.001);
+        assert_eq!(funding_carry(0.001, 7200.0, 28800.0), 0.00025);
+        assert_eq!(funding_carry(0.001, 0.0, 28800.0), 0.0);
+        // Funding announced further out than a period is capped at one payment
+        assert_eq!(funding_carry(-0.001, 57600.0, 28800.0), -0.001);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::order_book::{cents_to_dollars, OrderBook, SidedPrice};
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+
+// Standard measures of a single book. Each is a plain function of the book so they can
+// be checked against hand-built books, with a thin signal around it.
+// Everything is None unless the book has both sides, and prices come out in dollars
+
+// Bid levels then ask levels as (cents, size), each from the touch outwards
+fn sides(
+    book: &OrderBook,
+) -> (
+    impl Iterator<Item = (usize, f64)> + '_,
+    impl Iterator<Item = (usize, f64)> + '_,
+) {
+    (
+        book.bids().map(|(price, size)| (price.unsigned(), *size)),
+        book.asks().map(|(price, size)| (price.unsigned(), *size)),
+    )
+}
+
+fn mid_cents(book: &OrderBook) -> Option<f64> {
+    match book.bbo_price() {
+        (Some(bid), Some(ask)) => Some((bid + ask) as f64 * 0.5),
+        _ => None,
+    }
+}
+
+fn imbalance(bid_size: f64, ask_size: f64) -> Option<f64> {
+    let total = bid_size + ask_size;
+    if total > 0.0 {
+        Some((bid_size - ask_size) / total)
+    } else {
+        None
+    }
+}
+
+// Bid size less ask size at the touch, over their sum. Positive when bids are heavier
+pub fn top_imbalance(book: &OrderBook) -> Option<f64> {
+    match book.bbo() {
+        (Some((_, bid_size)), Some((_, ask_size))) => imbalance(bid_size, ask_size),
+        _ => None,
+    }
+}
+
+// Imbalance of the first levels on each side, the nth level weighted by decay^n
+pub fn weighted_imbalance(book: &OrderBook, levels: usize, decay: f64) -> Option<f64> {
+    mid_cents(book)?;
+    let weigh = |side: &mut dyn Iterator<Item = (usize, f64)>| {
+        side.take(levels)
+            .fold((0.0, 1.0), |(total, weight), (_, size)| {
+                (total + weight * size, weight * decay)
+            })
+            .0
+    };
+    let (mut bids, mut asks) = sides(book);
+    imbalance(weigh(&mut bids), weigh(&mut asks))
+}
+
+// The touch prices weighted by the opposite side's size, leaning towards the side
+// more likely to trade next
+pub fn microprice(book: &OrderBook) -> Option<f64> {
+    match book.bbo() {
+        (Some((bid, bid_size)), Some((ask, ask_size))) if bid_size + ask_size > 0.0 => {
+            let bid = cents_to_dollars(bid);
+            let ask = cents_to_dollars(ask);
+            Some((bid * ask_size + ask * bid_size) / (bid_size + ask_size))
+        }
+        _ => None,
+    }
+}
+
+// The spread in ticks of tick_cents, and in basis points of the mid
+pub fn spread(book: &OrderBook, tick_cents: usize) -> Option<(f64, f64)> {
+    match book.bbo_price() {
+        (Some(bid), Some(ask)) => {
+            let spread = ask as f64 - bid as f64;
+            let mid = (bid + ask) as f64 * 0.5;
+            Some((spread / tick_cents as f64, spread / mid * 10_000.0))
+        }
+        _ => None,
+    }
+}
+
+// Total bid and ask size priced within bps basis points of the mid
+pub fn depth_within(book: &OrderBook, bps: f64) -> Option<(f64, f64)> {
+    let mid = mid_cents(book)?;
+    let distance = mid * bps / 10_000.0;
+    let (bids, asks) = sides(book);
+    let bid_depth = bids
+        .take_while(|(price, _)| mid - *price as f64 <= distance)
+        .map(|(_, size)| size)
+        .sum();
+    let ask_depth = asks
+        .take_while(|(price, _)| *price as f64 - mid <= distance)
+        .map(|(_, size)| size)
+        .sum();
+    Some((bid_depth, ask_depth))
+}
+
+// How fast size builds up moving away from the mid on each side, in size per dollar.
+// Fit through the origin to the cumulative size at each of the first levels
+pub fn book_slope(book: &OrderBook, levels: usize) -> Option<(f64, f64)> {
+    let mid = mid_cents(book)?;
+    let slope = |side: &mut dyn Iterator<Item = (usize, f64)>| {
+        let (_, cross, square) = side.take(levels).fold(
+            (0.0, 0.0, 0.0),
+            |(cumulative, cross, square), (price, size)| {
+                let cumulative = cumulative + size;
+                let distance = (price as f64 - mid).abs() * 0.01;
+                (
+                    cumulative,
+                    cross + distance * cumulative,
+                    square + distance * distance,
+                )
+            },
+        );
+        if square > 0.0 {
+            Some(cross / square)
+        } else {
+            None
+        }
+    };
+    let (mut bids, mut asks) = sides(book);
+    Some((slope(&mut bids)?, slope(&mut asks)?))
+}
+
+#[derive(SignalInputs)]
+struct BookInputs {
+    book: BookViewer,
+}
+
+#[derive(Signal)]
+pub struct TopImbalance {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "output")]
+    imbalance: ConsumerOutput,
+}
+
+impl CallSignal for TopImbalance {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.imbalance
+            .set_from(top_imbalance(&self.inputs.book.book()), graph);
+    }
+}
+
+#[derive(SignalParams)]
+struct WeightedImbalanceParams {
+    /// Levels used on each side
+    #[param(validate = "levels > 0")]
+    levels: usize,
+    /// Weight of each level relative to the one before it
+    #[param(default = "1.0", validate = "decay > 0.0 && decay <= 1.0")]
+    decay: f64,
+}
+
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct WeightedImbalance {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "output")]
+    imbalance: ConsumerOutput,
+    #[params]
+    params: WeightedImbalanceParams,
+}
+
+impl CallSignal for WeightedImbalance {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let imbalance = weighted_imbalance(
+            &self.inputs.book.book(),
+            self.params.levels,
+            self.params.decay,
+        );
+        self.imbalance.set_from(imbalance, graph);
+    }
+}
+
+#[derive(Signal)]
+pub struct Microprice {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "output")]
+    price: ConsumerOutput,
+}
+
+impl CallSignal for Microprice {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.price
+            .set_from(microprice(&self.inputs.book.book()), graph);
+    }
+}
+
+#[derive(SignalParams)]
+struct SpreadParams {
+    /// Tick size of the security in cents
+    #[param(default = "1", validate = "tick_cents > 0")]
+    tick_cents: usize,
+}
+
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct Spread {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "ticks")]
+    ticks: ConsumerOutput,
+    #[output(name = "bps")]
+    bps: ConsumerOutput,
+    #[params]
+    params: SpreadParams,
+}
+
+impl CallSignal for Spread {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let spread = spread(&self.inputs.book.book(), self.params.tick_cents);
+        self.ticks.set_from(spread.map(|(ticks, _)| ticks), graph);
+        self.bps.set_from(spread.map(|(_, bps)| bps), graph);
+    }
+}
+
+#[derive(SignalParams)]
+struct DepthParams {
+    /// Distance from the mid, in basis points, of levels that count
+    #[param(validate = "bps >= 0.0")]
+    bps: f64,
+}
+
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct DepthWithin {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "bid")]
+    bid: ConsumerOutput,
+    #[output(name = "ask")]
+    ask: ConsumerOutput,
+    #[params]
+    params: DepthParams,
+}
+
+impl CallSignal for DepthWithin {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let depth = depth_within(&self.inputs.book.book(), self.params.bps);
+        self.bid.set_from(depth.map(|(bid, _)| bid), graph);
+        self.ask.set_from(depth.map(|(_, ask)| ask), graph);
+    }
+}
+
+#[derive(SignalParams)]
+struct SlopeParams {
+    /// Levels fit on each side
+    #[param(validate = "levels > 0")]
+    levels: usize,
+}
+
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct BookSlope {
+    #[inputs]
+    inputs: BookInputs,
+    #[output(name = "bid")]
+    bid: ConsumerOutput,
+    #[output(name = "ask")]
+    ask: ConsumerOutput,
+    #[params]
+    params: SlopeParams,
+}
+
+impl CallSignal for BookSlope {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let slope = book_slope(&self.inputs.book.book(), self.params.levels);
+        self.bid.set_from(slope.map(|(bid, _)| bid), graph);
+        self.ask.set_from(slope.map(|(_, ask)| ask), graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::normalized::{BookUpdate, Side};
+
+    fn make_book(bids: &[(usize, f64)], asks: &[(usize, f64)]) -> OrderBook {
+        let level = |side| {
+            move |&(cents, size): &(usize, f64)| BookUpdate {
+                cents,
+                side,
+                size,
+                exchange_time: 0,
+            }
+        };
+        let updates = bids
+            .iter()
+            .map(level(Side::Buy))
+            .chain(asks.iter().map(level(Side::Sell)))
+            .collect();
+        let mut book = OrderBook::new();
+        book.handle_updates(&updates);
+        book
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64) {
+        let value = value.expect("No value");
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn one_sided_books_are_invalid() {
+        for book in &[
+            make_book(&[], &[]),
+            make_book(&[(10000, 1.0)], &[]),
+            make_book(&[], &[(10001, 1.0)]),
+        ] {
+            assert_eq!(top_imbalance(book), None);
+            assert_eq!(weighted_imbalance(book, 3, 0.5), None);
+            assert_eq!(microprice(book), None);
+            assert_eq!(spread(book, 1), None);
+            assert_eq!(depth_within(book, 10.0), None);
+            assert_eq!(book_slope(book, 3), None);
+        }
+    }
+
+    #[test]
+    fn touch_signals() {
+        let book = make_book(&[(10000, 3.0), (9999, 10.0)], &[(10002, 1.0)]);
+        assert_close(top_imbalance(&book), 0.5);
+        // Three times the size on the bid pulls the price three quarters of the way up
+        assert_close(microprice(&book), 100.015);
+
+        let (ticks, bps) = spread(&book, 1).unwrap();
+        assert_close(Some(ticks), 2.0);
+        assert_close(Some(bps), 2.0 / 10001.0 * 10_000.0);
+        assert_close(spread(&book, 2).map(|(ticks, _)| ticks), 1.0);
+    }
+
+    #[test]
+    fn weighted_levels() {
+        let book = make_book(
+            &[(10000, 1.0), (9999, 2.0), (9998, 4.0)],
+            &[(10001, 1.0), (10002, 1.0), (10003, 100.0)],
+        );
+        // Only the top level on each side
+        assert_close(weighted_imbalance(&book, 1, 0.5), 0.0);
+        // 1 + 2 against 1 + 1
+        assert_close(weighted_imbalance(&book, 2, 1.0), 0.2);
+        // 1 + 1 + 1 against 1 + 0.5 + 25
+        assert_close(weighted_imbalance(&book, 3, 0.5), (3.0 - 26.5) / 29.5);
+    }
+
+    #[test]
+    fn depth_and_slope() {
+        // Mid of 100.00
+        let book = make_book(
+            &[(9999, 1.0), (9998, 2.0), (9990, 5.0)],
+            &[(10001, 2.0), (10003, 2.0)],
+        );
+        // One basis point is a cent either side of the mid
+        assert_eq!(depth_within(&book, 1.0), Some((1.0, 2.0)));
+        assert_eq!(depth_within(&book, 3.0), Some((3.0, 4.0)));
+        assert_eq!(depth_within(&book, 0.5), Some((0.0, 0.0)));
+
+        // Bids: cumulative 1 at 1c and 3 at 2c, a fit of (0.01 + 0.06) / 0.0005
+        let (bid, ask) = book_slope(&book, 2).unwrap();
+        assert_close(Some(bid), 140.0);
+        // Asks: 2 at 1c and 4 at 3c, (0.02 + 0.12) / 0.001
+        assert_close(Some(ask), 140.0);
+        let (bid, _) = book_slope(&book, 3).unwrap();
+        assert!(bid < 140.0);
+    }
+}
@@ -1,17 +1,55 @@
-use crate::displacement::Premium;
+use crate::basis::{Basis, DatedBasis, Funding, PerpBasis};
+use crate::book_signals::{
+    BookSlope, DepthWithin, Microprice, Spread, TopImbalance, WeightedImbalance,
+};
+use crate::cross_venue::{LeadLag, PairSpread};
+use crate::displacement::{Displacement, Premium};
 use crate::ema::Ema;
+use crate::expr::Expression;
 use crate::fair_value::FairValue;
+use crate::linear_model::Model;
 use crate::local_book::BookImprovedSignal;
 use crate::remote_venue_aggregator::RemoteVenueAggregator;
 use crate::signal_graph::graph_error::GraphError;
 use crate::signal_graph::graph_registrar::*;
+use crate::time_decay::{TimeEma, TimeEwmv, TimeZScore};
+use crate::trade_flow::{AggressorImbalance, SignedVolume, TradeThrough, TradeVwap};
+use crate::volatility::{RangeVol, RealizedVol, SpreadRegime};
 
 pub fn generate_registrar() -> Result<GraphRegistrar, GraphError> {
     GraphRegistrar::new(&[
         ("book_fair", make_signal_for::<FairValue>()),
+        ("top_imbalance", make_signal_for::<TopImbalance>()),
+        ("weighted_imbalance", make_signal_for::<WeightedImbalance>()),
+        ("microprice", make_signal_for::<Microprice>()),
+        ("spread", make_signal_for::<Spread>()),
+        ("depth_within", make_signal_for::<DepthWithin>()),
+        ("book_slope", make_signal_for::<BookSlope>()),
         ("aggregator", make_signal_for::<RemoteVenueAggregator>()),
         ("ema", make_signal_for::<Ema>()),
+        ("time_ema", make_signal_for::<TimeEma>()),
+        ("time_ewmv", make_signal_for::<TimeEwmv>()),
+        ("time_zscore", make_signal_for::<TimeZScore>()),
         ("bbo_improved", make_signal_for::<BookImprovedSignal>()),
         ("premium", make_signal_for::<Premium>()),
+        ("expr", make_signal_for::<Expression>()),
+        ("model", make_signal_for::<Model>()),
+        ("displacement", make_signal_for::<Displacement>()),
+        ("lead_lag", make_signal_for::<LeadLag>()),
+        ("pair_spread", make_signal_for::<PairSpread>()),
+        ("signed_volume", make_signal_for::<SignedVolume>()),
+        ("trade_vwap", make_signal_for::<TradeVwap>()),
+        (
+            "aggressor_imbalance",
+            make_signal_for::<AggressorImbalance>(),
+        ),
+        ("trade_through", make_signal_for::<TradeThrough>()),
+        ("funding", make_signal_for::<Funding>()),
+        ("basis", make_signal_for::<Basis>()),
+        ("dated_basis", make_signal_for::<DatedBasis>()),
+        ("perp_basis", make_signal_for::<PerpBasis>()),
+        ("realized_vol", make_signal_for::<RealizedVol>()),
+        ("range_vol", make_signal_for::<RangeVol>()),
+        ("spread_regime", make_signal_for::<SpreadRegime>()),
     ])
 }
@@ -0,0 +1,316 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::signal_graph::params::SignalParams;
+use crate::time_decay::{decay_weight, DecayedStats, MICROS_PER_SECOND};
+use crate::volatility::{interval_micros, GridSampler};
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+
+// Signals relating the fair prices of two venues
+
+// Pearson correlation of the pairs, None if either side doesn't vary
+fn correlation(pairs: impl Iterator<Item = (f64, f64)> + Clone) -> Option<f64> {
+    let (count, sum_x, sum_y) = pairs
+        .clone()
+        .fold((0.0, 0.0, 0.0), |(n, x, y), (a, b)| (n + 1.0, x + a, y + b));
+    if count < 2.0 {
+        return None;
+    }
+    let (mean_x, mean_y) = (sum_x / count, sum_y / count);
+    let (cov, var_x, var_y) = pairs.fold((0.0, 0.0, 0.0), |(c, vx, vy), (a, b)| {
+        let (dx, dy) = (a - mean_x, b - mean_y);
+        (c + dx * dy, vx + dx * dx, vy + dy * dy)
+    });
+    if var_x > 0.0 && var_y > 0.0 {
+        Some(cov / (var_x * var_y).sqrt())
+    } else {
+        None
+    }
+}
+
+// Correlation of the last window follower returns with leader returns lag samples
+// earlier, for every lag from -max_lag to max_lag. Positive lags have the leader moving
+// first. None until there are window + max_lag samples
+pub fn cross_correlations(
+    returns: &VecDeque<(f64, f64)>,
+    window: usize,
+    max_lag: usize,
+) -> Option<Vec<(isize, Option<f64>)>> {
+    if returns.len() < window + max_lag {
+        return None;
+    }
+    let end = returns.len();
+    let start = end - window;
+    let max_lag = max_lag as isize;
+    Some(
+        (-max_lag..=max_lag)
+            .map(|lag| {
+                // Negative lags shift the window so both ends stay inside the samples
+                let shift = lag.min(0);
+                let pairs = ((start as isize + shift) as usize..(end as isize + shift) as usize)
+                    .map(move |index| {
+                        (returns[(index as isize - lag) as usize].0, returns[index].1)
+                    });
+                (lag, correlation(pairs))
+            })
+            .collect(),
+    )
+}
+
+#[derive(SignalInputs)]
+struct PairInputs {
+    leader: ConsumerInput,
+    follower: ConsumerInput,
+}
+
+#[derive(SignalParams)]
+struct LeadLagParams {
+    /// Seconds between price samples
+    #[param(validate = "interval > 0.0")]
+    interval: f64,
+    /// Most samples either price is checked as leading the other by
+    #[param(validate = "max_lag > 0")]
+    max_lag: usize,
+    /// Samples each correlation is taken over
+    #[param(validate = "window > 2")]
+    window: usize,
+}
+
+// Samples both prices on a fixed time grid and correlates their log returns at every lag
+// up to max_lag. Outputs the lag with the strongest correlation in seconds, positive if
+// the leader moves first, that correlation, and the correlation with no lag.
+// A smaller window or max_lag trims the buffer on the next sample, and a new interval
+// starts sampling over
+#[derive(Signal)]
+#[signal(update_params = "LeadLag::apply_params")]
+pub struct LeadLag {
+    #[inputs]
+    inputs: PairInputs,
+    #[output(name = "lag")]
+    lag: ConsumerOutput,
+    #[output(name = "correlation")]
+    correlation: ConsumerOutput,
+    #[output(name = "zero_lag")]
+    zero_lag: ConsumerOutput,
+    #[params]
+    params: LeadLagParams,
+    sampler: GridSampler<(f64, f64)>,
+    last_sample: Option<(f64, f64)>,
+    returns: VecDeque<(f64, f64)>,
+}
+
+impl LeadLag {
+    // Returns sampled on the old grid can't be correlated with ones on the new grid
+    fn apply_params(&mut self, json: &str) -> Result<(), anyhow::Error> {
+        let params = LeadLagParams::parse(json)?;
+        if params.interval != self.params.interval {
+            self.sampler = GridSampler::default();
+            self.last_sample = None;
+            self.returns.clear();
+        }
+        self.params = params;
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.params.window + self.params.max_lag
+    }
+
+    fn sample(&mut self, (leader, follower): (f64, f64)) {
+        if let Some((last_leader, last_follower)) = self.last_sample {
+            self.returns
+                .push_back(((leader / last_leader).ln(), (follower / last_follower).ln()));
+            while self.returns.len() > self.capacity() {
+                self.returns.pop_front();
+            }
+        }
+        self.last_sample = Some((leader, follower));
+    }
+
+    fn set_outputs(&mut self, graph: &GraphHandle) {
+        let correlations =
+            cross_correlations(&self.returns, self.params.window, self.params.max_lag);
+        let best = correlations.as_ref().and_then(|correlations| {
+            correlations
+                .iter()
+                .filter_map(|(lag, correlation)| correlation.map(|c| (*lag, c)))
+                .max_by(|(_, a), (_, b)| a.abs().partial_cmp(&b.abs()).unwrap())
+        });
+        let zero_lag = correlations.and_then(|correlations| correlations[self.params.max_lag].1);
+        self.lag.set_from(
+            best.map(|(lag, _)| lag as f64 * self.params.interval),
+            graph,
+        );
+        self.correlation.set_from(best.map(|(_, c)| c), graph);
+        self.zero_lag.set_from(zero_lag, graph);
+    }
+}
+
+impl CallSignal for LeadLag {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let prices = self
+            .inputs
+            .leader
+            .and(&self.inputs.follower, graph)
+            .get()
+            .filter(|(leader, follower)| *leader > 0.0 && *follower > 0.0);
+        let interval = interval_micros(self.params.interval);
+        let sampled = self.sampler.update(prices, time, interval);
+        if let Some((held, due)) = sampled {
+            // Past a full buffer, more samples of the same prices change nothing
+            let due = due.min(self.capacity() as u64 + 1);
+            for _ in 0..due {
+                self.sample(held);
+            }
+        }
+
+        if prices.is_none() {
+            // Returns aren't taken across gaps
+            self.last_sample = None;
+            self.lag.mark_invalid(graph);
+            self.correlation.mark_invalid(graph);
+            self.zero_lag.mark_invalid(graph);
+        } else if sampled.is_some() || !self.lag.is_valid(graph) {
+            self.set_outputs(graph);
+        }
+    }
+}
+
+// Time-decayed means of two prices and their products, each weighted by how long
+// the prices were held like DecayedStats
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct PairMoments {
+    // Means of a, b, a * b and b * b
+    means: Option<[f64; 4]>,
+    held: Option<(f64, f64, u64)>,
+}
+
+impl PairMoments {
+    pub fn update(&mut self, values: Option<(f64, f64)>, time: u64, half_life: f64) {
+        if let Some((a, b, since)) = self.held {
+            let elapsed = time.saturating_sub(since) as f64 / MICROS_PER_SECOND;
+            if elapsed > 0.0 {
+                let weight = decay_weight(elapsed, half_life);
+                let sample = [a, b, a * b, b * b];
+                let means = self.means.get_or_insert(sample);
+                for (mean, value) in means.iter_mut().zip(sample.iter()) {
+                    *mean += weight * (value - *mean);
+                }
+            }
+        }
+        self.held = values.map(|(a, b)| (a, b, time));
+    }
+
+    // Regression coefficient of a on b, None if b hasn't moved
+    pub fn beta(&self) -> Option<f64> {
+        let [a, b, ab, bb] = self.means?;
+        let var_b = bb - b * b;
+        // Relative to the scale of b, anything smaller is rounding error
+        if var_b > 1e-12 * bb.abs() {
+            Some((ab - a * b) / var_b)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(SignalParams)]
+struct PairSpreadParams {
+    /// Half-life in seconds of the averages beta and the spread are taken over
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PairSpreadState {
+    moments: PairMoments,
+    spread: DecayedStats,
+}
+
+// Rolling hedge ratio between two prices, the spread of the leader over beta followers,
+// and how far that spread is from its own decayed average in standard deviations
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct PairSpread {
+    #[inputs]
+    inputs: PairInputs,
+    #[output(name = "beta")]
+    beta: ConsumerOutput,
+    #[output(name = "spread")]
+    spread: ConsumerOutput,
+    #[output(name = "zscore")]
+    zscore: ConsumerOutput,
+    #[params]
+    params: PairSpreadParams,
+    #[state(checkpoint)]
+    state: PairSpreadState,
+}
+
+impl CallSignal for PairSpread {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let prices = self.inputs.leader.and(&self.inputs.follower, graph).get();
+        let state = &mut self.state;
+        state.moments.update(prices, time, self.params.half_life);
+        let beta = prices.and(state.moments.beta());
+        let spread =
+            beta.and_then(|beta| prices.map(|(leader, follower)| leader - beta * follower));
+        state.spread.update(spread, time, self.params.half_life);
+        self.beta.set_from(beta, graph);
+        self.spread.set_from(spread, graph);
+        self.zscore
+            .set_from(spread.and(state.spread.zscore()), graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    #[test]
+    fn finds_leading_series() {
+        // The follower repeats the leader's returns two samples later
+        let leader: Vec<f64> = (0..40).map(|i| ((i * 7919) % 13) as f64 - 6.0).collect();
+        let returns: VecDeque<_> = (0..40)
+            .map(|i| (leader[i], if i >= 2 { leader[i - 2] } else { 0.0 }))
+            .collect();
+        let correlations = cross_correlations(&returns, 30, 3).unwrap();
+        assert_eq!(correlations.len(), 7);
+        let (lag, best) = correlations
+            .iter()
+            .filter_map(|(lag, c)| c.map(|c| (*lag, c)))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .unwrap();
+        assert_eq!(lag, 2);
+        assert!((best - 1.0).abs() < 1e-9);
+
+        // Swapping them makes the lag negative
+        let swapped: VecDeque<_> = returns.iter().map(|(a, b)| (*b, *a)).collect();
+        let correlations = cross_correlations(&swapped, 30, 3).unwrap();
+        assert!((correlations[1].1.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(correlations[1].0, -2);
+
+        assert!(cross_correlations(&returns, 38, 3).is_none());
+    }
+
+    #[test]
+    fn pair_beta() {
+        let mut moments = PairMoments::default();
+        assert_eq!(moments.beta(), None);
+        // a is always 2b + 5
+        for step in 0..100u64 {
+            let b = 100.0 + ((step * 37) % 11) as f64;
+            moments.update(Some((2.0 * b + 5.0, b)), step * SECOND, 20.0);
+        }
+        assert!((moments.beta().unwrap() - 2.0).abs() < 1e-6);
+
+        let mut flat = PairMoments::default();
+        for step in 0..10u64 {
+            flat.update(Some((step as f64, 100.0)), step * SECOND, 20.0);
+        }
+        assert_eq!(flat.beta(), None);
+    }
+}
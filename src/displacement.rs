@@ -1,17 +1,25 @@
 use crate::exchange::normalized::MarketUpdates;
 use crate::signal_graph::graph_registrar::*;
 use crate::signal_graph::interface_types::*;
-
-use std::collections::{HashMap, HashSet};
+use crate::time_decay::DecayedStats;
+use serde::{Deserialize, Serialize};
 
 // What's the algorithm?
 // Look at the displacement of a fast ema of fair price from a slower ema of fair
 // Assume the local exchange should be following the same curve, and return
 // the displacement the fast ema fair has from such a curve
 
-pub struct Premium {
+#[derive(SignalInputs)]
+struct PremiumInputs {
     in1: ConsumerInput,
     in2: ConsumerInput,
+}
+
+#[derive(Signal)]
+pub struct Premium {
+    #[inputs]
+    inputs: PremiumInputs,
+    #[output(name = "output")]
     diff: ConsumerOutput,
 }
 
@@ -20,8 +28,9 @@ pub struct Premium {
 impl CallSignal for Premium {
     fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
         self.diff.set_from(
-            self.in1
-                .and(&self.in2, graph)
+            self.inputs
+                .in1
+                .and(&self.inputs.in2, graph)
                 .get()
                 .map(|(in1, in2)| in1 - in2),
             graph,
@@ -29,77 +38,124 @@ impl CallSignal for Premium {
     }
 }
 
-impl RegisterSignal for Premium {
-    const PARAMS: bool = false;
-    type Child = Self;
-    fn get_inputs() -> HashMap<&'static str, SignalType> {
-        maplit::hashmap! {
-            "in1" => SignalType::Consumer,
-            "in2" => SignalType::Consumer,
-        }
-    }
+#[derive(SignalInputs)]
+struct DisplacementInputs {
+    local_fair: ConsumerInput,
+    local_size: ConsumerInput,
+    remote_fair: ConsumerInput,
+    remote_size: ConsumerInput,
+}
 
-    fn get_outputs() -> HashSet<&'static str> {
-        maplit::hashset! {
-            "output"
-        }
-    }
+#[derive(SignalParams)]
+struct DisplacementParams {
+    /// Half-life in seconds of the fast fair averages
+    #[param(validate = "fast_half_life > 0.0")]
+    fast_half_life: f64,
+    /// Half-life in seconds of the slow fair averages
+    #[param(validate = "slow_half_life > 0.0")]
+    slow_half_life: f64,
+    /// Half-life in seconds of the local size average
+    #[param(validate = "size_half_life > 0.0")]
+    size_half_life: f64,
+    /// Half-life in seconds of the average fast local less fast remote fair
+    #[param(validate = "premium_half_life > 0.0")]
+    premium_half_life: f64,
+}
 
-    fn create(
-        mut outputs: HashMap<&'static str, ConsumerOutput>,
-        mut inputs: InputLoader,
-        _: Option<&str>,
-    ) -> Result<Self, anyhow::Error> {
-        Ok(Premium {
-            in1: inputs.load_input("in1")?,
-            in2: inputs.load_input("in2")?,
-            diff: outputs.remove("output").unwrap(),
-        })
-    }
+#[derive(Serialize, Deserialize, Default)]
+struct DisplacementAverages {
+    local_fast: DecayedStats,
+    local_slow: DecayedStats,
+    remote_fast: DecayedStats,
+    remote_slow: DecayedStats,
+    local_size: DecayedStats,
+    premium: DecayedStats,
 }
 
-/*
-impl Displacement {
-    pub fn handle_local(&mut self, local_fair: f64, local_size: f64) {
-        let lf = self.local_fast_ema.add_value(local_fair);
-        self.local_slow_ema.add_value(local_fair);
-        self.local_size.add_value(local_size);
-        if let Some(rf) = self.remote_fast_ema.get_value() {
-            self.premium_ema.add_value(lf - rf);
-        }
-    }
+// Looks at how far a fast average of fair has moved from a slow one on both venues.
+// Assuming the local venue should follow the same curve as the remote one, outputs how
+// much further the local premium has to go, with the remote premium discounted by
+// the share of size on each venue. New parameters keep the averages, only changing
+// how fast they move from here
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct Displacement {
+    #[inputs]
+    inputs: DisplacementInputs,
+    #[output(name = "displacement")]
+    displacement: ConsumerOutput,
+    #[output(name = "premium")]
+    premium: ConsumerOutput,
+    #[params]
+    params: DisplacementParams,
+    #[state(checkpoint)]
+    averages: DisplacementAverages,
+}
 
-    pub fn handle_remote(&mut self, remote_fair: f64, remote_size: f64) {
-        self.remote_fast_ema.add_value(remote_fair);
-        self.remote_slow_ema.add_value(remote_fair);
-        self.remote_size = remote_size;
-    }
+// Averages follow their input until any time has passed
+fn average(stats: &DecayedStats, current: Option<f64>) -> Option<f64> {
+    stats.mean().or(current)
+}
+
+impl CallSignal for Displacement {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let params = &self.params;
+        let averages = &mut self.averages;
+        let local = self.inputs.local_fair.get(graph);
+        let remote = self.inputs.remote_fair.get(graph);
+        let local_size = self.inputs.local_size.get(graph);
 
-    pub fn get_displacement(&self) -> Option<(f64, f64)> {
-        if let (Some(lf), Some(ls), Some(rf), Some(rs), Some(lsize), Some(premium)) = (
-            self.local_fast_ema.get_value(),
-            self.local_slow_ema.get_value(),
-            self.remote_fast_ema.get_value(),
-            self.remote_slow_ema.get_value(),
-            self.local_size.get_value(),
-            self.premium_ema.get_value(),
+        averages
+            .local_fast
+            .update(local, time, params.fast_half_life);
+        averages
+            .local_slow
+            .update(local, time, params.slow_half_life);
+        averages
+            .local_size
+            .update(local_size, time, params.size_half_life);
+        averages
+            .remote_fast
+            .update(remote, time, params.fast_half_life);
+        averages
+            .remote_slow
+            .update(remote, time, params.slow_half_life);
+
+        let lf = average(&averages.local_fast, local);
+        let ls = average(&averages.local_slow, local);
+        let rf = average(&averages.remote_fast, remote);
+        let rs = average(&averages.remote_slow, remote);
+        let local_premium = lf.and_then(|lf| rf.map(|rf| lf - rf));
+        averages
+            .premium
+            .update(local_premium, time, params.premium_half_life);
+        let premium = average(&averages.premium, local_premium);
+
+        let displacement = match (
+            lf,
+            ls,
+            rf,
+            rs,
+            average(&averages.local_size, local_size),
+            self.inputs.remote_size.get(graph),
         ) {
-            // how far above the slower moving price is the fast fair value?
-            let remote_premium = rf - rs;
-            let local_premium = lf - ls;
-
-            // discount the remoteness by the size ratio
-            let total_size = lsize + self.remote_size;
-            let adjust_remote = self.remote_size / total_size;
-            let adjust_local = lsize / total_size;
-
-            let remote_premium = remote_premium * adjust_remote + local_premium * adjust_local;
-
-            // How much farther must the remote premium go (or has it gone too far?)
-            Some((remote_premium - local_premium, premium))
-        } else {
-            None
-        }
+            (Some(lf), Some(ls), Some(rf), Some(rs), Some(lsize), Some(rsize))
+                if lsize + rsize > 0.0 =>
+            {
+                // how far above the slower moving price is the fast fair value?
+                let remote_premium = rf - rs;
+                let local_premium = lf - ls;
+
+                // discount the remoteness by the size ratio
+                let total_size = lsize + rsize;
+                let remote_premium = (remote_premium * rsize + local_premium * lsize) / total_size;
+
+                // How much farther must the remote premium go (or has it gone too far?)
+                Some(remote_premium - local_premium)
+            }
+            _ => None,
+        };
+        self.displacement.set_from(displacement, graph);
+        self.premium.set_from(premium, graph);
     }
 }
-*/
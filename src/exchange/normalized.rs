@@ -5,7 +5,6 @@ use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-
 pub type SmallVec<T> = smallvec::SmallVec<[T; 8]>;
 pub type DataStream = async_tungstenite::tokio::TokioWebSocketStream;
 
@@ -79,11 +78,29 @@ impl Hash for Trade {
     }
 }
 
+// The rate paid from longs to shorts of a perpetual at funding_time,
+// in microseconds since the epoch
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FundingRate {
+    pub rate: f64,
+    pub funding_time: u64,
+    pub exchange_time: usize,
+}
+
+impl Hash for FundingRate {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.rate.to_bits().hash(hasher);
+        self.funding_time.hash(hasher);
+        self.exchange_time.hash(hasher);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Hash)]
 pub enum MarketUpdates {
     Book(SmallVec<BookUpdate>),
     Reset(SmallVec<BookUpdate>),
-    Trades(SmallVec<Trade>)
+    Trades(SmallVec<Trade>),
+    Funding(FundingRate),
 }
 
 #[repr(C)]
@@ -91,16 +108,18 @@ pub enum MarketDataTag {
     Book,
     Trade,
     Fill,
+    Funding,
 }
 
-pub type MarketDataTagArr<T> = [T; 1 + MarketDataTag::Fill as usize];
+pub type MarketDataTagArr<T> = [T; 1 + MarketDataTag::Funding as usize];
 
 impl MarketUpdates {
     #[inline]
     pub fn len(&self) -> usize {
         match self {
             MarketUpdates::Book(ev) | MarketUpdates::Reset(ev) => ev.len(),
-            MarketUpdates::Trades(tr) => tr.len()
+            MarketUpdates::Trades(tr) => tr.len(),
+            MarketUpdates::Funding(_) => 1,
         }
     }
 
@@ -109,6 +128,7 @@ impl MarketUpdates {
         match self {
             MarketUpdates::Book(_) | MarketUpdates::Reset(_) => MarketDataTag::Book,
             MarketUpdates::Trades(_) => MarketDataTag::Trade,
+            MarketUpdates::Funding(_) => MarketDataTag::Funding,
         }
     }
 
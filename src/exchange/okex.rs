@@ -30,6 +30,23 @@ enum BookUpdate {
     Partial([Update; 1]),
 }
 
+#[derive(Deserialize, Debug)]
+struct FundingUpdate {
+    funding_rate: SmallString,
+    funding_time: SmallString,
+}
+
+#[derive(Deserialize, Debug)]
+struct FundingMessage {
+    data: [FundingUpdate; 1],
+}
+
+// Every pushed message names its table, which says how to parse the rest
+#[derive(Deserialize, Debug)]
+struct Table {
+    table: SmallString,
+}
+
 #[derive(Debug)]
 pub enum OkexType {
     Spot,
@@ -46,6 +63,16 @@ impl OkexType {
         }
     }
 
+    // Only the perpetual has funding, and it comes over the same stream as its book
+    fn get_channels(&self) -> SmallVec<&'static str> {
+        let mut channels = SmallVec::new();
+        channels.push(self.get_product());
+        if let OkexType::Swap = self {
+            channels.push("swap/funding_rate:BTC-USD-SWAP");
+        }
+        channels
+    }
+
     fn exchange(&self) -> normalized::Exchange {
         match self {
             OkexType::Spot => normalized::Exchange::OkexSpot,
@@ -75,26 +102,31 @@ pub async fn okex_connection(which: OkexType) -> normalized::MarketDataStream {
     let (mut stream, _) = connect_async("wss://real.OKEx.com:8443/ws/v3")
         .await
         .expect("Could not connect to okex api");
+    let channels = which.get_channels();
+    let args: Vec<_> = channels.iter().map(|c| format!("\"{}\"", c)).collect();
     // What comes first
     let msg = Message::Text(format!(
-        "{{\"op\": \"subscribe\", \"args\": [\"{}\"]}}",
-        which.get_product()
+        "{{\"op\": \"subscribe\", \"args\": [{}]}}",
+        args.join(", ")
     ));
     stream.send(msg).await.expect("Could not request L2 stream");
-    let ack = stream.next().await.unwrap().unwrap();
-    match ack {
-        Message::Binary(data) => {
-            let mut deflater = DeflateDecoder::new(&data[..]);
-            let mut s = String::new();
-            deflater
-                .read_to_string(&mut s)
-                .expect("Could not unzip okex message");
-            if s.contains("rror") {
-                panic!("Error subscribing to api: message {}", s);
+    // Each channel is acked separately
+    for _ in channels.iter() {
+        let ack = stream.next().await.unwrap().unwrap();
+        match ack {
+            Message::Binary(data) => {
+                let mut deflater = DeflateDecoder::new(&data[..]);
+                let mut s = String::new();
+                deflater
+                    .read_to_string(&mut s)
+                    .expect("Could not unzip okex message");
+                if s.contains("rror") {
+                    panic!("Error subscribing to api: message {}", s);
+                }
             }
-        }
-        data => panic!("Incorrect ack type {:?}", data),
-    };
+            data => panic!("Incorrect ack type {:?}", data),
+        };
+    }
     normalized::MarketDataStream::new(stream, which.exchange(), which.get_convert())
 }
 
@@ -111,7 +143,7 @@ fn convert_future(data: Message) -> DataOrResponse {
 }
 
 fn convert_inner(data: Message, which: OkexType) -> MarketUpdates {
-    let data = match data {
+    let text = match data {
         Message::Binary(data) => {
             let mut deflater = DeflateDecoder::new(&data[..]);
             let mut s = String::new();
@@ -122,7 +154,25 @@ fn convert_inner(data: Message, which: OkexType) -> MarketUpdates {
         }
         data => panic!("Incorrect message type {:?}", data),
     };
-    let message = serde_json::from_str(&data).expect("Couldn't parse okex message");
+    convert_text(&text, which)
+}
+
+fn convert_text(data: &str, which: OkexType) -> MarketUpdates {
+    let Table { table } = serde_json::from_str(data)
+        .unwrap_or_else(|err| panic!("Couldn't read okex table: {} in {}", err, data));
+    // Funding messages carry no action, and only the perpetual's stream has them
+    if table.ends_with("/funding_rate") {
+        assert!(
+            matches!(which, OkexType::Swap),
+            "Funding message on the {:?} stream",
+            which
+        );
+        let FundingMessage { data: [funding] } = serde_json::from_str(data)
+            .unwrap_or_else(|err| panic!("Couldn't parse okex funding: {} in {}", err, data));
+        return convert_funding(funding);
+    }
+    let message: BookUpdate = serde_json::from_str(data)
+        .unwrap_or_else(|err| panic!("Couldn't parse okex {} book: {} in {}", table, err, data));
     let mut result = SmallVec::new();
     let ups = match &message {
         BookUpdate::Partial([ups]) | BookUpdate::Update([ups]) => ups,
@@ -154,3 +204,110 @@ fn convert_inner(data: Message, which: OkexType) -> MarketUpdates {
         BookUpdate::Update(_) => MarketUpdates::Book(result),
     }
 }
+
+fn convert_funding(funding: FundingUpdate) -> MarketUpdates {
+    let funding_time = chrono::DateTime::parse_from_rfc3339(&funding.funding_time)
+        .expect("Bad funding time")
+        .timestamp_nanos()
+        / 1000;
+    MarketUpdates::Funding(normalized::FundingRate {
+        rate: funding
+            .funding_rate
+            .parse::<f64>()
+            .expect("Bad floating point"),
+        funding_time: funding_time as u64,
+        exchange_time: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &str = r#"{"table": "swap/depth_l2_tbt", "action": "update", "data": [{
+        "instrument_id": "BTC-USD-SWAP",
+        "bids": [["9100.5", "12", "0", "3"]],
+        "asks": [["9101.0", "4", "0", "1"], ["9102.5", "0", "0", "0"]],
+        "timestamp": "2020-07-01T00:00:00.000Z", "checksum": 1}]}"#;
+
+    const FUNDING: &str = r#"{"table": "swap/funding_rate", "data": [{
+        "instrument_id": "BTC-USD-SWAP", "funding_rate": "0.00025",
+        "interest_rate": "0", "funding_time": "2020-07-01T08:00:00.000Z",
+        "estimated_rate": "0.0001", "settlement_time": "2020-07-01T08:00:00.000Z"}]}"#;
+
+    #[test]
+    fn converts_funding() {
+        let funding = FundingUpdate {
+            funding_rate: SmallString::from("-0.0005"),
+            funding_time: SmallString::from("2020-07-01T08:00:00.000Z"),
+        };
+        match convert_funding(funding) {
+            MarketUpdates::Funding(funding) => {
+                assert_eq!(funding.rate, -0.0005);
+                assert_eq!(funding.funding_time, 1_593_590_400_000_000);
+            }
+            updates => panic!("Not a funding update: {:?}", updates),
+        }
+    }
+
+    #[test]
+    fn parses_message_shapes() {
+        match serde_json::from_str(BOOK).unwrap() {
+            BookUpdate::Update([update]) => {
+                assert_eq!(update.bids.len(), 1);
+                assert_eq!(update.asks.len(), 2);
+            }
+            update => panic!("Not a book update: {:?}", update),
+        }
+        let partial = BOOK.replace("\"update\"", "\"partial\"");
+        assert!(matches!(
+            serde_json::from_str(&partial).unwrap(),
+            BookUpdate::Partial(_)
+        ));
+        assert!(serde_json::from_str::<BookUpdate>(FUNDING).is_err());
+        let FundingMessage { data: [funding] } = serde_json::from_str(FUNDING).unwrap();
+        assert_eq!(funding.funding_rate.as_str(), "0.00025");
+    }
+
+    #[test]
+    fn converts_books() {
+        let updates = match convert_text(BOOK, OkexType::Swap) {
+            MarketUpdates::Book(updates) => updates,
+            updates => panic!("Not a book update: {:?}", updates),
+        };
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0].cents, 910050);
+        assert_eq!(updates[0].side, normalized::Side::Buy);
+        // Swap contracts are 100 dollars each
+        assert_eq!(updates[0].size, 1200.0);
+        assert_eq!(updates[1].side, normalized::Side::Sell);
+        assert_eq!(updates[2].size, 0.0);
+
+        // Spot sizes are in coins
+        let partial = BOOK.replace("\"update\"", "\"partial\"");
+        match convert_text(&partial, OkexType::Spot) {
+            MarketUpdates::Reset(updates) => assert_eq!(updates[1].size, 9101.0 * 4.0),
+            updates => panic!("Not a reset: {:?}", updates),
+        }
+    }
+
+    #[test]
+    fn funding_only_on_swaps() {
+        assert!(matches!(
+            convert_text(FUNDING, OkexType::Swap),
+            MarketUpdates::Funding(_)
+        ));
+        let result = std::panic::catch_unwind(|| convert_text(FUNDING, OkexType::Quarterly));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn book_errors_on_swaps() {
+        // A broken book on the swap stream is reported as a book, not as bad funding
+        let broken = BOOK.replace("\"update\"", "\"snapshot\"");
+        let result = std::panic::catch_unwind(|| convert_text(&broken, OkexType::Swap));
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("swap/depth_l2_tbt book"), "{}", message);
+    }
+}
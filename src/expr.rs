@@ -0,0 +1,507 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::signal_graph::params::SignalParams;
+
+// A signal computing an arithmetic expression of its inputs, so glue like a difference
+// or a ratio of two outputs can be written in the graph spec instead of as a new type.
+// Inputs come in as one aggregate, and params name them in order for the expression.
+// The expression is compiled once into postfix ops and run over a fixed size stack.
+//
+// Any operation on an invalid value is invalid, as is any non-finite result.
+// Functions are min, max, abs, clamp(x, lo, hi), log (natural), exp, sqrt, and for
+// handling invalid values valid(x), which is 1 or 0, if(cond, a, b), which only needs
+// the branch it takes, and or(a, b, ...), which is the first valid argument.
+// Comparisons <, >, <=, >=, == and != give 1 or 0
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Const(f64),
+    Input(usize),
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    Min(usize),
+    Max(usize),
+    Abs,
+    Clamp,
+    Log,
+    Exp,
+    Sqrt,
+    Valid,
+    If,
+    Or(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+    End,
+}
+
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "(", ")", ",",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, anyhow::Error> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    loop {
+        let trimmed = rest.trim_start();
+        let position = source.len() - trimmed.len();
+        rest = trimmed;
+        let first = match rest.chars().next() {
+            Some(first) => first,
+            None => break,
+        };
+        let (token, length) = if first.is_ascii_digit() || first == '.' {
+            let mut length = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or_else(|| rest.len());
+            // Exponents, which can be signed
+            if rest[length..].starts_with(|c| c == 'e' || c == 'E') {
+                let sign = rest[length + 1..].starts_with(|c| c == '+' || c == '-') as usize;
+                let digits = rest[length + 1 + sign..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or_else(|| rest.len() - length - 1 - sign);
+                length += 1 + sign + digits;
+            }
+            let number = rest[..length]
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Bad number {} at {}", &rest[..length], position))?;
+            (Token::Number(number), length)
+        } else if first.is_alphabetic() || first == '_' {
+            let length = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or_else(|| rest.len());
+            (Token::Ident(rest[..length].to_string()), length)
+        } else {
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => (Token::Symbol(symbol), symbol.len()),
+                None => anyhow::bail!("Unexpected {} at {}", first, position),
+            }
+        };
+        tokens.push((position, token));
+        rest = &rest[length..];
+    }
+    tokens.push((source.len(), Token::End));
+    Ok(tokens)
+}
+
+// The fewest and most arguments each function takes
+fn arity(function: &str) -> Option<(usize, usize)> {
+    match function {
+        "min" | "max" | "or" => Some((2, usize::MAX)),
+        "abs" | "log" | "exp" | "sqrt" | "valid" => Some((1, 1)),
+        "clamp" | "if" => Some((3, 3)),
+        _ => None,
+    }
+}
+
+// Recursive descent over the tokens, writing ops in postfix order
+struct Parser<'a> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    names: &'a [String],
+    ops: Vec<Op>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].1.clone();
+        if token != Token::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self) -> anyhow::Error {
+        match &self.tokens[self.next] {
+            (_, Token::End) => anyhow::anyhow!("Unexpected end of expression"),
+            (position, token) => anyhow::anyhow!("Unexpected {:?} at {}", token, position),
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), anyhow::Error> {
+        if *self.peek() == Token::Symbol(symbol) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    // Tracks how deep the stack gets, each op popping its arguments and pushing one
+    fn emit(&mut self, op: Op, arguments: usize) {
+        self.depth = self.depth + 1 - arguments;
+        self.max_depth = self.max_depth.max(self.depth);
+        self.ops.push(op);
+    }
+
+    fn comparison(&mut self) -> Result<(), anyhow::Error> {
+        self.sum()?;
+        let op = match self.peek() {
+            Token::Symbol("<") => Op::Lt,
+            Token::Symbol(">") => Op::Gt,
+            Token::Symbol("<=") => Op::Le,
+            Token::Symbol(">=") => Op::Ge,
+            Token::Symbol("==") => Op::Eq,
+            Token::Symbol("!=") => Op::Ne,
+            _ => return Ok(()),
+        };
+        self.advance();
+        self.sum()?;
+        self.emit(op, 2);
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<(), anyhow::Error> {
+        self.product()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("+") => Op::Add,
+                Token::Symbol("-") => Op::Sub,
+                _ => return Ok(()),
+            };
+            self.advance();
+            self.product()?;
+            self.emit(op, 2);
+        }
+    }
+
+    fn product(&mut self) -> Result<(), anyhow::Error> {
+        self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("*") => Op::Mul,
+                Token::Symbol("/") => Op::Div,
+                _ => return Ok(()),
+            };
+            self.advance();
+            self.unary()?;
+            self.emit(op, 2);
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), anyhow::Error> {
+        if *self.peek() == Token::Symbol("-") {
+            self.advance();
+            self.unary()?;
+            self.emit(Op::Neg, 1);
+            Ok(())
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<(), anyhow::Error> {
+        match self.peek() {
+            Token::Number(_) | Token::Ident(_) | Token::Symbol("(") => (),
+            _ => return Err(self.unexpected()),
+        }
+        match self.advance() {
+            Token::Number(number) => self.emit(Op::Const(number), 0),
+            Token::Symbol("(") => {
+                self.comparison()?;
+                self.expect(")")?;
+            }
+            Token::Ident(name) if *self.peek() == Token::Symbol("(") => {
+                self.advance();
+                self.call(&name)?;
+            }
+            Token::Ident(name) => match self.names.iter().position(|n| *n == name) {
+                Some(index) => self.emit(Op::Input(index), 0),
+                None => anyhow::bail!("Unknown input {}", name),
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, function: &str) -> Result<(), anyhow::Error> {
+        let (least, most) =
+            arity(function).ok_or_else(|| anyhow::anyhow!("Unknown function {}", function))?;
+        let mut arguments = 0;
+        if *self.peek() != Token::Symbol(")") {
+            loop {
+                self.comparison()?;
+                arguments += 1;
+                if *self.peek() != Token::Symbol(",") {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(")")?;
+        if arguments < least || arguments > most {
+            anyhow::bail!("{} can't take {} arguments", function, arguments);
+        }
+        let op = match function {
+            "min" => Op::Min(arguments),
+            "max" => Op::Max(arguments),
+            "or" => Op::Or(arguments),
+            "abs" => Op::Abs,
+            "log" => Op::Log,
+            "exp" => Op::Exp,
+            "sqrt" => Op::Sqrt,
+            "valid" => Op::Valid,
+            "clamp" => Op::Clamp,
+            _ => Op::If,
+        };
+        self.emit(op, arguments);
+        Ok(())
+    }
+}
+
+fn finite(value: f64) -> Option<f64> {
+    Some(value).filter(|value| value.is_finite())
+}
+
+fn binary(a: Option<f64>, b: Option<f64>, op: impl Fn(f64, f64) -> f64) -> Option<f64> {
+    a.and_then(|a| b.and_then(|b| finite(op(a, b))))
+}
+
+fn truth(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+pub struct Program {
+    ops: Vec<Op>,
+    stack: Vec<Option<f64>>,
+}
+
+impl Program {
+    // Compiles the expression, with each name referring to the input at its position
+    pub fn compile(source: &str, names: &[String]) -> Result<Program, anyhow::Error> {
+        for (index, name) in names.iter().enumerate() {
+            let identifier = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !identifier || arity(name).is_some() {
+                anyhow::bail!("Input name {} isn't usable in an expression", name);
+            }
+            if names[..index].contains(name) {
+                anyhow::bail!("Input name {} is given twice", name);
+            }
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+            names,
+            ops: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+        };
+        parser.comparison()?;
+        if *parser.peek() != Token::End {
+            return Err(parser.unexpected());
+        }
+        Ok(Program {
+            ops: parser.ops,
+            stack: Vec::with_capacity(parser.max_depth),
+        })
+    }
+
+    pub fn eval(&mut self, inputs: &[Option<f64>]) -> Option<f64> {
+        let stack = &mut self.stack;
+        stack.clear();
+        for op in &self.ops {
+            let value = match *op {
+                Op::Const(value) => Some(value),
+                Op::Input(index) => inputs[index],
+                Op::Min(count) | Op::Max(count) | Op::Or(count) => {
+                    let arguments = stack.drain(stack.len() - count..);
+                    match op {
+                        Op::Or(_) => arguments.flatten().next(),
+                        Op::Min(_) => {
+                            arguments.fold(Some(f64::INFINITY), |a, b| binary(a, b, f64::min))
+                        }
+                        _ => arguments.fold(Some(f64::NEG_INFINITY), |a, b| binary(a, b, f64::max)),
+                    }
+                }
+                Op::Neg | Op::Abs | Op::Log | Op::Exp | Op::Sqrt | Op::Valid => {
+                    let a = stack.pop().unwrap();
+                    match op {
+                        Op::Valid => Some(truth(a.is_some())),
+                        Op::Neg => a.map(|a| -a),
+                        Op::Abs => a.map(f64::abs),
+                        Op::Log => a.filter(|a| *a > 0.0).map(f64::ln),
+                        Op::Exp => a.and_then(|a| finite(a.exp())),
+                        _ => a.filter(|a| *a >= 0.0).map(f64::sqrt),
+                    }
+                }
+                Op::Clamp | Op::If => {
+                    let c = stack.pop().unwrap();
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    match op {
+                        Op::If => a.and_then(|a| if a != 0.0 { b } else { c }),
+                        _ => a.and_then(|a| binary(b, c, |lo, hi| a.max(lo).min(hi))),
+                    }
+                }
+                _ => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    binary(a, b, |a, b| match op {
+                        Op::Add => a + b,
+                        Op::Sub => a - b,
+                        Op::Mul => a * b,
+                        Op::Div => a / b,
+                        Op::Lt => truth(a < b),
+                        Op::Gt => truth(a > b),
+                        Op::Le => truth(a <= b),
+                        Op::Ge => truth(a >= b),
+                        Op::Eq => truth(a == b),
+                        _ => truth(a != b),
+                    })
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+}
+
+#[derive(SignalInputs)]
+struct ExpressionInputs {
+    inputs: AggregateInput,
+}
+
+#[derive(SignalParams)]
+struct ExpressionParams {
+    /// The expression to compute, in terms of the input names
+    expr: String,
+    /// A name for each of the inputs, in the order they are given
+    names: Vec<String>,
+}
+
+impl ExpressionParams {
+    fn compile(&self, inputs: usize) -> Result<Program, anyhow::Error> {
+        if self.names.len() != inputs {
+            anyhow::bail!("Got {} names for {} inputs", self.names.len(), inputs);
+        }
+        Program::compile(&self.expr, &self.names)
+    }
+}
+
+#[derive(Signal)]
+#[signal(update_params = "Expression::apply_params")]
+pub struct Expression {
+    #[inputs]
+    inputs: ExpressionInputs,
+    #[output(name = "output")]
+    output: ConsumerOutput,
+    #[params]
+    params: ExpressionParams,
+    #[state(init = "params.compile(inputs.inputs.len())?")]
+    program: Program,
+    values: Vec<Option<f64>>,
+}
+
+impl Expression {
+    // The inputs are fixed, but the expression and names can change
+    fn apply_params(&mut self, json: &str) -> Result<(), anyhow::Error> {
+        let params = ExpressionParams::parse(json)?;
+        self.program = params.compile(self.inputs.inputs.len())?;
+        self.params = params;
+        Ok(())
+    }
+}
+
+impl CallSignal for Expression {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.values.clear();
+        self.values.extend(self.inputs.inputs.values(graph));
+        self.output.set_from(self.program.eval(&self.values), graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, inputs: &[Option<f64>]) -> Option<f64> {
+        let names = ["a", "b", "c"];
+        let names: Vec<String> = names[..inputs.len()]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        Program::compile(source, &names).unwrap().eval(inputs)
+    }
+
+    fn error(source: &str) -> String {
+        Program::compile(source, &["a".to_string()])
+            .err()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let inputs = [Some(6.0), Some(2.0)];
+        assert_eq!(eval("a - b", &inputs), Some(4.0));
+        assert_eq!(eval("a - b * 2 + 1", &inputs), Some(3.0));
+        assert_eq!(eval("(a - b) * 2", &inputs), Some(8.0));
+        assert_eq!(eval("-a / -b", &inputs), Some(3.0));
+        assert_eq!(eval("a - b - 1", &inputs), Some(3.0));
+        assert_eq!(eval("1.5e1 + 2E-1", &inputs), Some(15.2));
+        assert_eq!(eval("min(a, b, 3) + max(a, b)", &inputs), Some(8.0));
+        assert_eq!(eval("abs(b - a)", &inputs), Some(4.0));
+        assert_eq!(eval("clamp(a, 0, 5)", &inputs), Some(5.0));
+        assert_eq!(eval("exp(log(a))", &inputs).map(|v| v.round()), Some(6.0));
+        assert_eq!(eval("sqrt(a * 6)", &inputs), Some(6.0));
+        assert_eq!(
+            eval("(a > b) + (a <= b) * 2 + (a != b) * 4", &inputs),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn validity() {
+        let inputs = [Some(1.0), None];
+        assert_eq!(eval("a + b", &inputs), None);
+        assert_eq!(eval("min(a, b)", &inputs), None);
+        assert_eq!(eval("or(b, a)", &inputs), Some(1.0));
+        assert_eq!(eval("or(b, b)", &inputs), None);
+        assert_eq!(eval("valid(a) + valid(b)", &inputs), Some(1.0));
+        // Only the branch taken needs to be valid
+        assert_eq!(eval("if(valid(b), b, a * 2)", &inputs), Some(2.0));
+        assert_eq!(eval("if(b, a, a)", &inputs), None);
+        // Results that aren't finite are invalid
+        assert_eq!(eval("a / 0", &inputs), None);
+        assert_eq!(eval("log(a - 1)", &inputs), None);
+        assert_eq!(eval("sqrt(-a)", &inputs), None);
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert_eq!(error("a +"), "Unexpected end of expression");
+        assert_eq!(error("a b"), "Unexpected Ident(\"b\") at 2");
+        assert_eq!(error("b"), "Unknown input b");
+        assert_eq!(error("foo(a)"), "Unknown function foo");
+        assert_eq!(error("clamp(a, 1)"), "clamp can't take 2 arguments");
+        assert_eq!(error("(a"), "Unexpected end of expression");
+        assert_eq!(error("a $ 1"), "Unexpected $ at 2");
+        assert!(Program::compile("a", &["min".to_string()]).is_err());
+        assert!(Program::compile("a", &["a".to_string(), "a".to_string()]).is_err());
+    }
+}
@@ -1,68 +1,102 @@
 use crate::exchange::normalized::MarketUpdates;
-use crate::order_book::{BuyPrice, SellPrice, SidedPrice};
+use crate::order_book::{cents_to_dollars, BuyPrice, SellPrice, SidedPrice};
 use crate::signal_graph::graph_registrar::*;
 use crate::signal_graph::interface_types::*;
+use serde::{Deserialize, Serialize};
 
-use std::collections::{HashMap, HashSet};
-
-fn cents_to_dollars(cents: usize) -> f64 {
-    (cents as f64) * 0.01
-}
-
-pub struct FairValue {
-    fair_out: ConsumerOutput,
-    size_out: ConsumerOutput,
+#[derive(SignalInputs)]
+struct FairInputs {
     book: BookViewer,
-    score_denom: f64,
-    score_offset: f64,
-    dollars_out: f64,
-    levels_out: usize,
 }
 
-#[derive(serde::Deserialize)]
-struct FairParameters {
-    score_denom: f64,
-    score_offset: f64,
-    dollars_out: f64,
-    levels_out: usize,
+// How a level's weight falls off with its distance from the touch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Kernel {
+    // 1 / (1 + score_denom * distance^2)
+    InverseQuadratic,
+    // Halves every half_distance
+    Exponential { half_distance: f64 },
+    // Falls linearly to zero at cutoff
+    LinearCutoff { cutoff: f64 },
+    // weights[i] at distance i, interpolated in between and zero past the end
+    Tabulated { weights: Vec<f64> },
 }
 
-impl RegisterSignal for FairValue {
-    type Child = Self;
-    fn get_inputs() -> HashMap<&'static str, SignalType> {
-        maplit::hashmap! {
-            "book" => SignalType::Book,
+impl Kernel {
+    fn is_valid(&self) -> bool {
+        match self {
+            Kernel::InverseQuadratic => true,
+            Kernel::Exponential { half_distance } => *half_distance > 0.0,
+            Kernel::LinearCutoff { cutoff } => *cutoff > 0.0,
+            Kernel::Tabulated { weights } => {
+                !weights.is_empty() && weights.iter().all(|weight| *weight >= 0.0)
+            }
         }
     }
 
-    fn get_outputs() -> HashSet<&'static str> {
-        maplit::hashset! {
-            "fair",
-            "size",
+    fn weight(&self, distance: f64, score_denom: f64) -> f64 {
+        match self {
+            Kernel::InverseQuadratic => 1.0 / (1.0 + score_denom * distance * distance),
+            Kernel::Exponential { half_distance } => (-distance / half_distance).exp2(),
+            Kernel::LinearCutoff { cutoff } => (1.0 - distance / cutoff).max(0.0),
+            Kernel::Tabulated { weights } => {
+                let below = distance.floor() as usize;
+                let fraction = distance - distance.floor();
+                let at = |index: usize| weights.get(index).copied().unwrap_or(0.0);
+                at(below) * (1.0 - fraction) + at(below + 1) * fraction
+            }
         }
     }
+}
 
-    fn create(
-        mut outputs: HashMap<&'static str, ConsumerOutput>,
-        mut inputs: InputLoader,
-        json: Option<&str>,
-    ) -> Result<Self, anyhow::Error> {
-        let FairParameters {
-            score_denom,
-            score_offset,
-            dollars_out,
-            levels_out,
-        } = serde_json::from_str(json.unwrap())?;
-        Ok(FairValue {
-            score_denom,
-            score_offset,
-            dollars_out,
-            levels_out,
-            book: inputs.load_input("book")?,
-            fair_out: outputs.remove("fair").unwrap(),
-            size_out: outputs.remove("size").unwrap(),
-        })
-    }
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DistanceUnit {
+    Dollars,
+    Ticks,
+    Bps,
+}
+
+#[derive(SignalParams)]
+struct FairParams {
+    /// Shape of a level's score with distance from the touch
+    #[param(default = "Kernel::InverseQuadratic", validate = "kernel.is_valid()")]
+    kernel: Kernel,
+    /// Quadratic falloff of the inverse_quadratic kernel
+    #[param(default = "0.0", validate = "score_denom >= 0.0")]
+    score_denom: f64,
+    /// Score added to every level regardless of distance
+    #[param(validate = "score_offset >= 0.0")]
+    score_offset: f64,
+    /// Unit of the distances given to the kernel
+    #[param(default = "DistanceUnit::Dollars")]
+    distance: DistanceUnit,
+    /// Tick size in cents, for distances in ticks
+    #[param(default = "1", validate = "tick_cents > 0")]
+    tick_cents: usize,
+    /// Score each level by the size up to and including it instead of its own size
+    #[param(default = "false")]
+    cumulative: bool,
+    /// Furthest distance from the touch, in dollars, of levels that are scored
+    #[param(validate = "dollars_out >= 0.0")]
+    dollars_out: f64,
+    /// Most levels scored on each side
+    #[param(validate = "levels_out > 0")]
+    levels_out: usize,
+}
+
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct FairValue {
+    #[output(name = "fair")]
+    fair_out: ConsumerOutput,
+    #[output(name = "size")]
+    size_out: ConsumerOutput,
+    #[inputs]
+    inputs: FairInputs,
+    #[params]
+    params: FairParams,
 }
 
 /**
@@ -71,21 +105,39 @@ impl RegisterSignal for FairValue {
  * * use resulting values to calculate weighted midpoint
  */
 impl FairValue {
-    fn score(&self, distance: f64) -> f64 {
-        self.score_offset + 1.0 / (1.0 + self.score_denom * distance * distance)
+    fn score(&self, distance_cents: usize, touch_cents: usize) -> f64 {
+        let distance = match self.params.distance {
+            DistanceUnit::Dollars => cents_to_dollars(distance_cents),
+            DistanceUnit::Ticks => distance_cents as f64 / self.params.tick_cents as f64,
+            DistanceUnit::Bps => distance_cents as f64 / touch_cents as f64 * 10_000.0,
+        };
+        self.params.score_offset + self.params.kernel.weight(distance, self.params.score_denom)
     }
 
-    fn score_distanced<I>(&self, prices: I) -> (f64, f64)
+    // Takes (price, distance, size) in cents from the touch outwards
+    fn score_distanced<I>(&self, prices: I, touch_cents: usize) -> (f64, f64)
     where
-        I: Iterator<Item = (f64, f64, f64)>,
+        I: Iterator<Item = (usize, usize, f64)>,
     {
+        let dollars_out = (self.params.dollars_out * 100.0).round() as usize;
         prices
-            .take(self.levels_out)
-            .take_while(|(_, distance, _)| *distance <= self.dollars_out)
-            .map(|(prc, distance, sz)| (prc, self.score(distance), sz))
+            .take(self.params.levels_out)
+            .take_while(|(_, distance, _)| *distance <= dollars_out)
+            .scan(0.0, |cumulative, (prc, distance, sz)| {
+                *cumulative += sz;
+                let shares = if self.params.cumulative {
+                    *cumulative
+                } else {
+                    sz
+                };
+                Some((prc, self.score(distance, touch_cents), shares))
+            })
             .fold((0.0, 0.0), |(sum_prc, sum_shares), (prc, score, shares)| {
-                let shares_score = score * shares as f64;
-                (sum_prc + prc * shares_score, sum_shares + shares_score)
+                let shares_score = score * shares;
+                (
+                    sum_prc + cents_to_dollars(prc) * shares_score,
+                    sum_shares + shares_score,
+                )
             })
     }
 }
@@ -96,31 +148,32 @@ impl CallSignal for FairValue {
     // Btreemap should be optimal for this sort of behavior
     // Way, Way, Way too slow. Maybe use front-biased book?
     fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
-        let (best_bid, best_ask) = match self.book.book().bbo_price() {
+        let (best_bid, best_ask) = match self.inputs.book.book().bbo_price() {
             (Some(best_bid), Some(best_ask)) => (best_bid, best_ask),
             _ => {
-                self.fair_out.mark_invalid(graph);
-                self.size_out.mark_invalid(graph);
+                // Aggregators only rescan written venues, so they need to see this
+                self.fair_out.invalidate_and_notify(graph);
+                self.size_out.invalidate_and_notify(graph);
                 return;
             }
         };
-        let best_bid = cents_to_dollars(best_bid);
-        let best_ask = cents_to_dollars(best_ask);
-        let book = self.book.book();
+        let book = self.inputs.book.book();
         let bids = book
             .bids()
-            .map(|(prc, sz)| (cents_to_dollars(prc.unsigned()), *sz))
-            .map(|(prc, sz)| (prc, best_bid - prc, sz));
+            .map(|(prc, sz)| (prc.unsigned(), best_bid - prc.unsigned(), *sz));
         let asks = book
             .asks()
-            .map(|(prc, sz)| (cents_to_dollars(prc.unsigned()), *sz))
-            .map(|(prc, sz)| (prc, prc - best_ask, sz));
+            .map(|(prc, sz)| (prc.unsigned(), prc.unsigned() - best_ask, *sz));
 
-        let (bid_price, bid_shares) = self.score_distanced(bids);
-        let (ask_price, ask_shares) = self.score_distanced(asks);
+        let (bid_price, bid_shares) = self.score_distanced(bids, best_bid);
+        let (ask_price, ask_shares) = self.score_distanced(asks, best_ask);
 
-        assert!(bid_shares > 0.0);
-        assert!(ask_shares > 0.0);
+        // Kernels which reach zero can leave a side with no score at all
+        if bid_shares <= 0.0 || ask_shares <= 0.0 {
+            self.fair_out.invalidate_and_notify(graph);
+            self.size_out.invalidate_and_notify(graph);
+            return;
+        }
 
         let bid_price = bid_price / bid_shares;
         let ask_price = ask_price / ask_shares;
@@ -132,3 +185,56 @@ impl CallSignal for FairValue {
         self.size_out.set(fair_shares, graph);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal_graph::params::SignalParams;
+
+    #[test]
+    fn kernel_weights() {
+        assert_eq!(Kernel::InverseQuadratic.weight(2.0, 0.25), 0.5);
+        assert_eq!(Kernel::InverseQuadratic.weight(2.0, 0.0), 1.0);
+        assert_eq!(
+            Kernel::Exponential { half_distance: 2.0 }.weight(4.0, 0.0),
+            0.25
+        );
+        let linear = Kernel::LinearCutoff { cutoff: 4.0 };
+        assert_eq!(linear.weight(1.0, 0.0), 0.75);
+        assert_eq!(linear.weight(5.0, 0.0), 0.0);
+        let table = Kernel::Tabulated {
+            weights: vec![1.0, 0.5],
+        };
+        assert_eq!(table.weight(0.0, 0.0), 1.0);
+        assert_eq!(table.weight(0.5, 0.0), 0.75);
+        assert_eq!(table.weight(1.5, 0.0), 0.25);
+        assert_eq!(table.weight(3.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn kernel_params() {
+        let params = FairParams::parse(
+            r#"{"score_denom": 1.0, "score_offset": 0.1, "dollars_out": 10, "levels_out": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(params.kernel, Kernel::InverseQuadratic);
+        assert_eq!(params.distance, DistanceUnit::Dollars);
+        assert!(!params.cumulative);
+
+        let params = FairParams::parse(
+            r#"{"kernel": {"kind": "exponential", "half_distance": 3.0}, "distance": "ticks",
+                "tick_cents": 50, "cumulative": true, "score_offset": 0.0,
+                "dollars_out": 10, "levels_out": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(params.kernel, Kernel::Exponential { half_distance: 3.0 });
+        assert_eq!(params.distance, DistanceUnit::Ticks);
+        assert!(params.cumulative);
+
+        assert!(FairParams::parse(
+            r#"{"kernel": {"kind": "tabulated", "weights": []}, "score_offset": 0.0,
+                "dollars_out": 10, "levels_out": 10}"#,
+        )
+        .is_err());
+    }
+}
@@ -1,4 +1,11 @@
 #![allow(warnings)]
+pub mod cross_venue;
+pub mod displacement;
 pub mod exchange;
+pub mod fair_value;
+pub mod linear_model;
 pub mod order_book;
+pub mod remote_venue_aggregator;
 pub mod signal_graph;
+pub mod time_decay;
+pub mod volatility;
@@ -0,0 +1,107 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::signal_graph::model::{LinearModel, ModelWatcher};
+use crate::signal_graph::params::SignalParams;
+
+use std::time::Duration;
+
+#[derive(SignalInputs)]
+struct ModelInputs {
+    features: AggregateInput,
+}
+
+#[derive(SignalParams)]
+struct ModelParams {
+    /// Coefficient file, with one feature for each input in order
+    coefficients: String,
+    /// Valid features needed for a prediction, all of them if not given
+    #[param(default = "None")]
+    min_valid: Option<usize>,
+    /// Seconds between checks of the file for changes, never if zero
+    #[param(default = "1.0", validate = "reload >= 0.0")]
+    reload: f64,
+}
+
+impl ModelParams {
+    fn load(&self, features: usize) -> Result<LinearModel, anyhow::Error> {
+        let model = LinearModel::load(&self.coefficients)?;
+        check_features(&model, features)?;
+        if self
+            .min_valid
+            .map_or(false, |min_valid| min_valid > features)
+        {
+            anyhow::bail!("min_valid is more than the {} features", features);
+        }
+        Ok(model)
+    }
+
+    fn watch(&self, features: usize) -> Option<ModelWatcher> {
+        if self.reload > 0.0 {
+            Some(ModelWatcher::spawn(
+                self.coefficients.clone(),
+                Duration::from_secs_f64(self.reload),
+                move |model| check_features(model, features),
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+fn check_features(model: &LinearModel, features: usize) -> Result<(), anyhow::Error> {
+    if model.len() != features {
+        anyhow::bail!(
+            "Model has {} features but is given {} inputs",
+            model.len(),
+            features
+        );
+    }
+    Ok(())
+}
+
+// Linear prediction from an aggregate of features, with coefficients from a file that is
+// reloaded when it changes. A reload with the wrong number of features is ignored.
+// valid is 1 when enough features were valid for a prediction and 0 otherwise
+#[derive(Signal)]
+#[signal(update_params = "Model::apply_params")]
+pub struct Model {
+    #[inputs]
+    inputs: ModelInputs,
+    #[output(name = "prediction")]
+    prediction: ConsumerOutput,
+    #[output(name = "valid")]
+    valid: ConsumerOutput,
+    #[params]
+    params: ModelParams,
+    #[state(init = "params.load(inputs.features.len())?")]
+    model: LinearModel,
+    #[state(init = "params.watch(inputs.features.len())")]
+    watcher: Option<ModelWatcher>,
+}
+
+impl Model {
+    // Loads the file straight away, which may now be a different one
+    fn apply_params(&mut self, json: &str) -> Result<(), anyhow::Error> {
+        let params = ModelParams::parse(json)?;
+        self.model = params.load(self.inputs.features.len())?;
+        self.watcher = params.watch(self.inputs.features.len());
+        self.params = params;
+        Ok(())
+    }
+}
+
+impl CallSignal for Model {
+    fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        if let Some(model) = self.watcher.as_ref().and_then(ModelWatcher::poll) {
+            self.model = model;
+        }
+        let min_valid = self.params.min_valid.unwrap_or_else(|| self.model.len());
+        let prediction = self
+            .model
+            .predict(self.inputs.features.values(graph), min_valid);
+        self.prediction.set_from(prediction, graph);
+        self.valid
+            .set(if prediction.is_some() { 1.0 } else { 0.0 }, graph);
+    }
+}
@@ -3,18 +3,26 @@ use crate::signal_graph::interface_types::*;
 
 use crate::exchange::normalized::MarketUpdates;
 
-use std::collections::{HashMap, HashSet};
+#[derive(SignalInputs)]
+struct BookImprovedInputs {
+    book: BookViewer,
+}
 
+#[derive(Signal)]
+#[signal(cleanup)]
 pub struct BookImprovedSignal {
-    book: BookViewer,
+    #[inputs]
+    inputs: BookImprovedInputs,
+    #[output(name = "improved_bid")]
     improved_bid_to: ConsumerOutput,
+    #[output(name = "improved_ask")]
     improved_ask_to: ConsumerOutput,
     tob: Option<(usize, usize)>,
 }
 
 impl CallSignal for BookImprovedSignal {
     fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
-        match self.book.book().bbo_price() {
+        match self.inputs.book.book().bbo_price() {
             (Some(best_bid), Some(best_ask)) => {
                 match self.tob {
                     Some((old_bid, old_ask)) => {
@@ -38,33 +46,3 @@ impl CallSignal for BookImprovedSignal {
         self.improved_ask_to.mark_invalid(graph);
     }
 }
-
-impl RegisterSignal for BookImprovedSignal {
-    const CLEANUP: bool = true;
-    type Child = Self;
-    fn get_inputs() -> HashMap<&'static str, SignalType> {
-        maplit::hashmap! {
-            "book" => SignalType::Book,
-        }
-    }
-
-    fn get_outputs() -> HashSet<&'static str> {
-        maplit::hashset! {
-            "improved_bid",
-            "improved_ask",
-        }
-    }
-
-    fn create(
-        mut outputs: HashMap<&'static str, ConsumerOutput>,
-        mut inputs: InputLoader,
-        _: Option<&str>,
-    ) -> Result<BookImprovedSignal, anyhow::Error> {
-        Ok(BookImprovedSignal {
-            improved_bid_to: outputs.remove("improved_bid").unwrap(),
-            improved_ask_to: outputs.remove("improved_ask").unwrap(),
-            book: inputs.load_input("book")?,
-            tob: None,
-        })
-    }
-}
@@ -18,29 +18,42 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use std::collections::{HashMap, HashSet};
 
-use crossbeam_channel::{bounded, TryRecvError};
+use crossbeam_channel::bounded;
 
 mod args;
+mod basis;
+mod book_signals;
 mod central_registry;
+mod cross_venue;
 mod displacement;
 mod ema;
 mod exchange;
+mod expr;
 mod fair_value;
 mod generate_signal;
+mod linear_model;
 mod local_book;
 mod md_thread;
 mod order_book;
+mod recorded_data;
 mod remote_venue_aggregator;
 mod security_to_reader;
 mod signal_graph;
+mod time_decay;
+mod trade_flow;
+mod train;
+mod volatility;
 
 use fair_value::*;
 
+use signal_graph::graph_spec::GraphSpec;
 use signal_graph::security_index::{Security, SecurityMap};
 
 use horrorshow::html;
 
+use signal_graph::graph_error::GraphError;
 use signal_graph::graph_registrar::*;
+use signal_graph::graph_runner::{GraphRunner, RunnerConfig};
 
 pub static DIE: AtomicBool = AtomicBool::new(false);
 pub static LOOP: AtomicUsize = AtomicUsize::new(0);
@@ -58,7 +71,132 @@ fn html_writer(filename: String, requests: mpsc::Receiver<String>) {
     println!("Done writing html");
 }
 
+fn default_securities() -> Vec<Security> {
+    vec![
+        Security::new("bitmex", "BTCMEX"),
+        Security::new("okex", "BTC_PERP_OK"),
+        Security::new("okex", "BTC"),
+        Security::new("okex", "BTC_QUARTERLY"),
+        Security::new("bybit", "USDT"),
+        Security::new("bybit", "Inverse"),
+        Security::new("huobi", "BTC_PERP_HB"),
+        Security::new("ftx", "BTC_PERP_FTX"),
+        Security::new("gdax", "BTC"),
+    ]
+}
+
+fn write_file(filename: &str, contents: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let atomic =
+        atomicwrites::AtomicFile::new(filename, atomicwrites::OverwriteBehavior::AllowOverwrite);
+    atomic.write(|temp_file| temp_file.write_all(contents.as_bytes()))?;
+    Ok(())
+}
+
+fn load_spec(spec: Option<String>) -> Result<GraphSpec, Box<dyn std::error::Error>> {
+    Ok(match spec {
+        Some(spec) => GraphSpec::load(&spec)?,
+        None => {
+            let securities = default_securities();
+            GraphSpec::new(
+                &securities,
+                &generate_signal::generate_signal_list(&securities),
+                &generate_signal::generate_inputs(&securities),
+            )?
+        }
+    })
+}
+
+fn describe(
+    spec: Option<String>,
+    json: Option<String>,
+    dot: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = load_spec(spec)?;
+    let sec_map = SecurityMap::create(&spec.securities);
+    let registrar = central_registry::generate_registrar()?;
+    let graph = registrar.generate_graph(&spec.layout(), &sec_map, &spec.params())?;
+    let description = graph.describe(&sec_map);
+
+    if json.is_none() && dot.is_none() {
+        println!("{}", description.to_dot());
+    }
+    if let Some(json) = json {
+        write_file(&json, &description.to_json()?)?;
+    }
+    if let Some(dot) = dot {
+        write_file(&dot, &description.to_dot())?;
+    }
+    Ok(())
+}
+
+fn schema(json: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let registrar = central_registry::generate_registrar()?;
+    let schemas = serde_json::to_string_pretty(&registrar.param_schemas())?;
+    match json {
+        Some(json) => write_file(&json, &schemas)?,
+        None => println!("{}", schemas),
+    }
+    Ok(())
+}
+
+fn train(
+    spec: Option<String>,
+    data: Vec<String>,
+    options: train::TrainOptions,
+    ridge: f64,
+    out: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = load_spec(spec)?;
+    let sec_map = SecurityMap::create(&spec.securities);
+    let registrar = central_registry::generate_registrar()?;
+    let mut graph = registrar.generate_graph(&spec.layout(), &sec_map, &spec.params())?;
+    let mut trainer = train::Trainer::new(&graph, &options)?;
+
+    // Recorded exchanges without a security in the graph are skipped
+    let mut recorded = recorded_data::RecordedData::open(&data)?;
+    while let Some(block) = recorded.next_block()? {
+        let index = security_to_reader::security_for_exchange(block.exchange)
+            .and_then(|security| sec_map.to_index(&security));
+        if let Some(index) = index {
+            trainer.replay(&mut graph, index, &block);
+        }
+    }
+
+    let fit = serde_json::to_string_pretty(&trainer.fit(ridge)?)?;
+    match out {
+        Some(out) => write_file(&out, &fit)?,
+        None => println!("{}", fit),
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = args::Arguments::from_args();
+    match args.command {
+        Some(args::Command::Describe { spec, json, dot }) => return describe(spec, json, dot),
+        Some(args::Command::Schema { json }) => return schema(json),
+        Some(args::Command::Train {
+            spec,
+            data,
+            feature,
+            target,
+            horizon,
+            interval,
+            ridge,
+            max_gap,
+            out,
+        }) => {
+            let options = train::TrainOptions {
+                features: feature,
+                target,
+                horizon,
+                interval,
+                max_gap,
+            };
+            return train(spec, data, options, ridge, out);
+        }
+        None => (),
+    }
     let mut rt = tokio::runtime::Builder::new()
         .basic_scheduler()
         .enable_all()
@@ -69,12 +207,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 pub enum TacticInternalEvent {
     DisplayHtml,
     Reset(bool),
-}
-
-enum TacticEventType {
-    WriteHtml,
-    Reset,
-    None,
+    UpdateParams(HashMap<String, String>),
+    Checkpoint,
 }
 
 async fn reset_loop(mut event_queue: tokio::sync::mpsc::Sender<TacticInternalEvent>) {
@@ -95,21 +229,70 @@ async fn html_writer_loop(mut event_queue: tokio::sync::mpsc::Sender<TacticInter
     }
 }
 
+async fn checkpoint_loop(mut event_queue: tokio::sync::mpsc::Sender<TacticInternalEvent>) {
+    loop {
+        tokio::time::delay_for(std::time::Duration::from_millis(1000 * 60)).await;
+        if event_queue
+            .send(TacticInternalEvent::Checkpoint)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+fn load_params_file(filename: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(filename)?;
+    let params: HashMap<String, serde_json::Value> =
+        serde_json::from_reader(std::io::BufReader::new(file))?;
+    Ok(params
+        .into_iter()
+        .map(|(signal, params)| (signal, params.to_string()))
+        .collect())
+}
+
+// Polls the parameter file and sends the whole thing whenever it changes.
+// The first check always sends, so a freshly built graph picks up the current file
+async fn params_watcher_loop(
+    filename: String,
+    mut event_queue: tokio::sync::mpsc::Sender<TacticInternalEvent>,
+) {
+    let mut last_modified = None;
+    loop {
+        let modified = std::fs::metadata(&filename).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            match load_params_file(&filename) {
+                Ok(params) => {
+                    if event_queue
+                        .send(TacticInternalEvent::UpdateParams(params))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                Err(err) => println!("Couldn't load parameter file {}: {}", filename, err),
+            }
+        }
+        tokio::time::delay_for(std::time::Duration::from_millis(1000)).await;
+    }
+}
+
+fn write_checkpoint(runner: &GraphRunner, checkpoint: &Option<String>) {
+    if let Some(checkpoint) = checkpoint {
+        if let Err(err) = runner.checkpoint(checkpoint) {
+            println!("Couldn't checkpoint to {}: {}", checkpoint, err);
+        }
+    }
+}
+
 async fn run() -> Result<(), Box<dyn std::error::Error>> {
     let start = Local::now();
     let args = args::Arguments::from_args();
     let (html_queue, html_reader) = std::sync::mpsc::channel();
-    let securities = [
-        Security::new("bitmex", "BTCMEX"),
-        Security::new("okex", "BTC_PERP_OK"),
-        Security::new("okex", "BTC"),
-        Security::new("okex", "BTC_QUARTERLY"),
-        Security::new("bybit", "USDT"),
-        Security::new("bybit", "Inverse"),
-        Security::new("huobi", "BTC_PERP_HB"),
-        Security::new("ftx", "BTC_PERP_FTX"),
-        Security::new("gdax", "BTC"),
-    ];
+    let securities = default_securities();
 
     let sec_map = SecurityMap::create(&securities);
 
@@ -117,9 +300,13 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
     std::thread::spawn(move || html_writer(html, html_reader));
 
     let mut bad_runs_count: usize = 0;
-    let registrar = central_registry::generate_registrar().unwrap();
+    let registrar = Arc::new(central_registry::generate_registrar().unwrap());
     let all_signals = generate_signal::generate_signal_list(&securities);
-    let inputs = generate_signal::generate_inputs(&securities);
+    let mut inputs = generate_signal::generate_inputs(&securities);
+    let runner_config = RunnerConfig {
+        core: args.graph_core,
+        busy_poll: args.busy_poll,
+    };
     loop {
         // This is a little weird. We need to 'kill this', but actually dropping it poisons
         // the various events pushing into it. So instead, this lives outside the data loop scope,
@@ -127,10 +314,38 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
         // all incoming messages
         let (event_queue, mut event_reader) = tokio::sync::mpsc::channel(100);
         {
-            let mut signal_graph = registrar
-                .generate_graph(&all_signals[..], &sec_map, &inputs)
-                .unwrap();
             let (md_sender, md_receiver) = bounded(5000);
+            // The graph is built on the runner thread and stays there
+            let build = {
+                let registrar = registrar.clone();
+                let all_signals = all_signals.clone();
+                let sec_map = sec_map.clone();
+                let inputs = inputs.clone();
+                let checkpoint = args.checkpoint.clone();
+                move || -> Result<_, GraphError> {
+                    let mut signal_graph =
+                        registrar.generate_graph(&all_signals[..], &sec_map, &inputs)?;
+                    if let Some(checkpoint) = &checkpoint {
+                        if std::path::Path::new(checkpoint).exists() {
+                            match signal_graph.restore(checkpoint) {
+                                Ok(()) => println!("Restored signals from {}", checkpoint),
+                                Err(err) => {
+                                    println!("Couldn't restore from {}: {}", checkpoint, err)
+                                }
+                            }
+                        }
+                    }
+                    Ok(signal_graph)
+                }
+            };
+            let runner = GraphRunner::spawn(runner_config.clone(), build, md_receiver).unwrap();
+            // Read into the same snapshot every time, in output then signal name order
+            let mut html_snapshot = runner.outputs().snapshot();
+            let mut html_order: Vec<_> = runner.outputs().ids().collect();
+            html_order.sort_by_key(|id| {
+                let (name, output) = runner.outputs().key(*id);
+                (output.clone(), name.clone())
+            });
             let desired_indices: Vec<_> = securities
                 .iter()
                 .map(|s| sec_map.to_index(s).unwrap())
@@ -143,49 +358,39 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
             // Spawn all tasks after we've connected to everything
             tokio::task::spawn(html_writer_loop(event_queue.clone()));
             tokio::task::spawn(reset_loop(event_queue.clone()));
+            if let Some(params_file) = args.params_file.clone() {
+                tokio::task::spawn(params_watcher_loop(params_file, event_queue.clone()));
+            }
+            if args.checkpoint.is_some() {
+                tokio::task::spawn(checkpoint_loop(event_queue.clone()));
+            }
 
             loop {
                 if DIE.load(Ordering::Relaxed) {
                     panic!("Death variable set");
                 }
-                let event_type = match md_receiver.try_recv() {
-                    Ok((index, data)) => {
-                        signal_graph.trigger_book(index, &data.events, 0, |_, _| ());
-                        None
-                    }
-                    Err(TryRecvError::Empty) => None,
-                    Err(TryRecvError::Disconnected) => panic!("Market data disconnected"),
-                };
-                let event_type = if let Some(event_type) = event_type {
-                    event_type
-                } else {
-                    match event_reader.try_recv() {
-                        Ok(TacticInternalEvent::DisplayHtml) => TacticEventType::WriteHtml,
-                        Ok(TacticInternalEvent::Reset(is_bad)) => {
-                            if is_bad {
-                                bad_runs_count += 1;
-                            }
-                            TacticEventType::Reset
-                        }
-                        Err(tokio::sync::mpsc::error::TryRecvError::Empty) => TacticEventType::None,
-                        Err(tokio::sync::mpsc::error::TryRecvError::Closed) => {
-                            panic!("event queue died")
+                // The runner only stops on its own when market data goes away
+                if !runner.is_running() {
+                    panic!("Market data disconnected");
+                }
+                match event_reader.recv().await {
+                    Some(TacticInternalEvent::Reset(is_bad)) => {
+                        if is_bad {
+                            bad_runs_count += 1;
                         }
-                    }
-                };
-                match &event_type {
-                    TacticEventType::Reset => {
                         // let in-flight items propogate
                         // We do a cancel all before we wait, and will do another after the wait
                         // In almost all cases this should get rid of in-flight orders
                         tokio::time::delay_for(std::time::Duration::from_millis(1000 * 2)).await;
 
+                        write_checkpoint(&runner, &args.checkpoint);
+
                         // Do a reset
                         break;
                     }
-                    TacticEventType::WriteHtml => {
-                        let mut outputs = signal_graph.load_outputs();
-                        outputs.sort_by_key(|((name, out), _)| (out.clone(), name.clone()));
+                    Some(TacticInternalEvent::DisplayHtml) => {
+                        let outputs = runner.outputs();
+                        outputs.read_into(&mut html_snapshot);
                         let signal_output = format!(
                             "{}",
                             html! {
@@ -193,12 +398,12 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
                                     body {
                                         h4(id="Signals", class="title");
                                         ol(id="count") {
-                                            @ for ((name, output), val) in &outputs {
+                                            @ for id in &html_order {
                                                 li(class="item") {
                                                     : format!("({}, {}):{}",
-                                                    name,
-                                                    output,
-                                                    val.map(|v| format!("{:.2}", v)).unwrap_or("None".to_string()))
+                                                    outputs.key(*id).0,
+                                                    outputs.key(*id).1,
+                                                    html_snapshot.get(*id).map(|v| format!("{:.2}", v)).unwrap_or("None".to_string()))
                                                 }
                                             }
                                         }
@@ -206,6 +411,15 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
                                 }
                             }
                         );
+                        #[cfg(feature = "profile")]
+                        let signal_output = format!(
+                            "{}{}",
+                            signal_output,
+                            runner
+                                .profile(sec_map.clone())
+                                .map(|profile| profile.to_html())
+                                .unwrap_or_default()
+                        );
                         let html = format!(
                             "
                         <!DOCYPE html>
@@ -228,9 +442,28 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
                         );
                         html_queue.send(html).expect("Couldn't send html");
                     }
-                    TacticEventType::None => tokio::task::yield_now().await,
-                };
+                    Some(TacticInternalEvent::UpdateParams(params)) => {
+                        for (signal, params) in &params {
+                            if inputs.get(signal) == Some(params) {
+                                continue;
+                            }
+                            match runner.update_params(signal, params) {
+                                Ok(()) => {
+                                    println!("Updated {} to {}", signal, params);
+                                    // Keep the new parameters when the graph gets reset
+                                    inputs.insert(signal.clone(), params.clone());
+                                }
+                                Err(err) => println!("Couldn't update {}: {}", signal, err),
+                            }
+                        }
+                    }
+                    Some(TacticInternalEvent::Checkpoint) => {
+                        write_checkpoint(&runner, &args.checkpoint)
+                    }
+                    None => panic!("event queue died"),
+                }
             }
+            runner.stop();
         }
 
         // We keep the state in the destructor to ensure everything exits cleanly
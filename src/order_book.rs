@@ -2,6 +2,11 @@ use std::collections::BTreeMap;
 
 use crate::exchange::normalized::*;
 
+// Book prices are held in cents, see convert_price_cents
+pub fn cents_to_dollars(cents: usize) -> f64 {
+    (cents as f64) * 0.01
+}
+
 #[derive(Ord, PartialOrd, Clone, Copy, Debug, Eq, PartialEq)]
 pub struct BuyPrice {
     value: i64,
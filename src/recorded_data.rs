@@ -0,0 +1,68 @@
+use crate::exchange::normalized::MarketEventBlock;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// Market data recorded as a stream of bincode MarketEventBlocks, the way the logger
+// writes it. Several recordings are merged into one stream in receive time order
+
+struct Recording {
+    filename: String,
+    reader: BufReader<File>,
+    next: Option<MarketEventBlock>,
+}
+
+impl Recording {
+    fn advance(&mut self) -> Result<(), anyhow::Error> {
+        self.next = if self.reader.fill_buf()?.is_empty() {
+            None
+        } else {
+            Some(
+                bincode::deserialize_from(&mut self.reader)
+                    .map_err(|e| anyhow::anyhow!("Bad block in {}: {}", self.filename, e))?,
+            )
+        };
+        Ok(())
+    }
+}
+
+pub struct RecordedData {
+    recordings: Vec<Recording>,
+}
+
+impl RecordedData {
+    pub fn open(filenames: &[String]) -> Result<RecordedData, anyhow::Error> {
+        let recordings = filenames
+            .iter()
+            .map(|filename| {
+                let file = File::open(filename)
+                    .map_err(|e| anyhow::anyhow!("Couldn't open {}: {}", filename, e))?;
+                let mut recording = Recording {
+                    filename: filename.clone(),
+                    reader: BufReader::new(file),
+                    next: None,
+                };
+                recording.advance()?;
+                Ok(recording)
+            })
+            .collect::<Result<_, anyhow::Error>>()?;
+        Ok(RecordedData { recordings })
+    }
+
+    // The earliest block not yet read across every recording
+    pub fn next_block(&mut self) -> Result<Option<MarketEventBlock>, anyhow::Error> {
+        let earliest = self
+            .recordings
+            .iter_mut()
+            .filter(|recording| recording.next.is_some())
+            .min_by_key(|recording| recording.next.as_ref().unwrap().received_time);
+        match earliest {
+            Some(recording) => {
+                let block = recording.next.take();
+                recording.advance()?;
+                Ok(block)
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -1,77 +1,51 @@
-use crate::ema::Ema;
 use crate::exchange::normalized::*;
-use crate::fair_value::FairValue;
-use crate::order_book::OrderBook;
-use futures::{future::FutureExt, join, select};
 
+use crate::signal_graph::aggregate_ops::WeightedSum;
 use crate::signal_graph::graph_registrar::*;
 use crate::signal_graph::interface_types::*;
 
-use std::collections::{HashMap, HashSet};
+#[derive(SignalInputs)]
+struct AggregatorInputs {
+    fair_mids: AggregateInput,
+    fair_sizes: AggregateInput,
+}
 
-// Hardcoded because futures are a bit silly for selecting variable amounts
+// Size-weighted fair over every venue, only revisiting venues which changed
+#[derive(Signal)]
+#[signal(validate = "RemoteVenueAggregator::check_inputs")]
 pub struct RemoteVenueAggregator {
-    fairs: Vec<(ConsumerInput, ConsumerInput)>,
+    #[inputs]
+    inputs: AggregatorInputs,
+    #[output(name = "fair")]
     fair_mid: ConsumerOutput,
+    #[output(name = "size")]
     total_size: ConsumerOutput,
+    #[state(init = "WeightedSum::new(inputs.fair_mids.len())")]
+    venues: WeightedSum,
 }
 
-impl RegisterSignal for RemoteVenueAggregator {
-    type Child = RemoteVenueAggregator;
-    const PARAMS: bool = false;
-
-    fn get_inputs() -> HashMap<&'static str, SignalType> {
-        maplit::hashmap! {
-            "fair_mids" => SignalType::Aggregate,
-            "fair_sizes" => SignalType::Aggregate,
-        }
-    }
-
-    fn get_outputs() -> HashSet<&'static str> {
-        maplit::hashset! {
-            "fair",
-            "size",
+impl RemoteVenueAggregator {
+    fn check_inputs(&self) -> Result<(), anyhow::Error> {
+        let AggregatorInputs {
+            fair_mids,
+            fair_sizes,
+        } = &self.inputs;
+        if fair_mids.len() != fair_sizes.len() {
+            anyhow::bail!(
+                "Got {} fair_mids but {} fair_sizes",
+                fair_mids.len(),
+                fair_sizes.len()
+            );
         }
-    }
-
-    // TODO combine inputs into one big friendly structure hiding them behind any
-    fn create(
-        mut outputs: HashMap<&'static str, ConsumerOutput>,
-        mut inputs: InputLoader,
-        json: Option<&str>,
-    ) -> Result<Self, anyhow::Error> {
-        let fairs: Vec<_> = inputs
-            .load_input::<AggregateInputGenerator>("fair_mids")?
-            .as_consumers();
-        let sizes: Vec<_> = inputs
-            .load_input::<AggregateInputGenerator>("fair_sizes")?
-            .as_consumers();
-        assert_eq!(fairs.len(), sizes.len());
-        Ok(RemoteVenueAggregator {
-            fairs: fairs.into_iter().zip(sizes.into_iter()).collect(),
-            fair_mid: outputs.remove("fair").unwrap(),
-            total_size: outputs.remove("size").unwrap(),
-        })
+        Ok(())
     }
 }
 
 impl CallSignal for RemoteVenueAggregator {
-    // TODO only iterate over changed, although... that requires better zipping api
-    // for the aggregate updates. Must think about how to do such a thing
     fn call_signal(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
-        let mut total_price = 0.0;
-        let mut total_size = 0.0;
-        for (fair, size) in self.fairs.iter() {
-            if let Some((fair, size)) = fair.and(&size, graph).get() {
-                total_price += fair * size;
-                total_size += size;
-            } else {
-                self.fair_mid.mark_invalid(graph);
-                self.total_size.mark_invalid(graph);
-                return;
-            }
-        }
-        self.fair_mid.set(total_price / total_size, graph);
-        self.total_size.set(total_size, graph);
+        self.venues
+            .update_from(&self.inputs.fair_mids, &self.inputs.fair_sizes, graph);
+        self.fair_mid.set_from(self.venues.mean(), graph);
+        self.total_size.set_from(self.venues.total_weight(), graph);
     }
 }
@@ -1,4 +1,5 @@
 use crate::exchange::normalized;
+use crate::exchange::normalized::Exchange;
 use crate::exchange::{
     bitmex_connection, bybit_connection, coinbase_connection, huobi_connection,
     okex_connection, BybitType, HuobiType, OkexType,
@@ -21,22 +22,65 @@ impl MarketDataStream {
     }
 }
 
+// The security each exchange's stream is known by, both live and in recordings
+const SECURITIES: &[(Exchange, &str, &str)] = &[
+    (Exchange::Bitmex, "bitmex", "BTCMEX"),
+    (Exchange::OkexSwap, "okex", "BTC_PERP_OK"),
+    (Exchange::OkexSpot, "okex", "BTC"),
+    (Exchange::OkexQuarterly, "okex", "BTC_QUARTERLY"),
+    (Exchange::BybitUSDT, "bybit", "USDT"),
+    (Exchange::BybitInverse, "bybit", "Inverse"),
+    (Exchange::HuobiSpot, "huobi", "BTC_PERP_HB"),
+    (Exchange::Coinbase, "gdax", "BTC"),
+];
+
+pub fn security_for_exchange(which: Exchange) -> Option<Security> {
+    SECURITIES
+        .iter()
+        .find(|(exchange, _, _)| *exchange == which)
+        .map(|(_, exchange, product)| Security::new(exchange, product))
+}
+
+fn exchange_for_security(sec: &Security) -> Option<Exchange> {
+    SECURITIES
+        .iter()
+        .find(|(_, exchange, product)| {
+            sec.exchange.as_str() == *exchange && sec.product.as_str() == *product
+        })
+        .map(|(exchange, _, _)| *exchange)
+}
+
 pub async fn reader_from_security(
     seci: SecurityIndex,
     map: &SecurityMap,
 ) -> Result<MarketDataStream, &Security> {
     let sec = map.to_security(seci);
-    let inner = match (sec.exchange.as_str(), sec.product.as_str()) {
-        ("bitmex", "BTCMEX") => Ok(bitmex_connection().await),
-        ("okex", "BTC_PERP_OK") => Ok(okex_connection(OkexType::Swap).await),
-        ("okex", "BTC") => Ok(okex_connection(OkexType::Spot).await),
-        ("okex", "BTC_QUARTERLY") => Ok(okex_connection(OkexType::Quarterly).await),
-        ("bybit", "USDT") => Ok(bybit_connection(BybitType::USDT).await),
-        ("bybit", "Inverse") => Ok(bybit_connection(BybitType::Inverse).await),
-        ("huobi", "BTC_PERP_HB") => Ok(huobi_connection(HuobiType::Spot).await),
-        ("gdax", "BTC") => Ok(coinbase_connection().await),
-        _ => Err(sec),
-    }?;
+    let inner = match exchange_for_security(sec) {
+        Some(Exchange::Bitmex) => bitmex_connection().await,
+        Some(Exchange::OkexSwap) => okex_connection(OkexType::Swap).await,
+        Some(Exchange::OkexSpot) => okex_connection(OkexType::Spot).await,
+        Some(Exchange::OkexQuarterly) => okex_connection(OkexType::Quarterly).await,
+        Some(Exchange::BybitUSDT) => bybit_connection(BybitType::USDT).await,
+        Some(Exchange::BybitInverse) => bybit_connection(BybitType::Inverse).await,
+        Some(Exchange::HuobiSpot) => huobi_connection(HuobiType::Spot).await,
+        Some(Exchange::Coinbase) => coinbase_connection().await,
+        Some(Exchange::HuobiSwap) | Some(Exchange::HuobiQuarterly) | None => return Err(sec),
+    };
 
     Ok(MarketDataStream { inner, index: seci })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn securities_round_trip() {
+        for (exchange, _, _) in SECURITIES {
+            let security = security_for_exchange(*exchange).unwrap();
+            assert_eq!(exchange_for_security(&security), Some(*exchange));
+        }
+        assert_eq!(security_for_exchange(Exchange::HuobiSwap), None);
+        assert_eq!(exchange_for_security(&Security::new("okex", "ETH")), None);
+    }
+}
@@ -0,0 +1,165 @@
+use super::graph::GraphInnerMem;
+use super::interface_types::AggregateInput;
+
+// Running sum of value * weight and of weight over a set of aggregates,
+// updated only at the indices which changed.
+// Removing old contributions accumulates rounding error,
+// so the totals are rebuilt from scratch every so often
+pub struct WeightedSum {
+    entries: Vec<Option<(f64, f64)>>,
+    weighted: f64,
+    weight: f64,
+    invalid: usize,
+    updates_until_rebuild: usize,
+}
+
+// Roughly how many full passes worth of single updates happen between rebuilds
+const REBUILD_PASSES: usize = 64;
+
+impl WeightedSum {
+    pub fn new(len: usize) -> WeightedSum {
+        WeightedSum {
+            entries: vec![None; len],
+            weighted: 0.0,
+            weight: 0.0,
+            invalid: len,
+            updates_until_rebuild: Self::rebuild_period(len),
+        }
+    }
+
+    fn rebuild_period(len: usize) -> usize {
+        len.max(1) * REBUILD_PASSES
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // An entry only counts if both the value and weight are valid
+    #[inline]
+    pub fn update(&mut self, index: usize, value: Option<f64>, weight: Option<f64>) {
+        let new = value.and_then(|value| weight.map(|weight| (value, weight)));
+        let entry = &mut self.entries[index];
+        match entry.take() {
+            Some((value, weight)) => {
+                self.weighted -= value * weight;
+                self.weight -= weight;
+            }
+            None => self.invalid -= 1,
+        }
+        match new {
+            Some((value, weight)) => {
+                self.weighted += value * weight;
+                self.weight += weight;
+            }
+            None => self.invalid += 1,
+        }
+        *entry = new;
+
+        self.updates_until_rebuild -= 1;
+        if self.updates_until_rebuild == 0 {
+            self.rebuild();
+        }
+    }
+
+    // Applies every index where either aggregate was written
+    #[inline]
+    pub fn update_from(
+        &mut self,
+        values: &AggregateInput,
+        weights: &AggregateInput,
+        graph: &GraphInnerMem,
+    ) {
+        debug_assert_eq!(values.len(), self.len());
+        for (index, zipped) in AggregateInput::zip_changed(&[values, weights], graph) {
+            self.update(index, zipped[0], zipped[1]);
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.weighted = 0.0;
+        self.weight = 0.0;
+        for (value, weight) in self.entries.iter().flatten() {
+            self.weighted += value * weight;
+            self.weight += weight;
+        }
+        self.updates_until_rebuild = Self::rebuild_period(self.len());
+    }
+
+    pub fn all_valid(&self) -> bool {
+        self.invalid == 0
+    }
+
+    // None unless every entry is valid
+    pub fn weighted_sum(&self) -> Option<f64> {
+        if self.all_valid() {
+            Some(self.weighted)
+        } else {
+            None
+        }
+    }
+
+    pub fn total_weight(&self) -> Option<f64> {
+        if self.all_valid() {
+            Some(self.weight)
+        } else {
+            None
+        }
+    }
+
+    // The weighted mean, None if anything is invalid or there's no weight
+    pub fn mean(&self) -> Option<f64> {
+        match (self.weighted_sum(), self.total_weight()) {
+            (Some(weighted), Some(weight)) if weight != 0.0 => Some(weighted / weight),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_sum_tracks_updates() {
+        let mut sum = WeightedSum::new(3);
+        assert_eq!(sum.mean(), None);
+        sum.update(0, Some(10.0), Some(1.0));
+        sum.update(1, Some(20.0), Some(3.0));
+        assert_eq!(sum.mean(), None);
+        sum.update(2, Some(30.0), None);
+        assert_eq!(sum.mean(), None);
+        sum.update(2, Some(30.0), Some(0.0));
+        assert_eq!(sum.weighted_sum(), Some(70.0));
+        assert_eq!(sum.total_weight(), Some(4.0));
+        assert_eq!(sum.mean(), Some(17.5));
+        sum.update(1, None, Some(3.0));
+        assert_eq!(sum.mean(), None);
+        sum.update(1, Some(40.0), Some(1.0));
+        assert_eq!(sum.mean(), Some(25.0));
+    }
+
+    #[test]
+    fn weighted_sum_rebuild_matches() {
+        let mut sum = WeightedSum::new(4);
+        for step in 0..10_000 {
+            let index = step % 4;
+            sum.update(
+                index,
+                Some(step as f64 * 0.1),
+                Some(1.0 + index as f64 * 1e-3),
+            );
+        }
+        let (weighted, weight) = sum
+            .entries
+            .iter()
+            .flatten()
+            .fold((0.0, 0.0), |(s, w), (v, x)| (s + v * x, w + x));
+        assert!((sum.weighted_sum().unwrap() - weighted).abs() < 1e-6);
+        assert!((sum.total_weight().unwrap() - weight).abs() < 1e-9);
+    }
+}
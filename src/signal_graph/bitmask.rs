@@ -0,0 +1,280 @@
+use std::cell::Cell;
+
+use super::graph_index::GraphIndex;
+
+// Per-output status bits. Written is only meaningful during a trigger and is cleared
+// in bulk afterwards, valid persists until the owning signal invalidates it
+pub const WRITTEN_MASK: u8 = 1;
+pub const VALID_MASK: u8 = 2;
+
+// The mask used by the graph. On x86_64 the byte-per-output layout with vector clears
+// is the fastest for the access patterns we see, everywhere else the packed bitsets
+// are portable and compact
+#[cfg(target_arch = "x86_64")]
+pub type GraphBitmask = ByteMask;
+#[cfg(not(target_arch = "x86_64"))]
+pub type GraphBitmask = PackedMask;
+
+// One byte per output holding both bits.
+// Getting and setting are single byte operations, and clearing written bits
+// is done sixteen outputs at a time
+pub struct ByteMask {
+    bytes: Vec<Cell<u8>>,
+}
+
+// Separate written and valid bitsets, sixty-four outputs to a word.
+// Clearing written bits is a single word store per block
+pub struct PackedMask {
+    written: Vec<Cell<u64>>,
+    valid: Vec<Cell<u64>>,
+}
+
+impl ByteMask {
+    pub const BLOCK_SIZE: usize = 16;
+
+    pub fn new(outputs: usize) -> ByteMask {
+        // Extra padding so that the last block can always be loaded in full
+        let blocks = (outputs + Self::BLOCK_SIZE - 1) / Self::BLOCK_SIZE;
+        ByteMask {
+            bytes: (0..(blocks + 2) * Self::BLOCK_SIZE)
+                .map(|_| Cell::new(0))
+                .collect(),
+        }
+    }
+
+    #[inline]
+    pub fn block_of(index: GraphIndex) -> GraphIndex {
+        index / Self::BLOCK_SIZE as GraphIndex
+    }
+
+    #[inline]
+    fn cell(&self, index: GraphIndex) -> &Cell<u8> {
+        let index = index as usize;
+        debug_assert!(index < self.bytes.len());
+        unsafe { self.bytes.get_unchecked(index) }
+    }
+
+    #[inline]
+    pub fn get(&self, index: GraphIndex, mask: u8) -> u8 {
+        self.cell(index).get() & mask
+    }
+
+    #[inline]
+    pub fn set(&self, index: GraphIndex, mask: u8) {
+        let cell = self.cell(index);
+        cell.set(cell.get() | mask);
+    }
+
+    #[inline]
+    pub fn clear(&self, index: GraphIndex, mask: u8) {
+        let cell = self.cell(index);
+        cell.set(cell.get() & !mask);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn clear_written(&self, blocks: &[GraphIndex]) {
+        use std::arch::x86_64::*;
+        // This depends on Cell's transparent representation, if it's right
+        // this is compiled out, otherwise just compiles to a panic
+        assert_eq!(std::mem::size_of::<Cell<u8>>(), std::mem::size_of::<u8>());
+        let addr = self.bytes.as_ptr() as *mut __m128i;
+        for block in blocks {
+            let block = *block as usize;
+            debug_assert!(self.bytes.len() >= (block + 1) * Self::BLOCK_SIZE);
+            unsafe {
+                let real_addr = addr.add(block);
+                let block_mask = _mm_set1_epi8(!WRITTEN_MASK as i8);
+                let loaded = _mm_loadu_si128(real_addr);
+                let loaded = _mm_and_si128(block_mask, loaded);
+                _mm_storeu_si128(real_addr, loaded);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    #[inline]
+    pub fn clear_written(&self, blocks: &[GraphIndex]) {
+        for block in blocks {
+            let start = *block as usize * Self::BLOCK_SIZE;
+            for cell in &self.bytes[start..start + Self::BLOCK_SIZE] {
+                cell.set(cell.get() & !WRITTEN_MASK);
+            }
+        }
+    }
+}
+
+impl PackedMask {
+    pub const BLOCK_SIZE: usize = 64;
+
+    pub fn new(outputs: usize) -> PackedMask {
+        let words = (outputs + Self::BLOCK_SIZE - 1) / Self::BLOCK_SIZE;
+        PackedMask {
+            written: (0..words).map(|_| Cell::new(0)).collect(),
+            valid: (0..words).map(|_| Cell::new(0)).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn block_of(index: GraphIndex) -> GraphIndex {
+        index / Self::BLOCK_SIZE as GraphIndex
+    }
+
+    #[inline]
+    fn word(bits: &[Cell<u64>], index: GraphIndex) -> &Cell<u64> {
+        let word = index as usize / Self::BLOCK_SIZE;
+        debug_assert!(word < bits.len());
+        unsafe { bits.get_unchecked(word) }
+    }
+
+    #[inline]
+    fn bit(index: GraphIndex) -> u64 {
+        1 << (index as usize % Self::BLOCK_SIZE)
+    }
+
+    #[inline]
+    pub fn get(&self, index: GraphIndex, mask: u8) -> u8 {
+        let bit = Self::bit(index);
+        let mut rval = 0;
+        if mask & WRITTEN_MASK != 0 && Self::word(&self.written, index).get() & bit != 0 {
+            rval |= WRITTEN_MASK;
+        }
+        if mask & VALID_MASK != 0 && Self::word(&self.valid, index).get() & bit != 0 {
+            rval |= VALID_MASK;
+        }
+        rval
+    }
+
+    #[inline]
+    pub fn set(&self, index: GraphIndex, mask: u8) {
+        let bit = Self::bit(index);
+        if mask & WRITTEN_MASK != 0 {
+            let word = Self::word(&self.written, index);
+            word.set(word.get() | bit);
+        }
+        if mask & VALID_MASK != 0 {
+            let word = Self::word(&self.valid, index);
+            word.set(word.get() | bit);
+        }
+    }
+
+    #[inline]
+    pub fn clear(&self, index: GraphIndex, mask: u8) {
+        let bit = Self::bit(index);
+        if mask & WRITTEN_MASK != 0 {
+            let word = Self::word(&self.written, index);
+            word.set(word.get() & !bit);
+        }
+        if mask & VALID_MASK != 0 {
+            let word = Self::word(&self.valid, index);
+            word.set(word.get() & !bit);
+        }
+    }
+
+    #[inline]
+    pub fn clear_written(&self, blocks: &[GraphIndex]) {
+        for block in blocks {
+            debug_assert!((*block as usize) < self.written.len());
+            unsafe { self.written.get_unchecked(*block as usize) }.set(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // Fixed-seed xorshift so any failure is reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    // The written bits cleared by a set of output indices must match between layouts,
+    // even though each clears in different block sizes. Written bits outside the cleared
+    // outputs' blocks are left alone by both, so only compare the requested outputs
+    // and everything that isn't written
+    #[test]
+    fn byte_and_packed_masks_agree() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let outputs = 1 + rng.below(300) as usize;
+            let bytes = ByteMask::new(outputs);
+            let packed = PackedMask::new(outputs);
+            for _ in 0..500 {
+                let index = rng.below(outputs as u64) as GraphIndex;
+                let mask = match rng.below(3) {
+                    0 => WRITTEN_MASK,
+                    1 => VALID_MASK,
+                    _ => WRITTEN_MASK | VALID_MASK,
+                };
+                match rng.below(4) {
+                    0 | 1 => {
+                        bytes.set(index, mask);
+                        packed.set(index, mask);
+                    }
+                    2 => {
+                        bytes.clear(index, mask);
+                        packed.clear(index, mask);
+                    }
+                    _ => {
+                        let to_clean: BTreeSet<_> = (0..1 + rng.below(8))
+                            .map(|_| rng.below(outputs as u64) as GraphIndex)
+                            .collect();
+                        let byte_blocks: BTreeSet<_> =
+                            to_clean.iter().map(|i| ByteMask::block_of(*i)).collect();
+                        let packed_blocks: BTreeSet<_> =
+                            to_clean.iter().map(|i| PackedMask::block_of(*i)).collect();
+                        bytes.clear_written(&byte_blocks.into_iter().collect::<Vec<_>>());
+                        packed.clear_written(&packed_blocks.into_iter().collect::<Vec<_>>());
+                        for index in &to_clean {
+                            assert_eq!(bytes.get(*index, WRITTEN_MASK), 0);
+                            assert_eq!(packed.get(*index, WRITTEN_MASK), 0);
+                        }
+                        // Packed blocks are wider, so drop written bits in the
+                        // byte mask that the packed one has already lost
+                        for index in 0..outputs as GraphIndex {
+                            if packed.get(index, WRITTEN_MASK) == 0 {
+                                bytes.clear(index, WRITTEN_MASK);
+                            }
+                        }
+                    }
+                }
+                for index in 0..outputs as GraphIndex {
+                    for mask in &[WRITTEN_MASK, VALID_MASK, WRITTEN_MASK | VALID_MASK] {
+                        assert_eq!(bytes.get(index, *mask), packed.get(index, *mask));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clear_written_keeps_valid() {
+        let bytes = ByteMask::new(100);
+        let packed = PackedMask::new(100);
+        for index in 0..100 {
+            bytes.set(index, WRITTEN_MASK | VALID_MASK);
+            packed.set(index, WRITTEN_MASK | VALID_MASK);
+        }
+        bytes.clear_written(&[ByteMask::block_of(99)]);
+        packed.clear_written(&[PackedMask::block_of(99)]);
+        assert_eq!(bytes.get(99, WRITTEN_MASK | VALID_MASK), VALID_MASK);
+        assert_eq!(packed.get(99, WRITTEN_MASK | VALID_MASK), VALID_MASK);
+        // block 6 of the byte mask covers 96..112, the packed block covers 64..128
+        assert_eq!(bytes.get(95, WRITTEN_MASK), WRITTEN_MASK);
+        assert_eq!(packed.get(95, WRITTEN_MASK), 0);
+        assert_eq!(packed.get(63, WRITTEN_MASK), WRITTEN_MASK);
+    }
+}
@@ -0,0 +1,45 @@
+use super::graph_error::GraphError;
+use super::graph_registrar::NamedSignalType;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::io::Write;
+
+// Saved state of every checkpointing signal in a graph.
+// Each entry keeps enough of the instantiation around to tell whether it still
+// describes the same signal when restored into a different graph
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GraphCheckpoint {
+    pub signals: HashMap<String, SignalCheckpoint>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalCheckpoint {
+    pub definition: String,
+    pub inputs: HashMap<String, NamedSignalType>,
+    // Values of valid f64 outputs, invalid outputs are None.
+    // Signals with typed outputs can't checkpoint, since those values aren't saved
+    pub outputs: HashMap<String, Option<f64>>,
+    pub state: serde_json::Value,
+}
+
+impl GraphCheckpoint {
+    pub fn write(&self, filename: &str) -> Result<(), GraphError> {
+        let data = serde_json::to_vec(self).map_err(|e| GraphError::CheckpointIoError(e.into()))?;
+        let atomic = atomicwrites::AtomicFile::new(
+            filename,
+            atomicwrites::OverwriteBehavior::AllowOverwrite,
+        );
+        atomic
+            .write(|temp_file| temp_file.write_all(&data))
+            .map_err(|e| GraphError::CheckpointIoError(anyhow::anyhow!("{}", e)))
+    }
+
+    pub fn read(filename: &str) -> Result<GraphCheckpoint, GraphError> {
+        let file =
+            std::fs::File::open(filename).map_err(|e| GraphError::CheckpointIoError(e.into()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| GraphError::CheckpointIoError(e.into()))
+    }
+}
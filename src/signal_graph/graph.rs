@@ -8,12 +8,18 @@ use crate::exchange::normalized::MarketUpdates;
 
 use crate::order_book::OrderBook;
 
+use super::bitmask::{GraphBitmask, VALID_MASK, WRITTEN_MASK};
+use super::checkpoint::{GraphCheckpoint, SignalCheckpoint};
+use super::graph_description::*;
 use super::graph_error::GraphError;
+use super::graph_index::{GraphIndex, MAX_GRAPH_INDEX};
 use super::graph_registrar::*;
-use super::graph_sort::{find_seen_signals, topological_sort};
+use super::graph_sort::{check_for_cycles, find_seen_signals, topological_sort};
 use super::interface_types::*;
+#[cfg(feature = "profile")]
+use super::profile::*;
 use super::security_data::SecurityVector;
-use super::security_index::{SecurityIndex, SecurityMap};
+use super::security_index::{Security, SecurityIndex, SecurityMap};
 
 use dynstack::DynStack;
 
@@ -22,29 +28,55 @@ use dynstack::DynStack;
 // and not by arbitrary metadata
 pub struct GraphInnerMem {
     pub(crate) output_values: Vec<Cell<f64>>,
-    // This could be compressed even more into a real bitmask,
-    // BUT this has significant code size and performance costs given how much it's called
-    pub(crate) mark_bitmask: Vec<Cell<u8>>,
+    // Values of typed outputs, keyed on output index with the word offset into typed_values
+    pub(crate) typed_values: Vec<Cell<u64>>,
+    pub(crate) typed_outputs: HashMap<GraphIndex, (OutputType, u32)>,
+    pub(crate) mark_bitmask: GraphBitmask,
     pub(crate) books: SecurityVector<Rc<RefCell<OrderBook>>>,
-    pub(crate) signal_output_to_index: HashMap<(String, String), u16>,
-    pub(crate) signal_name_to_index: HashMap<String, u16>,
+    pub(crate) signal_output_to_index: HashMap<(String, String), GraphIndex>,
+    pub(crate) signal_name_to_index: HashMap<String, ObjectIndex>,
     pub(crate) signal_name_to_instance: HashMap<String, SignalInstantiation>,
-    pub(crate) aggregate_mapping_array: Vec<u16>,
-    pub(crate) objects: DynStack<dyn CallSignal>,
+    pub(crate) aggregate_mapping_array: Vec<GraphIndex>,
+    // Signal objects, grouped by the build or patch that created them.
+    // A patched graph shares the generations holding signals it keeps, so kept objects
+    // never move and a generation is freed once no graph uses any of its signals
+    pub(crate) objects: Vec<Rc<DynStack<dyn CallSignal>>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ObjectIndex {
+    pub(crate) generation: usize,
+    pub(crate) index: GraphIndex,
+}
+
+// The graph a new one is patched from. Outputs that still exist keep their index,
+// and retained signals keep their objects, values and books
+pub(crate) struct PatchBase<'a> {
+    pub(crate) mem: &'a GraphInnerMem,
+    pub(crate) params: &'a HashMap<String, String>,
+    pub(crate) security_map: &'a SecurityMap,
 }
 
 pub(crate) struct GraphCallList {
+    pub(crate) call_order: Vec<String>,
     // TODO pull out specific functions from vtable
     // not high importance, only worth doing after proper testing is in place
     pub(crate) calls: Vec<(fn(*mut u8, u64, &MarketUpdates, &GraphInnerMem), *mut u8)>,
     pub(crate) cleanup: Vec<(fn(*mut u8, u64, &MarketUpdates, &GraphInnerMem), *mut u8)>,
     pub(crate) mem: Rc<GraphInnerMem>,
-    pub(crate) mark_as_clean: Vec<u16>,
+    pub(crate) mark_as_clean: Vec<GraphIndex>,
+    // Outputs set outside of any call, like restored ones, which are marked written
+    // for the next trigger so the signals called see them change
+    pub(crate) pending_written: Vec<GraphIndex>,
+    #[cfg(feature = "profile")]
+    pub(crate) profile: CallListProfile,
 }
 
 pub struct Graph {
     pub(crate) book_updates: SecurityVector<Option<GraphCallList>>,
     pub(crate) mem: Rc<GraphInnerMem>,
+    // The parameters each signal is currently running with
+    pub(crate) params: HashMap<String, String>,
 }
 
 impl NamedSignalType {
@@ -57,13 +89,24 @@ impl NamedSignalType {
 }
 
 impl GraphInnerMem {
+    // Returns the memory and the signals carried over unchanged from the base, if any
     pub(crate) fn new(
         signal_name_to_instance: HashMap<String, SignalInstantiation>,
-        layout: &[(String, SignalCall)],
         security_map: &SecurityMap,
         params: &HashMap<String, String>,
-    ) -> Result<Rc<GraphInnerMem>, GraphError> {
-        assert!(signal_name_to_instance.len() < std::u16::MAX as usize);
+        base: Option<&PatchBase>,
+    ) -> Result<(Rc<GraphInnerMem>, HashSet<String>), GraphError> {
+        if signal_name_to_instance.len() >= MAX_GRAPH_INDEX {
+            return Err(GraphError::TooManySignals(signal_name_to_instance.len()));
+        }
+
+        let total_outputs: usize = signal_name_to_instance
+            .values()
+            .map(|inst| inst.definition.all_outputs().count())
+            .sum();
+        if total_outputs >= MAX_GRAPH_INDEX {
+            return Err(GraphError::TooManyOutputs(total_outputs));
+        }
 
         // TODO code duplication here and registrar
         let requested_book_signals: HashSet<_> = signal_name_to_instance
@@ -85,10 +128,12 @@ impl GraphInnerMem {
             }
         }
 
+        check_for_cycles(&signal_name_to_instance, security_map)?;
+
         let security_call_list_justnames = SecurityVector::new_with_err(security_map, |sec, _| {
             if requested_book_signals.contains(sec) {
                 let seen_signals = find_seen_signals(sec, &signal_name_to_instance)?;
-                let sorted_order = topological_sort(&seen_signals, &signal_name_to_instance);
+                let sorted_order = topological_sort(sec, &seen_signals, &signal_name_to_instance)?;
                 Ok(Some(sorted_order))
             } else {
                 Ok(None)
@@ -96,7 +141,34 @@ impl GraphInnerMem {
         })?;
 
         let mut signal_output_to_index = HashMap::new();
-        let mut index_so_far: u16 = 0;
+        let mut index_so_far: usize = 0;
+        let mut typed_outputs = HashMap::new();
+        let mut typed_words = 0;
+
+        // Outputs of the base that still exist with the same type keep their index
+        // and typed storage, removed ones are left as holes
+        if let Some(base) = base {
+            for ((signal, output), index) in &base.mem.signal_output_to_index {
+                let instance = match signal_name_to_instance.get(signal) {
+                    Some(instance) => instance,
+                    None => continue,
+                };
+                if !instance.definition.all_outputs().any(|o| o == output) {
+                    continue;
+                }
+                let wants = instance.definition.typed_outputs.get(output.as_str());
+                match (wants, base.mem.typed_outputs.get(index)) {
+                    (None, None) => (),
+                    (Some(wants), Some((given, offset))) if wants == given => {
+                        typed_outputs.insert(*index, (*given, *offset));
+                    }
+                    _ => continue,
+                }
+                signal_output_to_index.insert((signal.clone(), output.clone()), *index);
+            }
+            index_so_far = base.mem.output_values.len();
+            typed_words = base.mem.typed_values.len();
+        }
 
         security_call_list_justnames.for_each(|sigs| {
             if let Some(sigs) = sigs {
@@ -104,10 +176,10 @@ impl GraphInnerMem {
                     let instance = signal_name_to_instance
                         .get(sig)
                         .expect("Missing signal instance");
-                    for output in &instance.definition.outputs {
+                    for output in instance.definition.all_outputs() {
                         let key = (sig.clone(), output.to_string());
                         if !signal_output_to_index.contains_key(&key) {
-                            signal_output_to_index.insert(key, index_so_far);
+                            signal_output_to_index.insert(key, index_so_far as GraphIndex);
                             index_so_far += 1;
                         }
                     }
@@ -116,42 +188,106 @@ impl GraphInnerMem {
         });
 
         for (signal, instance) in &signal_name_to_instance {
-            for output in &instance.definition.outputs {
+            for output in instance.definition.all_outputs() {
                 let key = (signal.clone(), output.to_string());
                 if !signal_output_to_index.contains_key(&key) {
-                    signal_output_to_index.insert(key, index_so_far);
+                    signal_output_to_index.insert(key, index_so_far as GraphIndex);
                     index_so_far += 1;
                 }
             }
         }
 
-        let output_values: Vec<_> = signal_output_to_index
-            .iter()
-            .map(|_| Cell::new(0.0))
-            .collect();
-
-        let mut mark_bitmask: Vec<_> = signal_output_to_index
-            .iter()
-            .map(|_| Cell::new(0))
-            .collect();
-
-        // REQUIRED! extra padding for bulk de-mask operations
-        for _ in 0..32 {
-            mark_bitmask.push(Cell::new(0));
+        // Holes left by patches count against the limit too
+        if index_so_far >= MAX_GRAPH_INDEX {
+            return Err(GraphError::TooManyOutputs(index_so_far));
         }
 
-        let books =
-            SecurityVector::new_with(security_map, |_, _| Rc::new(RefCell::new(OrderBook::new())));
+        let retained = match base {
+            Some(base) => find_retained(
+                base,
+                &signal_name_to_instance,
+                &signal_output_to_index,
+                params,
+            ),
+            None => HashSet::new(),
+        };
+
+        let output_values: Vec<_> = (0..index_so_far).map(|_| Cell::new(0.0)).collect();
+
+        let mark_bitmask = GraphBitmask::new(index_so_far);
+
+        // Books are shared with the base, since retained signals still view them
+        let books = SecurityVector::new_with(security_map, |sec, _| {
+            base.and_then(|base| {
+                let index = base.security_map.to_index(sec)?;
+                Some(base.mem.books.get(index).clone())
+            })
+            .unwrap_or_else(|| Rc::new(RefCell::new(OrderBook::new())))
+        });
 
         let mut ordered_signals: Vec<_> = signal_output_to_index.iter().collect();
 
         ordered_signals.sort_by(|(_, ind), (_, ind2)| ind.cmp(ind2));
-        let mut aggregate_offsets = Vec::new();
+
+        // Typed values are laid out in output order, each starting on a fresh word
+        for ((signal_name, output), index) in &ordered_signals {
+            if typed_outputs.contains_key(*index) {
+                continue;
+            }
+            let instance = &signal_name_to_instance[signal_name];
+            if let Some(output_type) = instance.definition.typed_outputs.get(output.as_str()) {
+                typed_outputs.insert(**index, (*output_type, typed_words as u32));
+                typed_words += output_type.words();
+            }
+        }
+        let typed_values: Vec<_> = (0..typed_words).map(|_| Cell::new(0)).collect();
+
+        // Retained signals index into the aggregate mappings of the base
+        let mut aggregate_offsets = match base {
+            Some(base) => base.mem.aggregate_mapping_array.clone(),
+            None => Vec::new(),
+        };
 
         let mut built_signals = HashSet::new();
 
-        let mut objects = DynStack::new();
         let mut signal_name_to_index = HashMap::new();
+        let mut objects = Vec::new();
+
+        if let Some(base) = base {
+            // Carry over the generations of retained signals, along with their values
+            let mut generations = HashMap::new();
+            for signal_name in &retained {
+                let old = base.mem.signal_name_to_index[signal_name];
+                let generation = *generations.entry(old.generation).or_insert_with(|| {
+                    objects.push(base.mem.objects[old.generation].clone());
+                    objects.len() - 1
+                });
+                signal_name_to_index.insert(
+                    signal_name.clone(),
+                    ObjectIndex {
+                        generation,
+                        index: old.index,
+                    },
+                );
+                built_signals.insert(signal_name);
+
+                let instance = &signal_name_to_instance[signal_name];
+                for output in instance.definition.all_outputs() {
+                    let index = signal_output_to_index[&(signal_name.clone(), output.to_string())];
+                    base.mem
+                        .copy_output_to(index, &output_values, &mark_bitmask);
+                    if let Some((output_type, offset)) = typed_outputs.get(&index) {
+                        let words = *offset as usize..*offset as usize + output_type.words();
+                        for word in words {
+                            typed_values[word].set(base.mem.typed_values[word].get());
+                        }
+                    }
+                }
+            }
+        }
+
+        let generation = objects.len();
+        let mut new_objects = DynStack::new();
 
         for ((signal_name, _), _) in ordered_signals {
             if built_signals.contains(signal_name) {
@@ -170,6 +306,7 @@ impl GraphInnerMem {
                     match (item, def) {
                         (NamedSignalType::Book(_), SignalType::Book)
                         | (NamedSignalType::Consumer(_), SignalType::Consumer)
+                        | (NamedSignalType::Consumer(_), SignalType::Typed(_))
                         | (NamedSignalType::Aggregate(_), SignalType::Aggregate) => (),
                         (named, sig_type) => {
                             return Err(GraphError::InputWrongType {
@@ -188,7 +325,7 @@ impl GraphInnerMem {
                 }
             }
 
-            for name in signal_inst.definition.inputs.keys() {
+            for (name, def) in signal_inst.definition.inputs.iter() {
                 let parents_of_inst = if let Some(parents) = signal_inst.inputs.get(*name) {
                     parents
                 } else {
@@ -220,8 +357,34 @@ impl GraphInnerMem {
                             signal_name,
                             name,
                         )?;
-                        let consumer_signal = ConsumerInput { which: consumer };
-                        hooks.insert(*name, Box::new(consumer_signal));
+                        let wants = match def {
+                            SignalType::Typed(output_type) => Some(output_type),
+                            _ => None,
+                        };
+                        match (wants, typed_outputs.get(&consumer)) {
+                            (None, None) => {
+                                let consumer_signal = ConsumerInput { which: consumer };
+                                hooks.insert(*name, Box::new(consumer_signal));
+                            }
+                            (Some(wants), Some((given, offset))) if wants == given => {
+                                let typed_signal = TypedHook {
+                                    which: consumer,
+                                    offset: *offset,
+                                    output_type: *given,
+                                };
+                                hooks.insert(*name, Box::new(typed_signal));
+                            }
+                            (wants, given) => {
+                                return Err(GraphError::OutputWrongType {
+                                    input: name.to_string(),
+                                    signal: signal_name.clone(),
+                                    parent: parent_signal.clone(),
+                                    output: parent_output.clone(),
+                                    wants: type_name_of(wants),
+                                    given: type_name_of(given.map(|(given, _)| given)),
+                                });
+                            }
+                        }
                     }
                     NamedSignalType::Aggregate(parents) => {
                         if parents.len() == 0 {
@@ -230,13 +393,6 @@ impl GraphInnerMem {
                                 name: name,
                             });
                         }
-                        if parents.len() > MAX_SIGNALS_PER_AGGREGATE {
-                            return Err(GraphError::AggregateTooLarge {
-                                instance: signal_name.clone(),
-                                input: name,
-                                entries: parents.len(),
-                            });
-                        }
                         let range_start = aggregate_offsets.len();
                         for (parent, output) in parents {
                             let consumer = get_index_for(
@@ -246,11 +402,25 @@ impl GraphInnerMem {
                                 signal_name,
                                 name,
                             )?;
+                            // Aggregates only work over f64 outputs
+                            if let Some((given, _)) = typed_outputs.get(&consumer) {
+                                return Err(GraphError::OutputWrongType {
+                                    input: name.to_string(),
+                                    signal: signal_name.clone(),
+                                    parent: parent.clone(),
+                                    output: output.clone(),
+                                    wants: type_name_of(None),
+                                    given: given.name(),
+                                });
+                            }
                             aggregate_offsets.push(consumer);
                         }
                         let range_end = aggregate_offsets.len();
+                        if range_end > MAX_GRAPH_INDEX {
+                            return Err(GraphError::TooManyAggregateReferences(range_end));
+                        }
                         let aggregate_signal = AggregateInputGenerator {
-                            offsets: (range_start as u16)..(range_end as u16),
+                            offsets: (range_start as GraphIndex)..(range_end as GraphIndex),
                             mapping: aggregate_offsets.clone(),
                         };
                         hooks.insert(*name, Box::new(aggregate_signal));
@@ -274,23 +444,45 @@ impl GraphInnerMem {
                 );
             }
 
+            let mut typed_output_hooks = HashMap::new();
+            for output in signal_inst.definition.typed_outputs.keys() {
+                let key = (signal_name.clone(), output.to_string());
+                let index = signal_output_to_index
+                    .get(&key)
+                    .expect("Missing generated output key");
+                let (output_type, offset) = typed_outputs[index];
+                typed_output_hooks.insert(
+                    *output,
+                    TypedHook {
+                        which: *index,
+                        offset,
+                        output_type,
+                    },
+                );
+            }
+
             let input: Option<&str> = params.get(signal_name).map(|s| s.as_str());
 
             let index = (signal_inst.definition.creator)(
                 output_hooks,
-                InputLoader { all_inputs: hooks },
+                InputLoader::new(hooks, typed_output_hooks),
                 input,
                 signal_name,
-                &mut objects,
+                &mut new_objects,
             )?;
             assert_eq!(
-                signal_name_to_index.insert(signal_name.clone(), index),
+                signal_name_to_index.insert(signal_name.clone(), ObjectIndex { generation, index }),
                 None
             );
         }
+        if new_objects.len() > 0 {
+            objects.push(Rc::new(new_objects));
+        }
 
         let mut rval = Rc::new(GraphInnerMem {
             output_values,
+            typed_values,
+            typed_outputs,
             books,
             mark_bitmask,
             signal_output_to_index,
@@ -300,17 +492,96 @@ impl GraphInnerMem {
             objects,
         });
 
-        Ok(rval)
+        Ok((rval, retained))
+    }
+
+    fn copy_output_to(&self, index: GraphIndex, values: &[Cell<f64>], bitmask: &GraphBitmask) {
+        values[index as usize].set(self.output_values[index as usize].get());
+        if self.mark_bitmask.get(index, VALID_MASK) != 0 {
+            bitmask.set(index, VALID_MASK);
+        }
+    }
+}
+
+// Signals of the base that carry over as they are. They need the same definition,
+// inputs and parameters, and every output they read or write must keep its index
+fn find_retained(
+    base: &PatchBase,
+    signal_name_to_instance: &HashMap<String, SignalInstantiation>,
+    signal_output_to_index: &HashMap<(String, String), GraphIndex>,
+    params: &HashMap<String, String>,
+) -> HashSet<String> {
+    let same_index = |signal: &str, output: &str| {
+        let key = (signal.to_string(), output.to_string());
+        match (
+            base.mem.signal_output_to_index.get(&key),
+            signal_output_to_index.get(&key),
+        ) {
+            (Some(old), Some(new)) => old == new,
+            _ => false,
+        }
+    };
+    signal_name_to_instance
+        .iter()
+        .filter(|(name, instance)| {
+            let old = match base.mem.signal_name_to_instance.get(*name) {
+                Some(old) => old,
+                None => return false,
+            };
+            old.definition_name == instance.definition_name
+                && old.inputs == instance.inputs
+                && base.params.get(*name) == params.get(*name)
+                && instance
+                    .definition
+                    .all_outputs()
+                    .all(|output| same_index(name, output))
+                && instance.inputs.values().all(|input| match input {
+                    NamedSignalType::Book(_) => true,
+                    NamedSignalType::Consumer((parent, output)) => same_index(parent, output),
+                    NamedSignalType::Aggregate(parents) => parents
+                        .iter()
+                        .all(|(parent, output)| same_index(parent, output)),
+                })
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+impl GraphInnerMem {
+    // Pointer to the signal object, for handing to the functions in a SignalDefinition.
+    // Callers must ensure that no other reference to the object is live while it's used
+    pub(crate) fn object_ptr(&self, signal: &str) -> *mut u8 {
+        let index = self
+            .signal_name_to_index
+            .get(signal)
+            .expect("Signal instance without an object");
+        let object = &self.objects[index.generation][index.index as usize];
+        object as *const dyn CallSignal as *mut dyn CallSignal as *mut u8
     }
+
+    // Whether any signal reads the book of this security, and so needs a call list
+    pub(crate) fn requests_book(&self, security: &Security) -> bool {
+        self.signal_name_to_instance
+            .values()
+            .flat_map(|instance| instance.inputs.values())
+            .any(|input| match input {
+                NamedSignalType::Book(sec) => sec == security,
+                _ => false,
+            })
+    }
+}
+
+fn type_name_of(output_type: Option<&OutputType>) -> &'static str {
+    output_type.map_or("f64", |output_type| output_type.name())
 }
 
 fn get_index_for(
-    signal_output_to_index: &HashMap<(String, String), u16>,
+    signal_output_to_index: &HashMap<(String, String), GraphIndex>,
     parent: &str,
     output: &str,
     signal: &str,
     name: &str,
-) -> Result<u16, GraphError> {
+) -> Result<GraphIndex, GraphError> {
     let key = (parent.to_string(), output.to_string());
     if let Some(parent) = signal_output_to_index.get(&key) {
         Ok(*parent)
@@ -325,34 +596,50 @@ fn get_index_for(
 }
 
 impl GraphCallList {
+    fn mark_pending(&self) {
+        for index in &self.pending_written {
+            self.mem.mark_bitmask.set(*index, WRITTEN_MASK);
+        }
+    }
+
     // TODO check types
+    #[cfg(not(feature = "profile"))]
     fn trigger(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphInnerMem) {
         for (call, ptr) in self.calls.iter() {
             call(*ptr, time, updates, graph);
         }
     }
 
+    #[cfg(feature = "profile")]
+    fn trigger(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphInnerMem) {
+        for ((call, ptr), hist) in self.calls.iter().zip(self.profile.calls.iter_mut()) {
+            let start = std::time::Instant::now();
+            call(*ptr, time, updates, graph);
+            hist.record(start.elapsed());
+        }
+    }
+
     fn cleanup(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphInnerMem) {
+        #[cfg(not(feature = "profile"))]
         for (call, ptr) in self.cleanup.iter() {
             call(*ptr, time, updates, graph)
         }
 
-        let mark_slice = &self.mem.mark_bitmask[..];
-        for to_mark in &self.mark_as_clean {
-            let to_mark = *to_mark as usize;
-            use std::arch::x86_64::*;
-            // This depends on Cell's transparent representation, if it's right
-            // this is compiled out, otherwise just compiles to a panic
-            assert_eq!(std::mem::size_of::<Cell<u8>>(), std::mem::size_of::<u8>());
-            debug_assert!(mark_slice.len() > (to_mark + 1) * 16);
-            let addr = mark_slice.as_ptr() as *mut __m128i;
-            unsafe {
-                let real_addr = addr.add(to_mark);
-                let block_mask = _mm_set1_epi8(!1);
-                let loaded = _mm_loadu_si128(real_addr);
-                let loaded = _mm_and_si128(block_mask, loaded);
-                _mm_store_si128(real_addr, loaded);
-            }
+        #[cfg(feature = "profile")]
+        for ((call, ptr), (_, hist)) in self.cleanup.iter().zip(self.profile.cleanup.iter_mut()) {
+            let start = std::time::Instant::now();
+            call(*ptr, time, updates, graph);
+            hist.record(start.elapsed());
+        }
+
+        self.mem.mark_bitmask.clear_written(&self.mark_as_clean);
+        if !self.pending_written.is_empty() {
+            let blocks: Vec<_> = self
+                .pending_written
+                .drain(..)
+                .map(GraphBitmask::block_of)
+                .collect();
+            self.mem.mark_bitmask.clear_written(&blocks);
         }
     }
 }
@@ -373,9 +660,65 @@ impl Graph {
         }
 
         if let Some(calls) = self.book_updates.get_mut(security) {
+            calls.mark_pending();
+            #[cfg(feature = "profile")]
+            let start = std::time::Instant::now();
             calls.trigger(time, events, &self.mem);
+            #[cfg(feature = "profile")]
+            let triggered = start.elapsed();
+
             fnc(time, &self.mem);
+
+            #[cfg(feature = "profile")]
+            let start = std::time::Instant::now();
             calls.cleanup(time, events, &self.mem);
+            #[cfg(feature = "profile")]
+            calls.profile.trigger.record(triggered + start.elapsed());
+        }
+    }
+
+    #[cfg(feature = "profile")]
+    pub fn profile(&self, security_map: &SecurityMap) -> GraphProfile {
+        let securities = security_map
+            .iter()
+            .zip(self.book_updates.iter())
+            .filter_map(|((security, _), calls)| {
+                let calls = calls.as_ref()?;
+                let mut signals: Vec<_> = calls
+                    .call_order
+                    .iter()
+                    .zip(calls.profile.calls.iter())
+                    .map(|(name, call)| SignalProfile {
+                        name: name.clone(),
+                        call: call.clone(),
+                        cleanup: None,
+                    })
+                    .collect();
+                for (owner, cleanup) in &calls.profile.cleanup {
+                    signals[*owner].cleanup = Some(cleanup.clone());
+                }
+                Some(SecurityProfile {
+                    security: security.clone(),
+                    trigger: calls.profile.trigger.clone(),
+                    signals,
+                })
+            })
+            .collect();
+        GraphProfile { securities }
+    }
+
+    #[cfg(feature = "profile")]
+    pub fn reset_profile(&mut self) {
+        for calls in self.book_updates.iter_mut() {
+            if let Some(calls) = calls {
+                calls.profile.trigger = Default::default();
+                for hist in calls.profile.calls.iter_mut() {
+                    *hist = Default::default();
+                }
+                for (_, hist) in calls.profile.cleanup.iter_mut() {
+                    *hist = Default::default();
+                }
+            }
         }
     }
 
@@ -383,16 +726,273 @@ impl Graph {
         self.mem
             .signal_output_to_index
             .get(&(signal.to_string(), output.to_string()))
+            .filter(|ind| !self.mem.typed_outputs.contains_key(ind))
             .map(|ind| ConsumerWatcher {
                 inner: ConsumerInput { which: *ind },
                 graph: self.mem.clone(),
             })
     }
 
+    // None if the output doesn't exist or doesn't hold a T
+    pub fn typed_listener<T: OutputValue>(
+        &self,
+        signal: &str,
+        output: &str,
+    ) -> Option<TypedWatcher<T>> {
+        let index = self
+            .mem
+            .signal_output_to_index
+            .get(&(signal.to_string(), output.to_string()))?;
+        let (output_type, offset) = self.mem.typed_outputs.get(index)?;
+        TypedHook {
+            which: *index,
+            offset: *offset,
+            output_type: *output_type,
+        }
+        .typed()
+        .map(|inner| TypedWatcher {
+            inner,
+            graph: self.mem.clone(),
+        })
+    }
+
+    pub fn update_params(&mut self, signal: &str, json: &str) -> Result<(), GraphError> {
+        let instance = self
+            .mem
+            .signal_name_to_instance
+            .get(signal)
+            .ok_or_else(|| GraphError::SignalNotFound(signal.to_string()))?;
+        let updater = instance
+            .definition
+            .updater
+            .ok_or_else(|| GraphError::NodeNotUpdatable(signal.to_string()))?;
+        // Same reasoning as for the call lists, we have a mutable reference to the graph
+        // so no call can be running and nobody else can be touching this object
+        updater(self.mem.object_ptr(signal), json).map_err(|error| {
+            GraphError::NodeUpdateError {
+                signal: signal.to_string(),
+                error,
+            }
+        })?;
+        self.params.insert(signal.to_string(), json.to_string());
+        Ok(())
+    }
+
+    pub fn checkpoint_state(&self) -> Result<GraphCheckpoint, GraphError> {
+        let mut checkpoint = GraphCheckpoint::default();
+        for (name, instance) in &self.mem.signal_name_to_instance {
+            let saver = match instance.definition.saver {
+                Some(saver) => saver,
+                None => continue,
+            };
+            let state =
+                saver(self.mem.object_ptr(name)).map_err(|error| GraphError::NodeStateError {
+                    signal: name.clone(),
+                    error,
+                })?;
+            let outputs = instance
+                .definition
+                .outputs
+                .iter()
+                .map(|output| {
+                    let index =
+                        self.mem.signal_output_to_index[&(name.clone(), output.to_string())];
+                    (
+                        output.to_string(),
+                        ConsumerInput { which: index }.get(&self.mem),
+                    )
+                })
+                .collect();
+            checkpoint.signals.insert(
+                name.clone(),
+                SignalCheckpoint {
+                    definition: instance.definition_name.clone(),
+                    inputs: instance.inputs.clone(),
+                    outputs,
+                    state,
+                },
+            );
+        }
+        Ok(checkpoint)
+    }
+
+    // Signals missing from the checkpoint start fresh, and checkpointed signals
+    // that aren't in this graph are ignored. Nothing is restored unless every signal
+    // in both matches and loads its state, although a signal's parameters are allowed
+    // to differ. Restored outputs are marked written on each book's next trigger
+    pub fn restore_state(&mut self, checkpoint: &GraphCheckpoint) -> Result<(), GraphError> {
+        let mut to_restore = Vec::new();
+        for (name, saved) in &checkpoint.signals {
+            let instance = match self.mem.signal_name_to_instance.get(name) {
+                Some(instance) => instance,
+                None => continue,
+            };
+            let mismatch = |reason: String| GraphError::CheckpointMismatch {
+                signal: name.clone(),
+                reason,
+            };
+            if instance.definition_name != saved.definition {
+                return Err(mismatch(format!(
+                    "definition is {} but checkpoint has {}",
+                    instance.definition_name, saved.definition
+                )));
+            }
+            if instance.inputs != saved.inputs {
+                return Err(mismatch("inputs differ".to_string()));
+            }
+            let (saver, loader) = match (instance.definition.saver, instance.definition.loader) {
+                (Some(saver), Some(loader)) => (saver, loader),
+                _ => return Err(mismatch("signal doesn't checkpoint".to_string())),
+            };
+            let mut outputs = Vec::new();
+            for output in &instance.definition.outputs {
+                let value = saved
+                    .outputs
+                    .get(*output)
+                    .ok_or_else(|| mismatch(format!("output {} missing", output)))?;
+                let index = self.mem.signal_output_to_index[&(name.clone(), output.to_string())];
+                outputs.push((ConsumerInput { which: index }, *value));
+            }
+            to_restore.push((name, saver, loader, outputs, saved.state.clone()));
+        }
+
+        // Each signal's current state is saved before loading, so that if a later
+        // load fails the ones already loaded can be put back.
+        // Loading in name order keeps which signal fails reproducible
+        to_restore.sort_by(|a, b| a.0.cmp(b.0));
+        let mut loaded = Vec::new();
+        for (name, saver, loader, _, state) in &to_restore {
+            // See update_params for why this is fine
+            let object = self.mem.object_ptr(name);
+            let result = saver(object).and_then(|previous| {
+                loader(object, state.clone())?;
+                Ok(previous)
+            });
+            match result {
+                Ok(previous) => loaded.push((name, loader, previous)),
+                Err(error) => {
+                    for (name, loader, previous) in loaded {
+                        loader(self.mem.object_ptr(name), previous)
+                            .expect("Signal couldn't reload its own saved state");
+                    }
+                    return Err(GraphError::NodeStateError {
+                        signal: (*name).clone(),
+                        error,
+                    });
+                }
+            }
+        }
+
+        let mut restored = Vec::new();
+        for (name, _, _, outputs, _) in to_restore {
+            for (output, value) in outputs {
+                output.restore(value, &self.mem);
+                restored.push((name.clone(), output.which));
+            }
+        }
+        self.mark_written_on_next_trigger(&restored, |_| true);
+        Ok(())
+    }
+
+    // Marks the signals' outputs written for the next trigger of each call list that
+    // wants them, so signals which only look at changes pick up values set outside
+    // a call. Lists calling the signal itself leave it to write its own outputs
+    pub(crate) fn mark_written_on_next_trigger<F: Fn(&GraphCallList) -> bool>(
+        &mut self,
+        outputs: &[(String, GraphIndex)],
+        wants: F,
+    ) {
+        for calls in self.book_updates.iter_mut().flatten() {
+            if !wants(calls) {
+                continue;
+            }
+            let called: HashSet<_> = calls.call_order.iter().collect();
+            calls.pending_written.extend(
+                outputs
+                    .iter()
+                    .filter(|(signal, _)| !called.contains(signal))
+                    .map(|(_, index)| *index),
+            );
+        }
+    }
+
+    pub fn checkpoint(&self, filename: &str) -> Result<(), GraphError> {
+        self.checkpoint_state()?.write(filename)
+    }
+
+    pub fn restore(&mut self, filename: &str) -> Result<(), GraphError> {
+        let checkpoint = GraphCheckpoint::read(filename)?;
+        self.restore_state(&checkpoint)
+    }
+
+    pub fn describe(&self, security_map: &SecurityMap) -> GraphDescription {
+        let books = security_map
+            .iter()
+            .zip(self.book_updates.iter())
+            .filter_map(|((security, _), calls)| {
+                calls.as_ref().map(|calls| BookDescription {
+                    security: security.clone(),
+                    call_order: calls.call_order.clone(),
+                })
+            })
+            .collect();
+
+        let mut signals: Vec<_> = self
+            .mem
+            .signal_name_to_instance
+            .iter()
+            .map(|(name, instance)| {
+                let mut inputs: Vec<_> = instance
+                    .inputs
+                    .iter()
+                    .map(|(input, source)| InputDescription {
+                        name: input.clone(),
+                        source: source.clone(),
+                    })
+                    .collect();
+                inputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+                // Typed values aren't described, only their type
+                let mut outputs: Vec<_> = instance
+                    .definition
+                    .all_outputs()
+                    .map(|output| {
+                        let index =
+                            self.mem.signal_output_to_index[&(name.clone(), output.to_string())];
+                        let output_type = self.mem.typed_outputs.get(&index).map(|(t, _)| t);
+                        OutputDescription {
+                            name: output.to_string(),
+                            index,
+                            value_type: type_name_of(output_type),
+                            value: match output_type {
+                                Some(_) => None,
+                                None => ConsumerInput { which: index }.get(&self.mem),
+                            },
+                        }
+                    })
+                    .collect();
+                outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+                SignalDescription {
+                    name: name.clone(),
+                    definition: instance.definition_name.clone(),
+                    params: self.params.get(name).cloned(),
+                    inputs,
+                    outputs,
+                }
+            })
+            .collect();
+        signals.sort_by(|a, b| a.name.cmp(&b.name));
+
+        GraphDescription { books, signals }
+    }
+
+    // Only the f64 outputs, typed outputs are read with typed_listener
     pub fn load_outputs(&self) -> Vec<((String, String), Option<f64>)> {
         self.mem
             .signal_output_to_index
             .iter()
+            .filter(|(_, index)| !self.mem.typed_outputs.contains_key(index))
             .map(|((name, output), index)| {
                 (
                     (name.clone(), output.clone()),
@@ -0,0 +1,131 @@
+use super::graph_index::GraphIndex;
+use super::graph_registrar::NamedSignalType;
+use super::security_index::Security;
+
+use serde::Serialize;
+
+use std::fmt::Write;
+
+// A plain-data snapshot of what generate_graph built, meant for humans and tooling.
+// Everything is sorted by name so that the same graph always describes identically.
+#[derive(Serialize, Debug)]
+pub struct GraphDescription {
+    pub books: Vec<BookDescription>,
+    pub signals: Vec<SignalDescription>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BookDescription {
+    pub security: Security,
+    // Signals in the order they are called when this book updates
+    pub call_order: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SignalDescription {
+    pub name: String,
+    pub definition: String,
+    pub params: Option<String>,
+    pub inputs: Vec<InputDescription>,
+    pub outputs: Vec<OutputDescription>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InputDescription {
+    pub name: String,
+    pub source: NamedSignalType,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OutputDescription {
+    pub name: String,
+    pub index: GraphIndex,
+    #[serde(rename = "type")]
+    pub value_type: &'static str,
+    // Always None for typed outputs
+    pub value: Option<f64>,
+}
+
+fn book_node(security: &Security) -> String {
+    format!("book:{}:{}", security.exchange, security.product)
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl GraphDescription {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    // Books are boxes feeding signal nodes, and every edge is labelled with the
+    // consuming input (and for signals, the output it reads)
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph signal_graph {{").unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+
+        for book in &self.books {
+            writeln!(
+                dot,
+                "    \"{}\" [shape=box, label=\"{} {}\"];",
+                escape(&book_node(&book.security)),
+                escape(&book.security.exchange),
+                escape(&book.security.product)
+            )
+            .unwrap();
+        }
+
+        for signal in &self.signals {
+            let mut label = format!("{}\n{}", signal.name, signal.definition);
+            if let Some(params) = &signal.params {
+                label.push('\n');
+                label.push_str(params.trim());
+            }
+            writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\"];",
+                escape(&signal.name),
+                escape(&label)
+            )
+            .unwrap();
+        }
+
+        for signal in &self.signals {
+            for input in &signal.inputs {
+                let edges: Vec<(String, String)> = match &input.source {
+                    NamedSignalType::Book(security) => {
+                        vec![(book_node(security), input.name.clone())]
+                    }
+                    NamedSignalType::Consumer((parent, output)) => {
+                        vec![(parent.clone(), format!("{}: {}", input.name, output))]
+                    }
+                    NamedSignalType::Aggregate(parents) => parents
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (parent, output))| {
+                            (parent.clone(), format!("{}[{}]: {}", input.name, i, output))
+                        })
+                        .collect(),
+                };
+                for (from, label) in edges {
+                    writeln!(
+                        dot,
+                        "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                        escape(&from),
+                        escape(&signal.name),
+                        escape(&label)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
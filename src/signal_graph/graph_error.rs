@@ -1,3 +1,4 @@
+use super::graph_index::MAX_GRAPH_INDEX;
 use super::graph_registrar::{NamedSignalType, SignalType};
 use super::security_index::Security;
 use thiserror::Error;
@@ -12,10 +13,8 @@ pub enum GraphError {
     DefinitionNotFound { definition: String, signal: String },
     #[error("Input {input} not given on signal {signal}")]
     InputNotGiven { input: &'static str, signal: String },
-    // TODO test
-    #[error("Input {input} does not exist on signal {}")]
+    #[error("Input {input} does not exist on signal {signal}")]
     InputNotExist { input: String, signal: String },
-    // TODO test
     #[error("Input {input} on signal {signal} has type {given:?}, has type {wants:?}")]
     InputWrongType {
         input: String,
@@ -23,16 +22,36 @@ pub enum GraphError {
         given: NamedSignalType,
         wants: SignalType,
     },
-    // TODO test
+    #[error(
+        "Input {input} on signal {signal} wants a {wants} but parent output {parent}:{output} is a {given}"
+    )]
+    OutputWrongType {
+        input: String,
+        signal: String,
+        parent: String,
+        output: String,
+        wants: &'static str,
+        given: &'static str,
+    },
+    #[error("Signal definition {definition} has output {output} as both f64 and typed")]
+    DuplicateOutputName {
+        definition: &'static str,
+        output: &'static str,
+    },
+    #[error("Signal definition {definition} checkpoints but has typed output {output}")]
+    TypedCheckpoint {
+        definition: &'static str,
+        output: &'static str,
+    },
     #[error("Signal {signal} missing subscription for inputs {inputs:?}")]
     MissingSubscription {
         signal: String,
         inputs: Vec<&'static str>,
     },
-    // TODO test
     #[error("Book input {security:?} not found")]
     BookNotFound { security: Security },
-    // TODO test
+    #[error("Security {0:?} is already in the graph")]
+    DuplicateSecurity(Security),
     #[error("Parent output {parent}:{output} requested by signal {child} input {input} not found")]
     ParentNotFound {
         parent: String,
@@ -40,37 +59,56 @@ pub enum GraphError {
         input: String,
         child: String,
     },
-    // TODO Test
-    #[error("Signal instance {instance} has too many entries {entries} in aggregate {input}")]
-    AggregateTooLarge {
-        instance: String,
-        entries: usize,
-        input: &'static str,
-    },
     #[error("Aggregate {name} on signal {signal} has no inputs")]
     AggregateNoInputs { signal: String, name: &'static str },
-    // TODO test
-    #[error("Too many signals in graph {0}, maximum is 2^16-1")]
+    #[error(
+        "Too many signals in graph {0}, maximum is {}. This can be increased with the wide-index feature",
+        MAX_GRAPH_INDEX - 1
+    )]
     TooManySignals(usize),
-    // TODO test
-    #[error("Too many signals in graph {0}, maximum references is 2^16-1. This can be increased")]
+    #[error(
+        "Too many outputs in graph {0}, maximum is {}. This can be increased with the wide-index feature",
+        MAX_GRAPH_INDEX - 1
+    )]
+    TooManyOutputs(usize),
+    #[error(
+        "Too many aggregate references in graph {0}, maximum is {}. This can be increased with the wide-index feature",
+        MAX_GRAPH_INDEX
+    )]
     TooManyAggregateReferences(usize),
-    //TODO test
+    // security is None when no book feeds the cycle, so it would never be called
     #[error(
         "Cycle discovered in graph call {call} containing {signals:?} in block for {security:?}"
     )]
     GraphCycle {
         call: String,
         signals: Vec<String>,
-        security: Security,
+        security: Option<Security>,
     },
-    // TODO test
     #[error("Signal {0} did not receive parameters")]
     NodeNoParams(String),
-    // TODO test
     #[error("Signal {0} received parameters but cannot take them")]
     NodeGotParams(String),
-    // TODO test
     #[error(transparent)]
     NodeInitError(anyhow::Error),
+    #[error("Signal {0} does not exist")]
+    SignalNotFound(String),
+    #[error("Signal {0} can't have parameters updated")]
+    NodeNotUpdatable(String),
+    #[error("Signal {signal} rejected parameter update: {error}")]
+    NodeUpdateError {
+        signal: String,
+        error: anyhow::Error,
+    },
+    #[error("Signal {signal} failed to save or load state: {error}")]
+    NodeStateError {
+        signal: String,
+        error: anyhow::Error,
+    },
+    #[error("Checkpoint for signal {signal} doesn't match the graph: {reason}")]
+    CheckpointMismatch { signal: String, reason: String },
+    #[error("Couldn't read or write checkpoint: {0}")]
+    CheckpointIoError(anyhow::Error),
+    #[error("Graph runner has stopped")]
+    RunnerStopped,
 }
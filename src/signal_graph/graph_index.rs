@@ -0,0 +1,9 @@
+// Width of output, aggregate and security indices. u16 keeps signal handles and
+// aggregate mappings small and cache friendly, while the wide-index feature allows
+// graphs with more than 65535 outputs or securities
+#[cfg(not(feature = "wide-index"))]
+pub type GraphIndex = u16;
+#[cfg(feature = "wide-index")]
+pub type GraphIndex = u32;
+
+pub const MAX_GRAPH_INDEX: usize = GraphIndex::MAX as usize;
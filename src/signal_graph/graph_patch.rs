@@ -0,0 +1,162 @@
+use super::graph::{Graph, GraphInnerMem, PatchBase};
+use super::graph_error::GraphError;
+use super::graph_registrar::{GraphRegistrar, NamedSignalType, SignalCall};
+use super::graph_sort::{find_seen_signals, generate_calls_for};
+use super::security_data::SecurityVector;
+use super::security_index::{Security, SecurityMap};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Changes to apply to a running graph, like listing a new expiry or dropping a venue.
+// Removals are applied before additions, so a signal can be replaced by removing
+// and adding it under the same name, or just by adding it again
+#[derive(Default, Debug, Clone)]
+pub struct GraphPatch {
+    pub add_securities: Vec<Security>,
+    pub remove_securities: Vec<Security>,
+    pub add_signals: Vec<(String, SignalCall)>,
+    pub remove_signals: Vec<String>,
+    // Parameters for added signals, or new parameters for existing ones.
+    // Signals given new parameters here are rebuilt instead of updated
+    pub params: HashMap<String, String>,
+}
+
+impl GraphPatch {
+    fn security_list(&self, security_map: &SecurityMap) -> Result<Vec<Security>, GraphError> {
+        for security in &self.remove_securities {
+            if security_map.to_index(security).is_none() {
+                return Err(GraphError::BookNotFound {
+                    security: security.clone(),
+                });
+            }
+        }
+        let mut securities: Vec<_> = security_map
+            .iter()
+            .map(|(security, _)| security.clone())
+            .filter(|security| !self.remove_securities.contains(security))
+            .collect();
+        for security in &self.add_securities {
+            if securities.contains(security) {
+                return Err(GraphError::DuplicateSecurity(security.clone()));
+            }
+            securities.push(security.clone());
+        }
+        Ok(securities)
+    }
+}
+
+impl GraphRegistrar {
+    // Applies the patch to the graph, returning the map for the new set of securities.
+    // Signals with the same definition, inputs and parameters as before keep their
+    // objects and output values, and call lists whose order is unchanged keep running
+    // as they were. Watchers and security indices from before the patch must be fetched
+    // again from the patched graph and returned map, and using an old index panics.
+    // The graph is left untouched if the patch fails
+    pub fn patch_graph(
+        &self,
+        graph: &mut Graph,
+        security_map: &SecurityMap,
+        patch: &GraphPatch,
+    ) -> Result<Arc<SecurityMap>, GraphError> {
+        let new_map = SecurityMap::create(&patch.security_list(security_map)?);
+
+        let mut instances = graph.mem.signal_name_to_instance.clone();
+        let mut params = graph.params.clone();
+        for name in &patch.remove_signals {
+            if instances.remove(name).is_none() {
+                return Err(GraphError::SignalNotFound(name.clone()));
+            }
+            params.remove(name);
+        }
+        for (name, call) in &patch.add_signals {
+            instances.insert(name.clone(), self.instantiate(name, call)?);
+            params.remove(name);
+        }
+        for (name, json) in &patch.params {
+            if !instances.contains_key(name) {
+                return Err(GraphError::SignalNotFound(name.clone()));
+            }
+            params.insert(name.clone(), json.clone());
+        }
+
+        let base = PatchBase {
+            mem: &graph.mem,
+            params: &graph.params,
+            security_map,
+        };
+        let (mem, retained) = GraphInnerMem::new(instances, &new_map, &params, Some(&base))?;
+
+        // Only securities whose calls changed get new call lists, the rest are moved over
+        let mut reuse = Vec::new();
+        let new_calls = SecurityVector::new_with_err(&new_map, |sec, _| {
+            if !mem.requests_book(sec) {
+                return Ok(None);
+            }
+            let old_calls = security_map
+                .to_index(sec)
+                .and_then(|index| graph.book_updates.get(index).as_ref());
+            if let Some(old_calls) = old_calls {
+                // Retained signals have the same inputs, so if the same signals are called
+                // the old order is still a valid one
+                let seen_signals = find_seen_signals(sec, &mem.signal_name_to_instance)?;
+                if seen_signals.len() == old_calls.call_order.len()
+                    && old_calls
+                        .call_order
+                        .iter()
+                        .all(|signal| seen_signals.contains(signal) && retained.contains(signal))
+                {
+                    reuse.push(sec.clone());
+                    return Ok(None);
+                }
+            }
+            Some(generate_calls_for(sec, mem.clone())).transpose()
+        })?;
+
+        // Added and rebuilt signals start out empty, so the retained outputs they
+        // aggregate are marked written for the lists calling them to pick up
+        let mut seeds = Vec::new();
+        for (name, instance) in &mem.signal_name_to_instance {
+            if retained.contains(name) {
+                continue;
+            }
+            let parents: Vec<_> = instance
+                .inputs
+                .values()
+                .filter_map(|input| match input {
+                    NamedSignalType::Aggregate(parents) => Some(parents),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|(parent, _)| retained.contains(parent))
+                .map(|key| (key.0.clone(), mem.signal_output_to_index[key]))
+                .collect();
+            if !parents.is_empty() {
+                seeds.push((name.clone(), parents));
+            }
+        }
+
+        // Nothing below can fail
+        let mut book_updates = new_calls;
+        for sec in reuse {
+            let old_index = security_map
+                .to_index(&sec)
+                .expect("Reused security missing");
+            let new_index = new_map.to_index(&sec).expect("Reused security missing");
+            let mut calls = graph
+                .book_updates
+                .get_mut(old_index)
+                .take()
+                .expect("Reused call list missing");
+            calls.mem = mem.clone();
+            *book_updates.get_mut(new_index) = Some(calls);
+        }
+        graph.book_updates = book_updates;
+        graph.mem = mem;
+        graph.params = params;
+        for (name, parents) in seeds {
+            graph.mark_written_on_next_trigger(&parents, |calls| calls.call_order.contains(&name));
+        }
+        Ok(new_map)
+    }
+}
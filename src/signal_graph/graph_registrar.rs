@@ -1,17 +1,23 @@
 use super::interface_types::*;
-use std::any::Any;
-use std::cell::{Cell, UnsafeCell};
-use std::collections::{HashMap, HashSet};
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
 use super::graph::{Graph, GraphCallList, GraphInnerMem};
 use super::graph_error::GraphError;
+use super::graph_index::{GraphIndex, MAX_GRAPH_INDEX};
 use super::graph_sort::generate_calls_for;
+use super::params::ParamSchema;
 use super::security_data::SecurityVector;
 use super::security_index::{Security, SecurityMap};
 
 use crate::exchange::normalized::MarketUpdates;
 
+use serde::{Deserialize, Serialize, Serializer};
+
+pub use signal_derive::{Signal, SignalInputs, SignalParams};
+
 pub type GraphHandle = GraphInnerMem;
 
 pub struct GraphRegistrar {
@@ -22,31 +28,85 @@ pub struct GraphRegistrar {
 pub struct SignalDefinition {
     pub(crate) inputs: HashMap<&'static str, SignalType>,
     pub(crate) outputs: HashSet<&'static str>,
+    pub(crate) typed_outputs: HashMap<&'static str, OutputType>,
+    pub(crate) params_schema: Option<ParamSchema>,
     pub(crate) creator: fn(
         outputs: HashMap<&'static str, ConsumerOutput>,
         inputs: InputLoader,
         json: Option<&str>,
         name: &str,
         objects: &mut dynstack::DynStack<dyn CallSignal>,
-    ) -> Result<u16, GraphError>,
+    ) -> Result<GraphIndex, GraphError>,
     pub(crate) caller: fn(*mut u8, u64, &MarketUpdates, &GraphInnerMem),
     pub(crate) cleanup: Option<fn(*mut u8, u64, &MarketUpdates, &GraphInnerMem)>,
+    pub(crate) updater: Option<fn(*mut u8, &str) -> Result<(), anyhow::Error>>,
+    pub(crate) saver: Option<fn(*mut u8) -> Result<serde_json::Value, anyhow::Error>>,
+    pub(crate) loader: Option<fn(*mut u8, serde_json::Value) -> Result<(), anyhow::Error>>,
 }
 
 #[derive(Clone)]
 pub(crate) struct SignalInstantiation {
+    pub(crate) definition_name: String,
     pub(crate) definition: SignalDefinition,
     pub(crate) inputs: HashMap<String, NamedSignalType>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub enum SignalType {
     Book,
     Consumer,
     Aggregate,
+    // A consumer of a typed output, given as NamedSignalType::Consumer in a layout
+    Typed(OutputType),
+}
+
+// Runtime description of the value held by a typed output.
+// Inputs and outputs are matched on this when the graph is built
+#[derive(Copy, Clone, Debug)]
+pub struct OutputType {
+    id: TypeId,
+    name: &'static str,
+    size: usize,
 }
 
-#[derive(Clone, Debug)]
+impl OutputType {
+    pub fn of<T: OutputValue>() -> OutputType {
+        // Values are stored in u64 words, anything more aligned would need
+        // padding that isn't worth supporting
+        assert!(
+            std::mem::align_of::<T>() <= std::mem::align_of::<u64>(),
+            "Output type {} is too aligned",
+            std::any::type_name::<T>()
+        );
+        OutputType {
+            id: TypeId::of::<T>(),
+            name: std::any::type_name::<T>(),
+            size: std::mem::size_of::<T>(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub(crate) fn words(&self) -> usize {
+        (self.size + 7) / 8
+    }
+}
+
+impl PartialEq for OutputType {
+    fn eq(&self, other: &OutputType) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Serialize for OutputType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum NamedSignalType {
     Book(Security),
     Consumer((String, String)),
@@ -54,7 +114,7 @@ pub enum NamedSignalType {
 }
 
 // TODO should be called instantiation, name already taken
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignalCall {
     pub signal_name: String,
     pub inputs: HashMap<String, NamedSignalType>,
@@ -66,6 +126,25 @@ impl GraphRegistrar {
     ) -> Result<Self, GraphError> {
         let mut signal_definitions = HashMap::new();
         for (name, definition) in signal_definition_list.iter().map(|(n, d)| (*n, d.clone())) {
+            if let Some(output) = definition
+                .typed_outputs
+                .keys()
+                .find(|output| definition.outputs.contains(*output))
+            {
+                return Err(GraphError::DuplicateOutputName {
+                    definition: name,
+                    output,
+                });
+            }
+            // Typed values can't be saved, so they'd come back invalid after a restore
+            if definition.saver.is_some() {
+                if let Some(output) = definition.typed_outputs.keys().min() {
+                    return Err(GraphError::TypedCheckpoint {
+                        definition: name,
+                        output,
+                    });
+                }
+            }
             if signal_definitions.insert(name, definition).is_some() {
                 return Err(GraphError::DuplicateSignalName(name));
             }
@@ -73,6 +152,19 @@ impl GraphRegistrar {
         Ok(GraphRegistrar { signal_definitions })
     }
 
+    // Parameter schemas of every definition which publishes one
+    pub fn param_schemas(&self) -> BTreeMap<&'static str, ParamSchema> {
+        self.signal_definitions
+            .iter()
+            .filter_map(|(name, definition)| {
+                definition
+                    .params_schema
+                    .clone()
+                    .map(|schema| (*name, schema))
+            })
+            .collect()
+    }
+
     pub fn generate_graph(
         &self,
         layout: &[(String, SignalCall)],
@@ -81,49 +173,15 @@ impl GraphRegistrar {
     ) -> Result<Graph, GraphError> {
         let mut signal_to_instance = HashMap::new();
         for (name, call) in layout {
-            let call_sig: &str = &call.signal_name;
             if signal_to_instance.contains_key(name) {
                 return Err(GraphError::DuplicateSignalInstance(name.clone()));
             }
-            let definition = if let Some(def) = self.signal_definitions.get(call_sig) {
-                def.clone()
-            } else {
-                return Err(GraphError::DefinitionNotFound {
-                    definition: call.signal_name.clone(),
-                    signal: name.clone(),
-                });
-            };
-            signal_to_instance.insert(
-                name.clone(),
-                SignalInstantiation {
-                    inputs: call.inputs.clone(),
-                    definition,
-                },
-            );
-        }
-
-        let inner_mem = GraphInnerMem::new(signal_to_instance, layout, security_map, inits)?;
-        let requested_book_signals: HashSet<_> = layout
-            .iter()
-            .map(|(_, b)| b)
-            .flat_map(|sc| sc.inputs.values())
-            .filter_map(|nst| match nst {
-                NamedSignalType::Book(sec) => Some(sec),
-                _ => None,
-            })
-            .collect();
-
-        for security in &requested_book_signals {
-            let security = *security;
-            if security_map.to_index(security).is_none() {
-                return Err(GraphError::BookNotFound {
-                    security: security.clone(),
-                });
-            }
+            signal_to_instance.insert(name.clone(), self.instantiate(name, call)?);
         }
 
+        let (inner_mem, _) = GraphInnerMem::new(signal_to_instance, security_map, inits, None)?;
         let security_call_list = SecurityVector::new_with_err(security_map, |sec, _| {
-            if requested_book_signals.contains(sec) {
+            if inner_mem.requests_book(sec) {
                 Some(generate_calls_for(sec, inner_mem.clone())).transpose()
             } else {
                 Ok(None)
@@ -133,6 +191,28 @@ impl GraphRegistrar {
         Ok(Graph {
             book_updates: security_call_list,
             mem: inner_mem,
+            params: inits.clone(),
+        })
+    }
+
+    pub(crate) fn instantiate(
+        &self,
+        name: &str,
+        call: &SignalCall,
+    ) -> Result<SignalInstantiation, GraphError> {
+        let definition = match self.signal_definitions.get(call.signal_name.as_str()) {
+            Some(definition) => definition.clone(),
+            None => {
+                return Err(GraphError::DefinitionNotFound {
+                    definition: call.signal_name.clone(),
+                    signal: name.to_string(),
+                })
+            }
+        };
+        Ok(SignalInstantiation {
+            definition_name: call.signal_name.clone(),
+            inputs: call.inputs.clone(),
+            definition,
         })
     }
 }
@@ -142,6 +222,35 @@ pub trait CallSignal {
     fn cleanup(&mut self, _: u64, _: &MarketUpdates, _: &GraphInnerMem) {
         unimplemented!("Cleanup called for signal without implementation, check your registrations")
     }
+    // Must leave the signal untouched if the parameters are rejected
+    fn update_params(&mut self, _: &str) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support parameter updates",
+            std::any::type_name::<Self>()
+        ))
+    }
+    // Outputs are checkpointed by the graph, these only need to cover internal state
+    fn save_state(&self) -> Result<serde_json::Value, anyhow::Error> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support checkpoints",
+            std::any::type_name::<Self>()
+        ))
+    }
+    fn load_state(&mut self, _: serde_json::Value) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support checkpoints",
+            std::any::type_name::<Self>()
+        ))
+    }
+}
+
+impl SignalDefinition {
+    pub(crate) fn all_outputs(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.outputs
+            .iter()
+            .chain(self.typed_outputs.keys())
+            .cloned()
+    }
 }
 
 pub fn make_signal_for<T: CallSignal + RegisterSignal<Child = T> + 'static>() -> SignalDefinition {
@@ -151,24 +260,39 @@ pub fn make_signal_for<T: CallSignal + RegisterSignal<Child = T> + 'static>() ->
         json: Option<&str>,
         name: &str,
         objects: &mut dynstack::DynStack<dyn CallSignal>,
-    ) -> Result<u16, GraphError> {
+    ) -> Result<GraphIndex, GraphError> {
         if json.is_some() && !F::PARAMS {
             return Err(GraphError::NodeGotParams(name.to_string()));
         }
         if json.is_none() && F::PARAMS {
             return Err(GraphError::NodeNoParams(name.to_string()));
         }
+        let loaded = inputs.loaded.clone();
         let val = match F::create(outputs, inputs, json) {
             Ok(val) => val,
             Err(err) => return Err(GraphError::NodeInitError(err)),
         };
+        let loaded = loaded.borrow();
+        let mut missing: Vec<_> = F::get_inputs()
+            .keys()
+            .filter(|input| !loaded.contains(*input))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(GraphError::MissingSubscription {
+                signal: name.to_string(),
+                inputs: missing,
+            });
+        }
         // BOOOOO rust and weird type specification problems
         // make it impossible to do this another way.
         let index = objects.len();
+        if index >= MAX_GRAPH_INDEX {
+            return Err(GraphError::TooManySignals(index + 1));
+        }
         dynstack::dyn_push!(objects, val);
-
-        assert!(index < std::u16::MAX as usize);
-        Ok(index as u16)
+        Ok(index as GraphIndex)
     }
 
     fn _call_signal<F: CallSignal>(
@@ -181,14 +305,44 @@ pub fn make_signal_for<T: CallSignal + RegisterSignal<Child = T> + 'static>() ->
         real_ref.call_signal(time, updates, graph)
     }
 
-    fn _cleanup_signal<F: CallSignal>(data: *mut u8, time: u64, events: &MarketUpdates, graph: &GraphInnerMem) {
+    fn _cleanup_signal<F: CallSignal>(
+        data: *mut u8,
+        time: u64,
+        events: &MarketUpdates,
+        graph: &GraphInnerMem,
+    ) {
         let real_ref = unsafe { &mut *(data as *mut F) };
         real_ref.cleanup(time, events, graph)
     }
 
+    fn _update_signal<F: RegisterSignal<Child = F>>(
+        data: *mut u8,
+        json: &str,
+    ) -> Result<(), anyhow::Error> {
+        let real_ref = unsafe { &mut *(data as *mut F) };
+        F::update_child_params(real_ref, json)
+    }
+
+    fn _save_signal<F: RegisterSignal<Child = F>>(
+        data: *mut u8,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        let real_ref = unsafe { &*(data as *const F) };
+        F::save_child_state(real_ref)
+    }
+
+    fn _load_signal<F: RegisterSignal<Child = F>>(
+        data: *mut u8,
+        state: serde_json::Value,
+    ) -> Result<(), anyhow::Error> {
+        let real_ref = unsafe { &mut *(data as *mut F) };
+        F::load_child_state(real_ref, state)
+    }
+
     SignalDefinition {
         inputs: T::get_inputs(),
         outputs: T::get_outputs(),
+        typed_outputs: T::get_typed_outputs(),
+        params_schema: T::params_schema(),
         creator: _real_create::<T>,
         caller: _call_signal::<T>,
         cleanup: if T::CLEANUP {
@@ -196,17 +350,49 @@ pub fn make_signal_for<T: CallSignal + RegisterSignal<Child = T> + 'static>() ->
         } else {
             None
         },
+        updater: if T::UPDATE_PARAMS {
+            Some(_update_signal::<T>)
+        } else {
+            None
+        },
+        saver: if T::CHECKPOINT {
+            Some(_save_signal::<T>)
+        } else {
+            None
+        },
+        loader: if T::CHECKPOINT {
+            Some(_load_signal::<T>)
+        } else {
+            None
+        },
     }
 }
 
 pub struct InputLoader {
     pub(crate) all_inputs: HashMap<&'static str, Box<dyn Any>>,
+    // Typed outputs are handed out here to keep create's signature the same for
+    // signals that only have f64 outputs
+    pub(crate) typed_outputs: HashMap<&'static str, TypedHook>,
+    // Shared with the creator so that inputs a signal never loads can be reported
+    pub(crate) loaded: Rc<RefCell<HashSet<&'static str>>>,
 }
 
 impl InputLoader {
+    pub(crate) fn new(
+        all_inputs: HashMap<&'static str, Box<dyn Any>>,
+        typed_outputs: HashMap<&'static str, TypedHook>,
+    ) -> InputLoader {
+        InputLoader {
+            all_inputs,
+            typed_outputs,
+            loaded: Rc::new(RefCell::new(HashSet::new())),
+        }
+    }
+
     pub fn load_input<T: InputType>(&mut self, name: &'static str) -> Result<T, anyhow::Error> {
         if let Some(signal) = self.all_inputs.remove(name) {
             if let Ok(downcast) = signal.downcast::<T>() {
+                self.loaded.borrow_mut().insert(name);
                 Ok(*downcast)
             } else {
                 anyhow::bail!("Wong type requested on input {}", name);
@@ -215,17 +401,246 @@ impl InputLoader {
             anyhow::bail!("Could not find signal for input {}", name);
         }
     }
+
+    pub fn load_typed_input<T: OutputValue>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<TypedInput<T>, anyhow::Error> {
+        let hook: TypedHook = self.load_input(name)?;
+        hook.typed().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Input {} has type {}, not {}",
+                name,
+                hook.output_type.name(),
+                std::any::type_name::<T>()
+            )
+        })
+    }
+
+    pub fn load_typed_output<T: OutputValue>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<TypedOutput<T>, anyhow::Error> {
+        if let Some(hook) = self.typed_outputs.get(name) {
+            match hook.typed() {
+                Some(inner) => {
+                    self.typed_outputs.remove(name);
+                    Ok(TypedOutput { inner })
+                }
+                None => anyhow::bail!(
+                    "Output {} has type {}, not {}",
+                    name,
+                    hook.output_type.name(),
+                    std::any::type_name::<T>()
+                ),
+            }
+        } else {
+            anyhow::bail!("Could not find typed output {}", name);
+        }
+    }
 }
 
 pub trait RegisterSignal {
     type Child: CallSignal + 'static;
     const PARAMS: bool = true;
     const CLEANUP: bool = false;
+    // Whether parameters can be swapped out on a live graph with update_child_params
+    const UPDATE_PARAMS: bool = false;
+    // Whether the signal's outputs and save_child_state survive a graph rebuild.
+    // Signals that are purely a function of their inputs don't need this
+    const CHECKPOINT: bool = false;
     fn get_inputs() -> HashMap<&'static str, SignalType>;
     fn get_outputs() -> HashSet<&'static str>;
+    // Outputs holding something other than a single f64, loaded with
+    // InputLoader::load_typed_output. Names can't overlap with get_outputs
+    fn get_typed_outputs() -> HashMap<&'static str, OutputType> {
+        HashMap::new()
+    }
+    // Only used for tooling, see SignalParams
+    fn params_schema() -> Option<ParamSchema> {
+        None
+    }
     fn create(
         outputs: HashMap<&'static str, ConsumerOutput>,
         inputs: InputLoader,
         json: Option<&str>,
     ) -> Result<Self::Child, anyhow::Error>;
+    // These call through to CallSignal unless generated by #[derive(Signal)]
+    fn update_child_params(child: &mut Self::Child, json: &str) -> Result<(), anyhow::Error> {
+        child.update_params(json)
+    }
+    fn save_child_state(child: &Self::Child) -> Result<serde_json::Value, anyhow::Error> {
+        child.save_state()
+    }
+    fn load_child_state(
+        child: &mut Self::Child,
+        state: serde_json::Value,
+    ) -> Result<(), anyhow::Error> {
+        child.load_state(state)
+    }
+}
+
+// A struct of every input a signal takes, usually from #[derive(SignalInputs)].
+// get_inputs can be forwarded from RegisterSignal, and load called in create
+pub trait SignalInputs: Sized {
+    fn get_inputs() -> HashMap<&'static str, SignalType>;
+    fn load(inputs: &mut InputLoader) -> Result<Self, anyhow::Error>;
+    fn all_valid(&self, graph: &GraphInnerMem) -> bool;
+    fn any_changed(&self, graph: &GraphInnerMem) -> bool;
+}
+
+// Anything that can be a field of a SignalInputs struct.
+// Books are always valid and never count as changed, since a signal can't tell
+// whether its book is the one that triggered it
+pub trait SignalInput: Sized {
+    fn signal_type() -> SignalType;
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error>;
+    fn is_valid(&self, graph: &GraphInnerMem) -> bool;
+    fn was_written(&self, graph: &GraphInnerMem) -> bool;
+}
+
+impl SignalInput for BookViewer {
+    fn signal_type() -> SignalType {
+        SignalType::Book
+    }
+
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error> {
+        inputs.load_input(name)
+    }
+
+    #[inline]
+    fn is_valid(&self, _: &GraphInnerMem) -> bool {
+        true
+    }
+
+    #[inline]
+    fn was_written(&self, _: &GraphInnerMem) -> bool {
+        false
+    }
+}
+
+impl SignalInput for ConsumerInput {
+    fn signal_type() -> SignalType {
+        SignalType::Consumer
+    }
+
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error> {
+        inputs.load_input(name)
+    }
+
+    #[inline]
+    fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        ConsumerInput::is_valid(self, graph)
+    }
+
+    #[inline]
+    fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        ConsumerInput::was_written(self, graph)
+    }
+}
+
+impl<T: OutputValue> SignalInput for TypedInput<T> {
+    fn signal_type() -> SignalType {
+        SignalType::Typed(OutputType::of::<T>())
+    }
+
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error> {
+        inputs.load_typed_input(name)
+    }
+
+    #[inline]
+    fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        TypedInput::is_valid(self, graph)
+    }
+
+    #[inline]
+    fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        TypedInput::was_written(self, graph)
+    }
+}
+
+impl SignalInput for AggregateInput {
+    fn signal_type() -> SignalType {
+        SignalType::Aggregate
+    }
+
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error> {
+        Ok(inputs
+            .load_input::<AggregateInputGenerator>(name)?
+            .as_update())
+    }
+
+    #[inline]
+    fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        self.all_valid(graph)
+    }
+
+    #[inline]
+    fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        self.any_written(graph)
+    }
+}
+
+// An aggregate split into its individual consumers
+impl SignalInput for Vec<ConsumerInput> {
+    fn signal_type() -> SignalType {
+        SignalType::Aggregate
+    }
+
+    fn load(inputs: &mut InputLoader, name: &'static str) -> Result<Self, anyhow::Error> {
+        Ok(inputs
+            .load_input::<AggregateInputGenerator>(name)?
+            .as_consumers())
+    }
+
+    #[inline]
+    fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        self.iter().all(|input| input.is_valid(graph))
+    }
+
+    #[inline]
+    fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        self.iter().any(|input| input.was_written(graph))
+    }
+}
+
+// Anything that can be an #[output] of a #[derive(Signal)] struct
+pub trait SignalOutput: Sized {
+    // None for plain f64 outputs
+    fn output_type() -> Option<OutputType>;
+    fn load(
+        outputs: &mut HashMap<&'static str, ConsumerOutput>,
+        inputs: &mut InputLoader,
+        name: &'static str,
+    ) -> Result<Self, anyhow::Error>;
+}
+
+impl SignalOutput for ConsumerOutput {
+    fn output_type() -> Option<OutputType> {
+        None
+    }
+
+    fn load(
+        outputs: &mut HashMap<&'static str, ConsumerOutput>,
+        _: &mut InputLoader,
+        name: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        outputs
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Could not find output {}", name))
+    }
+}
+
+impl<T: OutputValue> SignalOutput for TypedOutput<T> {
+    fn output_type() -> Option<OutputType> {
+        Some(OutputType::of::<T>())
+    }
+
+    fn load(
+        _: &mut HashMap<&'static str, ConsumerOutput>,
+        inputs: &mut InputLoader,
+        name: &'static str,
+    ) -> Result<Self, anyhow::Error> {
+        inputs.load_typed_output(name)
+    }
 }
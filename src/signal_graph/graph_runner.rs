@@ -0,0 +1,251 @@
+use super::graph::Graph;
+use super::graph_error::GraphError;
+#[cfg(feature = "profile")]
+use super::profile::GraphProfile;
+use super::published_outputs::{OutputPublisher, PublishedOutputs};
+use super::security_index::SecurityIndex;
+#[cfg(feature = "profile")]
+use super::security_index::SecurityMap;
+
+use crate::exchange::normalized::MarketEventBlock;
+
+use crossbeam_channel::{bounded, unbounded, Receiver, Select, Sender, TryRecvError};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone, Default)]
+pub struct RunnerConfig {
+    // Core to pin the graph thread to
+    pub core: Option<usize>,
+    // Spin on the queues instead of sleeping when there's nothing to do
+    pub busy_poll: bool,
+}
+
+enum RunnerCommand {
+    UpdateParams {
+        signal: String,
+        json: String,
+        reply: Sender<Result<(), GraphError>>,
+    },
+    Checkpoint {
+        filename: String,
+        reply: Sender<Result<(), GraphError>>,
+    },
+    #[cfg(feature = "profile")]
+    Profile {
+        security_map: Arc<SecurityMap>,
+        reply: Sender<GraphProfile>,
+    },
+    Stop,
+}
+
+// Owns a graph on its own thread, feeding it market data and publishing its outputs.
+// The graph is built on that thread and never leaves it, so nothing in it has to be Send.
+// The runner stops once the market data senders are all dropped, or when told to
+pub struct GraphRunner {
+    commands: Sender<RunnerCommand>,
+    outputs: Arc<PublishedOutputs>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GraphRunner {
+    // Blocks until the graph is built, returning the error if building it failed
+    pub fn spawn<B>(
+        config: RunnerConfig,
+        build: B,
+        market_data: Receiver<(SecurityIndex, MarketEventBlock)>,
+    ) -> Result<GraphRunner, GraphError>
+    where
+        B: FnOnce() -> Result<Graph, GraphError> + Send + 'static,
+    {
+        let (command_sender, commands) = unbounded();
+        let (built_sender, built) = bounded(1);
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = std::thread::Builder::new()
+            .name("graph".to_string())
+            .spawn(move || {
+                let _guard = scopeguard::guard((), |_| {
+                    thread_running.store(false, Ordering::Release);
+                });
+                if let Some(core) = config.core {
+                    core_affinity::set_for_current(core_affinity::CoreId { id: core });
+                }
+                let graph = match build() {
+                    Ok(graph) => graph,
+                    Err(err) => {
+                        let _ = built_sender.send(Err(err));
+                        return;
+                    }
+                };
+                let publisher = OutputPublisher::new(&graph);
+                if built_sender.send(Ok(publisher.outputs().clone())).is_ok() {
+                    run_graph(graph, publisher, config.busy_poll, market_data, commands);
+                }
+            })
+            .expect("Couldn't spawn graph thread");
+
+        match built.recv() {
+            Ok(Ok(outputs)) => Ok(GraphRunner {
+                commands: command_sender,
+                outputs,
+                running,
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                Err(err)
+            }
+            // The build panicked, so pass that along
+            Err(_) => match thread.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!("Graph thread exited without building"),
+            },
+        }
+    }
+
+    pub fn outputs(&self) -> &Arc<PublishedOutputs> {
+        &self.outputs
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    fn request<T>(&self, make: impl FnOnce(Sender<T>) -> RunnerCommand) -> Result<T, GraphError> {
+        let (reply, response) = bounded(1);
+        self.commands
+            .send(make(reply))
+            .map_err(|_| GraphError::RunnerStopped)?;
+        response.recv().map_err(|_| GraphError::RunnerStopped)
+    }
+
+    pub fn update_params(&self, signal: &str, json: &str) -> Result<(), GraphError> {
+        self.request(|reply| RunnerCommand::UpdateParams {
+            signal: signal.to_string(),
+            json: json.to_string(),
+            reply,
+        })?
+    }
+
+    pub fn checkpoint(&self, filename: &str) -> Result<(), GraphError> {
+        self.request(|reply| RunnerCommand::Checkpoint {
+            filename: filename.to_string(),
+            reply,
+        })?
+    }
+
+    #[cfg(feature = "profile")]
+    pub fn profile(&self, security_map: Arc<SecurityMap>) -> Result<GraphProfile, GraphError> {
+        self.request(|reply| RunnerCommand::Profile {
+            security_map,
+            reply,
+        })
+    }
+
+    // Stops the graph thread and waits for it, passing on any panic from it
+    pub fn stop(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(RunnerCommand::Stop);
+            if let Err(panic) = thread.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+}
+
+impl Drop for GraphRunner {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(RunnerCommand::Stop);
+            let _ = thread.join();
+        }
+    }
+}
+
+// Returns false once the runner should stop
+fn handle_command(graph: &mut Graph, command: RunnerCommand) -> bool {
+    match command {
+        RunnerCommand::UpdateParams {
+            signal,
+            json,
+            reply,
+        } => {
+            let _ = reply.send(graph.update_params(&signal, &json));
+        }
+        RunnerCommand::Checkpoint { filename, reply } => {
+            let _ = reply.send(graph.checkpoint(&filename));
+        }
+        #[cfg(feature = "profile")]
+        RunnerCommand::Profile {
+            security_map,
+            reply,
+        } => {
+            let _ = reply.send(graph.profile(&security_map));
+        }
+        RunnerCommand::Stop => return false,
+    }
+    true
+}
+
+// Most blocks handled between publishing and checking for commands
+const MAX_DRAIN: usize = 256;
+
+fn run_graph(
+    mut graph: Graph,
+    mut publisher: OutputPublisher,
+    busy_poll: bool,
+    market_data: Receiver<(SecurityIndex, MarketEventBlock)>,
+    commands: Receiver<RunnerCommand>,
+) {
+    loop {
+        // Drain what's queued before publishing, so a burst is published once.
+        // The drain is capped so sustained data still gets published and commands served
+        let mut triggered = false;
+        let mut disconnected = false;
+        for _ in 0..MAX_DRAIN {
+            match market_data.try_recv() {
+                Ok((index, block)) => {
+                    graph.trigger_book(index, &block.events, block.received_time, |_, _| ());
+                    triggered = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+        if triggered {
+            publisher.publish(&graph);
+        }
+        if disconnected {
+            return;
+        }
+
+        match commands.try_recv() {
+            Ok(command) => {
+                if !handle_command(&mut graph, command) {
+                    return;
+                }
+                continue;
+            }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => return,
+        }
+
+        if !triggered {
+            if busy_poll {
+                std::hint::spin_loop();
+            } else {
+                let mut select = Select::new();
+                select.recv(&market_data);
+                select.recv(&commands);
+                select.ready();
+            }
+        }
+    }
+}
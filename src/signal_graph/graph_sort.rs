@@ -4,9 +4,12 @@ use std::cell::UnsafeCell;
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+use super::bitmask::GraphBitmask;
 use super::graph::{Graph, GraphCallList, GraphInnerMem};
 use super::graph_error::GraphError;
 use super::graph_registrar::*;
+#[cfg(feature = "profile")]
+use super::profile::CallListProfile;
 use super::security_data::SecurityVector;
 use super::security_index::{Security, SecurityMap};
 
@@ -66,28 +69,126 @@ pub(crate) fn find_seen_signals(
     }
 }
 
+pub(crate) fn parents_of(instance: &SignalInstantiation) -> impl Iterator<Item = &String> {
+    instance
+        .inputs
+        .values()
+        .map(|parents| match parents {
+            NamedSignalType::Consumer(parent) => vec![&parent.0],
+            NamedSignalType::Aggregate(parents) => parents.iter().map(|(s, _)| s).collect(),
+            NamedSignalType::Book(_) => vec![],
+        })
+        .flat_map(|parents| parents.into_iter())
+}
+
+// Searches a child -> parents dependency map for a cycle, returning the signals
+// in the cycle ordered the way data flows through them (each signal consumes the one before it,
+// and the first consumes the last). The path is rotated to start at its smallest name
+// so that the same graph always reports the same cycle.
+pub(crate) fn find_cycle(dependencies: &HashMap<String, Vec<String>>) -> Option<Vec<String>> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum Visit {
+        InProgress,
+        Done,
+    }
+
+    fn visit<'a>(
+        signal: &'a str,
+        dependencies: &'a HashMap<String, Vec<String>>,
+        state: &mut HashMap<&'a str, Visit>,
+        stack: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        match state.get(signal) {
+            Some(Visit::Done) => return None,
+            Some(Visit::InProgress) => {
+                let start = stack
+                    .iter()
+                    .position(|s| *s == signal)
+                    .expect("In-progress signal missing from stack");
+                // The stack walks from child to parent, reverse it to get the call order
+                let mut cycle: Vec<_> =
+                    stack[start..].iter().rev().map(|s| s.to_string()).collect();
+                let smallest = (0..cycle.len())
+                    .min_by_key(|i| &cycle[*i])
+                    .expect("Cycle can't be empty");
+                cycle.rotate_left(smallest);
+                return Some(cycle);
+            }
+            None => (),
+        }
+        state.insert(signal, Visit::InProgress);
+        stack.push(signal);
+        if let Some(parents) = dependencies.get(signal) {
+            for parent in parents {
+                // Parents that aren't in the map are reported elsewhere as not found
+                if dependencies.contains_key(parent) {
+                    if let Some(cycle) = visit(parent, dependencies, state, stack) {
+                        return Some(cycle);
+                    }
+                }
+            }
+        }
+        stack.pop();
+        state.insert(signal, Visit::Done);
+        None
+    }
+
+    let mut signals: Vec<_> = dependencies.keys().collect();
+    signals.sort();
+    let mut state = HashMap::new();
+    let mut stack = Vec::new();
+    for signal in signals {
+        if let Some(cycle) = visit(signal, dependencies, &mut state, &mut stack) {
+            return Some(cycle);
+        }
+    }
+    None
+}
+
+pub(crate) fn check_for_cycles(
+    signal_name_to_instance: &HashMap<String, SignalInstantiation>,
+    security_map: &SecurityMap,
+) -> Result<(), GraphError> {
+    let dependencies: HashMap<_, _> = signal_name_to_instance
+        .iter()
+        .map(|(name, instance)| (name.clone(), parents_of(instance).cloned().collect()))
+        .collect();
+
+    if let Some(signals) = find_cycle(&dependencies) {
+        // Report the first security whose block would have contained the cycle.
+        // A cycle with no book anywhere upstream has no block, but is still an error
+        let mut security = None;
+        for (sec, _) in security_map.iter() {
+            if find_seen_signals(sec, signal_name_to_instance)?.contains(&signals[0]) {
+                security = Some(sec.clone());
+                break;
+            }
+        }
+        return Err(GraphError::GraphCycle {
+            call: signals[0].clone(),
+            signals,
+            security,
+        });
+    }
+    Ok(())
+}
+
 pub(crate) fn topological_sort(
+    security: &Security,
     seen_signals: &HashSet<String>,
     signal_name_to_instance: &HashMap<String, SignalInstantiation>,
-) -> Vec<String> {
+) -> Result<Vec<String>, GraphError> {
     let mut dependencies = HashMap::new();
     // gather the dependencies for every signal in the seen_signals set
     for signal in seen_signals {
-        let parents: HashSet<_> = signal_name_to_instance
-            .get(signal)
-            .expect("Missing signal late in process")
-            .inputs
-            .values()
-            .map(|parents| match parents {
-                NamedSignalType::Consumer(parent) => vec![parent.0.clone()],
-                NamedSignalType::Aggregate(parents) => {
-                    parents.iter().map(|(s, n)| s.clone()).collect()
-                }
-                NamedSignalType::Book(_) => vec![],
-            })
-            .flat_map(|parents| parents.into_iter())
-            .filter(|parent| seen_signals.contains(parent.as_str()))
-            .collect();
+        let parents: HashSet<_> = parents_of(
+            signal_name_to_instance
+                .get(signal)
+                .expect("Missing signal late in process"),
+        )
+        .filter(|parent| seen_signals.contains(parent.as_str()))
+        .cloned()
+        .collect();
         dependencies.insert(signal, parents);
     }
 
@@ -115,9 +216,19 @@ pub(crate) fn topological_sort(
 
         if ordered_signals.len() == starting_size {
             if dependencies.len() != 0 {
-                panic!("had a cycle with signals {:?}", dependencies.keys());
+                let remaining = dependencies
+                    .into_iter()
+                    .map(|(child, parents)| (child.clone(), parents.into_iter().collect()))
+                    .collect();
+                let signals = find_cycle(&remaining)
+                    .expect("Topological sort stalled without a cycle in the remaining signals");
+                return Err(GraphError::GraphCycle {
+                    call: signals[0].clone(),
+                    signals,
+                    security: Some(security.clone()),
+                });
             }
-            return ordered_signals;
+            return Ok(ordered_signals);
         }
     }
 }
@@ -127,7 +238,7 @@ pub(crate) fn generate_calls_for(
     mem: Rc<GraphInnerMem>,
 ) -> Result<GraphCallList, GraphError> {
     let seen_signals = find_seen_signals(security, &mem.signal_name_to_instance)?;
-    let sorted = topological_sort(&seen_signals, &mem.signal_name_to_instance);
+    let sorted = topological_sort(security, &seen_signals, &mem.signal_name_to_instance)?;
 
     // Now generate the list of distinct mark indices to mark
     // Since we generate indices in terms of call order, this hopefully should be fairly compact
@@ -139,8 +250,8 @@ pub(crate) fn generate_calls_for(
         .map(|((sig, _), index)| (sig, index))
         .filter(|(sig, _)| seen_signals.contains(*sig))
         .map(|(_, index)| {
-            // Reduce to the nearest 16 elements to mask off
-            index / 16
+            // Reduce to the block of outputs the mask clears at once
+            GraphBitmask::block_of(*index)
         })
         .collect();
 
@@ -151,22 +262,26 @@ pub(crate) fn generate_calls_for(
 
     let mut calls = Vec::new();
     let mut cleanup = Vec::new();
+    #[cfg(feature = "profile")]
+    let mut profile = CallListProfile::default();
 
-    for signal in sorted {
-        let index = mem
-            .signal_name_to_index
-            .get(&signal)
-            .expect("signal name missing late");
+    for signal in &sorted {
         let inst = mem
             .signal_name_to_instance
-            .get(&signal)
+            .get(signal)
             .expect("signal name missing late");
-        let object = &mem.objects[*index as usize];
         // this carefully, carefully, carefully works since we control
         // when the actual objects are referenced out of the graph list.
         // This pointer is only dereferenced during the calls, and we won't have
         // overlapping references
-        let object_ptr = unsafe { object as *const CallSignal as *mut CallSignal as *mut u8 };
+        let object_ptr = mem.object_ptr(signal);
+        #[cfg(feature = "profile")]
+        {
+            if inst.definition.cleanup.is_some() {
+                profile.cleanup.push((calls.len(), Default::default()));
+            }
+            profile.calls.push(Default::default());
+        }
         calls.push((inst.definition.caller, object_ptr));
         if let Some(cleaner) = inst.definition.cleanup {
             cleanup.push((cleaner, object_ptr));
@@ -174,9 +289,13 @@ pub(crate) fn generate_calls_for(
     }
 
     Ok(GraphCallList {
+        call_order: sorted,
         calls,
         cleanup,
         mark_as_clean,
+        pending_written: Vec::new(),
         mem,
+        #[cfg(feature = "profile")]
+        profile,
     })
 }
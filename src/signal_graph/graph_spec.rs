@@ -0,0 +1,68 @@
+use super::graph_registrar::SignalCall;
+use super::security_index::Security;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+// File representation of everything generate_graph needs.
+// Parameters are stored as real json instead of strings, and are turned back into
+// the strings that signals parse when the layout is generated
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphSpec {
+    pub securities: Vec<Security>,
+    pub signals: Vec<SignalSpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub call: SignalCall,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+}
+
+impl GraphSpec {
+    pub fn new(
+        securities: &[Security],
+        layout: &[(String, SignalCall)],
+        params: &HashMap<String, String>,
+    ) -> Result<GraphSpec, serde_json::Error> {
+        let mut signals = Vec::new();
+        for (name, call) in layout {
+            let params = params
+                .get(name)
+                .map(|p| serde_json::from_str(p))
+                .transpose()?;
+            signals.push(SignalSpec {
+                name: name.clone(),
+                call: call.clone(),
+                params,
+            });
+        }
+        Ok(GraphSpec {
+            securities: securities.to_vec(),
+            signals,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<GraphSpec, anyhow::Error> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    pub fn layout(&self) -> Vec<(String, SignalCall)> {
+        self.signals
+            .iter()
+            .map(|s| (s.name.clone(), s.call.clone()))
+            .collect()
+    }
+
+    pub fn params(&self) -> HashMap<String, String> {
+        self.signals
+            .iter()
+            .filter_map(|s| s.params.as_ref().map(|p| (s.name.clone(), p.to_string())))
+            .collect()
+    }
+}
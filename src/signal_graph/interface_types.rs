@@ -1,7 +1,13 @@
+use super::bitmask::{GraphBitmask, VALID_MASK, WRITTEN_MASK};
 use super::graph::GraphInnerMem;
+use super::graph_index::GraphIndex;
+use super::graph_registrar::OutputType;
 use crate::order_book::OrderBook;
 
+use smallvec::SmallVec;
+
 use std::cell::{Cell, Ref, RefCell};
+use std::marker::PhantomData;
 use std::rc::Rc;
 
 #[derive(Default)]
@@ -24,10 +30,8 @@ impl BookViewer {
     }
 }
 
-const MAX_AGGREGATE_SIGNALS: usize = 64;
-
 pub struct ConsumerInput {
-    pub(crate) which: u16,
+    pub(crate) which: GraphIndex,
 }
 
 pub struct ConsumerOutput {
@@ -39,17 +43,61 @@ pub struct ConsumerWatcher {
     pub(crate) graph: Rc<GraphInnerMem>,
 }
 
+// Anything plain enough to be copied in and out of graph memory,
+// like fixed size arrays of floats or small Copy structs
+pub trait OutputValue: Copy + 'static {}
+
+impl<T: Copy + 'static> OutputValue for T {}
+
+// Typed counterparts of ConsumerInput/Output/Watcher. These share the valid and written
+// bits with every other output, only the value lives in separate storage
+pub struct TypedInput<T> {
+    pub(crate) which: GraphIndex,
+    pub(crate) offset: u32,
+    _marker: PhantomData<T>,
+}
+
+pub struct TypedOutput<T> {
+    pub(crate) inner: TypedInput<T>,
+}
+
+pub struct TypedWatcher<T> {
+    pub(crate) inner: TypedInput<T>,
+    pub(crate) graph: Rc<GraphInnerMem>,
+}
+
+// Type-erased typed input or output, as handed over by the graph builder
+pub(crate) struct TypedHook {
+    pub(crate) which: GraphIndex,
+    pub(crate) offset: u32,
+    pub(crate) output_type: OutputType,
+}
+
 pub struct AggregateInput {
-    pub(crate) offsets: std::ops::Range<u16>,
+    pub(crate) offsets: std::ops::Range<GraphIndex>,
 }
 
 pub struct AggregateInputIter<'a> {
-    updated_mask: u64,
+    changed: ChangedMask,
+    graph: &'a GraphInnerMem,
+    index_mapping: &'a [GraphIndex],
+}
+
+// Values of each zipped aggregate at one index, stored inline for a few aggregates
+pub type ZippedValues = SmallVec<[Option<f64>; 4]>;
+
+pub struct AggregateZipIter<'a> {
+    changed: ChangedMask,
     graph: &'a GraphInnerMem,
-    index_mapping: &'a [u16],
+    mappings: SmallVec<[&'a [GraphIndex]; 4]>,
 }
 
-pub(crate) const MAX_SIGNALS_PER_AGGREGATE: usize = 64;
+// One bit per aggregate member, set if that member was written.
+// Aggregates of up to 64 members, by far the most common, fit in a single inline word
+struct ChangedMask {
+    words: SmallVec<[u64; 1]>,
+    word: usize,
+}
 
 impl ConsumerInput {
     #[inline]
@@ -95,42 +143,154 @@ impl ConsumerInput {
     ) -> AndConsumers<(f64, f64)> {
         self.and(&other.inner, graph)
     }
-}
-
-const WRITTEN_MASK: u8 = 1;
-const VALID_MASK: u8 = 2;
 
-// TODO combine
+    // Sets the value and valid bit without marking the output as written,
+    // since restored values weren't produced by any call in progress.
+    // The graph marks them written on the next trigger instead
+    pub(crate) fn restore(&self, value: Option<f64>, graph: &GraphInnerMem) {
+        clear_slice(self.which, VALID_MASK | WRITTEN_MASK, &graph.mark_bitmask);
+        if let Some(value) = value {
+            self.get_cell(graph).set(value);
+            mark_slice(self.which, VALID_MASK, &graph.mark_bitmask);
+        }
+    }
+}
 
 #[inline]
-fn get_raw_bit(index: u16, mask: u8, slice: &[Cell<u8>]) -> u8 {
-    let index = index as usize;
-    debug_assert!(index < slice.len());
-    let mask_cell = unsafe { slice.get_unchecked(index) };
-    mask_cell.get() & mask
+fn get_raw_bit(index: GraphIndex, mask: u8, bitmask: &GraphBitmask) -> u8 {
+    bitmask.get(index, mask)
 }
 
 #[inline]
-fn get_bit(index: u16, mask: u8, slice: &[Cell<u8>]) -> bool {
-    get_raw_bit(index, mask, slice) != 0
+fn get_bit(index: GraphIndex, mask: u8, bitmask: &GraphBitmask) -> bool {
+    get_raw_bit(index, mask, bitmask) != 0
 }
 
 #[inline]
-fn mark_slice(index: u16, mask: u8, slice: &[Cell<u8>]) {
-    let index = index as usize;
-    debug_assert!(index < slice.len());
-    let mask_cell = unsafe { slice.get_unchecked(index) };
-    let old_mask = mask_cell.get();
-    mask_cell.set(old_mask | mask);
+fn mark_slice(index: GraphIndex, mask: u8, bitmask: &GraphBitmask) {
+    bitmask.set(index, mask)
 }
 
 #[inline]
-fn clear_slice(index: u16, mask: u8, slice: &[Cell<u8>]) {
-    let index = index as usize;
-    debug_assert!(index < slice.len());
-    let mask_cell = unsafe { slice.get_unchecked(index) };
-    let old_mask = mask_cell.get();
-    mask_cell.set(old_mask & !mask);
+fn clear_slice(index: GraphIndex, mask: u8, bitmask: &GraphBitmask) {
+    bitmask.clear(index, mask)
+}
+
+impl TypedHook {
+    pub(crate) fn typed<T: OutputValue>(&self) -> Option<TypedInput<T>> {
+        if self.output_type == OutputType::of::<T>() {
+            Some(TypedInput {
+                which: self.which,
+                offset: self.offset,
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: OutputValue> TypedInput<T> {
+    #[inline]
+    fn value_ptr(&self, graph: &GraphInnerMem) -> *mut T {
+        let offset = self.offset as usize;
+        debug_assert!(offset * 8 + std::mem::size_of::<T>() <= graph.typed_values.len() * 8);
+        // Cell is transparent and the storage is u64 aligned,
+        // which OutputType checks is enough for T
+        unsafe { graph.typed_values.as_ptr().add(offset) as *mut T }
+    }
+
+    #[inline]
+    pub fn get(&self, graph: &GraphInnerMem) -> Option<T> {
+        if self.is_valid(graph) {
+            Some(unsafe { *self.value_ptr(graph) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        get_bit(self.which, VALID_MASK, &graph.mark_bitmask)
+    }
+
+    #[inline]
+    pub fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        get_bit(self.which, WRITTEN_MASK, &graph.mark_bitmask)
+    }
+}
+
+impl<T: OutputValue> TypedOutput<T> {
+    #[inline]
+    pub fn get(&self, graph: &GraphInnerMem) -> Option<T> {
+        self.inner.get(graph)
+    }
+
+    #[inline]
+    pub fn is_valid(&self, graph: &GraphInnerMem) -> bool {
+        self.inner.is_valid(graph)
+    }
+
+    #[inline]
+    pub fn was_written(&self, graph: &GraphInnerMem) -> bool {
+        self.inner.was_written(graph)
+    }
+
+    #[inline]
+    pub fn set(&mut self, value: T, graph: &GraphInnerMem) {
+        self.set_from(Some(value), graph)
+    }
+
+    #[inline]
+    pub fn set_from(&mut self, value: Option<T>, graph: &GraphInnerMem) {
+        if let Some(value) = value {
+            unsafe { *self.inner.value_ptr(graph) = value };
+            mark_slice(
+                self.inner.which,
+                VALID_MASK | WRITTEN_MASK,
+                &graph.mark_bitmask,
+            );
+        } else {
+            self.mark_invalid(graph)
+        }
+    }
+
+    #[inline]
+    pub fn mark_invalid(&mut self, graph: &GraphInnerMem) {
+        if self.is_valid(graph) {
+            clear_slice(
+                self.inner.which,
+                VALID_MASK | WRITTEN_MASK,
+                &graph.mark_bitmask,
+            );
+        }
+    }
+
+    // Same as ConsumerOutput::invalidate_and_notify
+    #[inline]
+    pub fn invalidate_and_notify(&mut self, graph: &GraphInnerMem) {
+        if self.is_valid(graph) {
+            clear_slice(self.inner.which, VALID_MASK, &graph.mark_bitmask);
+            mark_slice(self.inner.which, WRITTEN_MASK, &graph.mark_bitmask);
+        }
+    }
+}
+
+impl<T: OutputValue> TypedWatcher<T> {
+    #[inline]
+    pub fn get(&self) -> Option<T> {
+        self.inner.get(&*self.graph)
+    }
+
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.inner.is_valid(&*self.graph)
+    }
+
+    #[inline]
+    pub fn was_written(&self) -> bool {
+        self.inner.was_written(&*self.graph)
+    }
 }
 
 impl ConsumerOutput {
@@ -193,6 +353,16 @@ impl ConsumerOutput {
             );
         }
     }
+
+    // Invalidates the output and marks it written, so consumers which only look at
+    // changed inputs, like incremental aggregates, see it go invalid
+    #[inline]
+    pub fn invalidate_and_notify(&mut self, graph: &GraphInnerMem) {
+        if self.is_valid(graph) {
+            clear_slice(self.inner.which, VALID_MASK, &graph.mark_bitmask);
+            mark_slice(self.inner.which, WRITTEN_MASK, &graph.mark_bitmask);
+        }
+    }
 }
 
 impl ConsumerWatcher {
@@ -213,8 +383,8 @@ impl ConsumerWatcher {
 }
 
 pub struct AggregateInputGenerator {
-    pub(crate) mapping: Vec<u16>,
-    pub(crate) offsets: std::ops::Range<u16>,
+    pub(crate) mapping: Vec<GraphIndex>,
+    pub(crate) offsets: std::ops::Range<GraphIndex>,
 }
 
 impl AggregateInputGenerator {
@@ -236,42 +406,165 @@ impl AggregateInputGenerator {
 
 impl AggregateInput {
     #[inline]
-    pub fn iter_changed<'a>(&self, graph: &'a GraphInnerMem) -> AggregateInputIter<'a> {
-        // it's faster to create an aggregate mask as opposed to branching on each offset
-        let mut mask: u64 = 0;
-        let written = &graph.mark_bitmask[..];
-        for index in self.offsets.start..self.offsets.end {
-            let bit = get_bit(index, WRITTEN_MASK, written) as u64;
-            mask |= (bit << index);
-        }
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    #[inline]
+    fn mapping<'a>(&self, graph: &'a GraphInnerMem) -> &'a [GraphIndex] {
         let usize_range = (self.offsets.start as usize)..(self.offsets.end as usize);
+        debug_assert!(usize_range.end <= graph.aggregate_mapping_array.len());
+        unsafe { graph.aggregate_mapping_array.get_unchecked(usize_range) }
+    }
+
+    #[inline]
+    pub fn iter_changed<'a>(&self, graph: &'a GraphInnerMem) -> AggregateInputIter<'a> {
+        let index_mapping = self.mapping(graph);
         AggregateInputIter {
-            updated_mask: mask,
+            changed: ChangedMask::of(index_mapping, graph),
             graph,
-            index_mapping: &graph.aggregate_mapping_array[usize_range],
+            index_mapping,
         }
     }
+
+    // Walks several aggregates of the same length in lockstep, visiting every index
+    // where a member of any of them was written. Values of all aggregates are
+    // returned at each index, in the order given
+    #[inline]
+    pub fn zip_changed<'a>(
+        aggregates: &[&AggregateInput],
+        graph: &'a GraphInnerMem,
+    ) -> AggregateZipIter<'a> {
+        let mut mappings = SmallVec::new();
+        let mut changed = ChangedMask::new(aggregates.first().map_or(0, |a| a.len()));
+        for aggregate in aggregates {
+            let mapping = aggregate.mapping(graph);
+            assert_eq!(
+                mapping.len(),
+                aggregates[0].len(),
+                "Zipped aggregates must all have the same length"
+            );
+            changed.add(mapping, graph);
+            mappings.push(mapping);
+        }
+        AggregateZipIter {
+            changed,
+            graph,
+            mappings,
+        }
+    }
+}
+
+impl AggregateInput {
+    #[inline]
+    fn consumers<'a>(&self, graph: &'a GraphInnerMem) -> impl Iterator<Item = ConsumerInput> + 'a {
+        self.mapping(graph)
+            .iter()
+            .map(|which| ConsumerInput { which: *which })
+    }
+
+    // Every member's value in order, whether written or not
+    #[inline]
+    pub fn values<'a>(&self, graph: &'a GraphInnerMem) -> impl Iterator<Item = Option<f64>> + 'a {
+        self.consumers(graph).map(move |input| input.get(graph))
+    }
+
+    #[inline]
+    pub fn all_valid(&self, graph: &GraphInnerMem) -> bool {
+        self.consumers(graph).all(|input| input.is_valid(graph))
+    }
+
+    #[inline]
+    pub fn any_written(&self, graph: &GraphInnerMem) -> bool {
+        self.consumers(graph).any(|input| input.was_written(graph))
+    }
 }
 
 impl<'a> Iterator for AggregateInputIter<'a> {
     type Item = (usize, Option<f64>);
     #[inline]
     fn next(&mut self) -> Option<(usize, Option<f64>)> {
-        if self.updated_mask == 0 {
-            None
-        } else {
-            let first_set = self.updated_mask.trailing_zeros() as usize;
-            // Resets the first set bit
-            self.updated_mask &= self.updated_mask - 1;
+        self.changed.next_set().map(|first_set| {
             debug_assert!(first_set < self.index_mapping.len());
-            let real_index = unsafe { *self.index_mapping.get_unchecked(first_set) } as usize;
-            debug_assert!(real_index < self.graph.output_values.len());
-            let cons = ConsumerInput {
-                which: real_index as u16,
-            };
-            Some((first_set, cons.get(self.graph)))
+            let which = unsafe { *self.index_mapping.get_unchecked(first_set) };
+            debug_assert!((which as usize) < self.graph.output_values.len());
+            (first_set, ConsumerInput { which }.get(self.graph))
+        })
+    }
+}
+
+impl<'a> Iterator for AggregateZipIter<'a> {
+    type Item = (usize, ZippedValues);
+    #[inline]
+    fn next(&mut self) -> Option<(usize, ZippedValues)> {
+        let graph = self.graph;
+        let mappings = &self.mappings;
+        self.changed.next_set().map(|first_set| {
+            let values = mappings
+                .iter()
+                .map(|mapping| {
+                    debug_assert!(first_set < mapping.len());
+                    let which = unsafe { *mapping.get_unchecked(first_set) };
+                    ConsumerInput { which }.get(graph)
+                })
+                .collect();
+            (first_set, values)
+        })
+    }
+}
+
+impl ChangedMask {
+    const WORD_BITS: usize = 64;
+
+    #[inline]
+    fn new(len: usize) -> ChangedMask {
+        ChangedMask {
+            words: smallvec::smallvec![0; (len + Self::WORD_BITS - 1) / Self::WORD_BITS],
+            word: 0,
+        }
+    }
+
+    #[inline]
+    fn of(mapping: &[GraphIndex], graph: &GraphInnerMem) -> ChangedMask {
+        let mut mask = ChangedMask::new(mapping.len());
+        mask.add(mapping, graph);
+        mask
+    }
+
+    // Sets the bits of every written member of the mapping
+    #[inline]
+    fn add(&mut self, mapping: &[GraphIndex], graph: &GraphInnerMem) {
+        debug_assert!(mapping.len() <= self.words.len() * Self::WORD_BITS);
+        for (word, chunk) in self.words.iter_mut().zip(mapping.chunks(Self::WORD_BITS)) {
+            // it's faster to create an aggregate mask as opposed to branching on each offset
+            let mut mask: u64 = 0;
+            for (index, which) in chunk.iter().enumerate() {
+                let bit = get_bit(*which, WRITTEN_MASK, &graph.mark_bitmask) as u64;
+                mask |= bit << index;
+            }
+            *word |= mask;
         }
     }
+
+    // Returns and clears the lowest set bit
+    #[inline]
+    fn next_set(&mut self) -> Option<usize> {
+        while let Some(bits) = self.words.get_mut(self.word) {
+            if *bits != 0 {
+                let first_set = bits.trailing_zeros() as usize;
+                // Resets the first set bit
+                *bits &= *bits - 1;
+                return Some(self.word * Self::WORD_BITS + first_set);
+            }
+            self.word += 1;
+        }
+        None
+    }
 }
 
 mod private {
@@ -347,3 +640,6 @@ impl InputType for ConsumerInput {}
 
 impl private::Seal for AggregateInputGenerator {}
 impl InputType for AggregateInputGenerator {}
+
+impl private::Seal for TypedHook {}
+impl InputType for TypedHook {}
@@ -1,7 +1,19 @@
+pub mod aggregate_ops;
+pub mod bitmask;
+pub mod checkpoint;
 pub mod graph;
+pub mod graph_description;
 pub mod graph_error;
+pub mod graph_index;
+pub mod graph_patch;
 pub mod graph_registrar;
+pub mod graph_runner;
 pub(crate) mod graph_sort;
+pub mod graph_spec;
 pub mod interface_types;
+pub mod model;
+pub mod params;
+pub mod profile;
+pub mod published_outputs;
 pub mod security_data;
 pub mod security_index;
@@ -1 +1,523 @@
-mm
+use crossbeam_channel::{bounded, Receiver, TryRecvError};
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// Linear models over graph values, with coefficients fit offline and loaded from a file.
+// Ridge and plain least squares fits produce the same kind of file, which looks like
+// {"intercept": 0.1, "features": [{"name": "imbalance", "weight": 2.0, "mean": 0.0,
+//  "scale": 0.5, "clip": 3.0}]}
+// Each feature is standardised by its mean and scale and clipped to within clip of zero
+// before being weighted. Missing features sit at their mean, so contribute nothing
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Feature {
+    // Only for the reader, features are matched up by position
+    #[serde(default)]
+    pub name: Option<String>,
+    pub weight: f64,
+    #[serde(default)]
+    pub mean: f64,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub clip: Option<f64>,
+}
+
+impl Feature {
+    #[inline]
+    fn contribution(&self, value: f64) -> f64 {
+        let standard = (value - self.mean) / self.scale;
+        let standard = match self.clip {
+            Some(clip) => standard.max(-clip).min(clip),
+            None => standard,
+        };
+        self.weight * standard
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LinearModel {
+    pub intercept: f64,
+    pub features: Vec<Feature>,
+}
+
+impl LinearModel {
+    pub fn parse(json: &str) -> Result<LinearModel, anyhow::Error> {
+        let model: LinearModel = serde_json::from_str(json)?;
+        if !model.intercept.is_finite() {
+            anyhow::bail!("Intercept must be finite");
+        }
+        for (index, feature) in model.features.iter().enumerate() {
+            let valid = feature.weight.is_finite()
+                && feature.mean.is_finite()
+                && feature.scale.is_finite()
+                && feature.scale > 0.0
+                && feature.clip.map_or(true, |clip| clip > 0.0);
+            if !valid {
+                anyhow::bail!(
+                    "Feature {} needs a finite weight and mean, and a positive scale and clip",
+                    feature.name.as_deref().unwrap_or(&index.to_string())
+                );
+            }
+        }
+        Ok(model)
+    }
+
+    pub fn load(path: &str) -> Result<LinearModel, anyhow::Error> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Couldn't read model file {}: {}", path, e))?;
+        LinearModel::parse(&json).map_err(|e| anyhow::anyhow!("Bad model file {}: {}", path, e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    // None with fewer than min_valid of the values valid
+    pub fn predict(
+        &self,
+        values: impl Iterator<Item = Option<f64>>,
+        min_valid: usize,
+    ) -> Option<f64> {
+        let mut valid = 0;
+        let mut prediction = self.intercept;
+        for (feature, value) in self.features.iter().zip(values) {
+            if let Some(value) = value {
+                valid += 1;
+                prediction += feature.contribution(value);
+            }
+        }
+        Some(prediction).filter(|_| valid >= min_valid)
+    }
+}
+
+// What a file looked like when it was last read, to spot it changing
+fn file_version(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// Reloads a model file on its own thread whenever it changes, so the graph thread
+// never touches the filesystem. Models which fail to load or to pass check are
+// reported there and never handed out. The thread stops once the watcher is dropped
+pub struct ModelWatcher {
+    models: Receiver<LinearModel>,
+    // The thread only holds a weak reference, so it can tell without waiting for a change
+    _alive: Arc<()>,
+}
+
+impl ModelWatcher {
+    pub fn spawn<F>(path: String, every: Duration, check: F) -> ModelWatcher
+    where
+        F: Fn(&LinearModel) -> Result<(), anyhow::Error> + Send + 'static,
+    {
+        // Holds one reload at a time, and the thread waits for the graph to take it
+        // before looking at the file again
+        let (sender, models) = bounded(1);
+        let mut version = file_version(&path);
+        let alive = Arc::new(());
+        let watched = Arc::downgrade(&alive);
+        std::thread::Builder::new()
+            .name("model watcher".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(every);
+                if watched.upgrade().is_none() {
+                    return;
+                }
+                let current = file_version(&path);
+                if current.is_none() || current == version {
+                    continue;
+                }
+                version = current;
+                let model = match LinearModel::load(&path).and_then(|model| {
+                    check(&model)?;
+                    Ok(model)
+                }) {
+                    Ok(model) => model,
+                    Err(err) => {
+                        println!("Couldn't reload {}: {}", path, err);
+                        continue;
+                    }
+                };
+                if sender.send(model).is_err() {
+                    return;
+                }
+            })
+            .expect("Couldn't start model watcher");
+        ModelWatcher {
+            models,
+            _alive: alive,
+        }
+    }
+
+    // The latest reload since the last call, if there was one
+    pub fn poll(&self) -> Option<LinearModel> {
+        match self.models.try_recv() {
+            Ok(model) => Some(model),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+}
+
+// Running means and co-moments of features and a target, which is everything an ordinary
+// or ridge least squares fit needs, so samples don't have to be kept around.
+// Updated one sample at a time with Welford's method to stay accurate over long runs
+#[derive(Debug, Clone)]
+pub struct LeastSquares {
+    samples: u64,
+    // Features, then the target last
+    means: Vec<f64>,
+    // Sums of products of differences from the mean, row major
+    comoments: Vec<f64>,
+    delta: Vec<f64>,
+}
+
+// A fitted model, with how much to trust it
+#[derive(Serialize, Debug, Clone)]
+pub struct Fit {
+    #[serde(flatten)]
+    pub model: LinearModel,
+    // For each feature's weight, in the same order
+    pub t_stats: Vec<f64>,
+    pub r_squared: f64,
+    pub samples: u64,
+}
+
+// Solves a x = b for symmetric positive definite a, also returning the inverse of a.
+// Gauss-Jordan with partial pivoting, which is plenty for a few dozen features
+fn solve(mut a: Vec<f64>, b: &[f64]) -> Option<(Vec<f64>, Vec<f64>)> {
+    let n = b.len();
+    let mut inverse: Vec<f64> = (0..n * n)
+        .map(|i| if i / n == i % n { 1.0 } else { 0.0 })
+        .collect();
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|x, y| a[x * n + column].abs().total_cmp(&a[y * n + column].abs()))
+            .unwrap();
+        if a[pivot * n + column].abs() < 1e-12 {
+            return None;
+        }
+        for k in 0..n {
+            a.swap(column * n + k, pivot * n + k);
+            inverse.swap(column * n + k, pivot * n + k);
+        }
+        let scale = a[column * n + column];
+        for k in 0..n {
+            a[column * n + k] /= scale;
+            inverse[column * n + k] /= scale;
+        }
+        for row in (0..n).filter(|row| *row != column) {
+            let factor = a[row * n + column];
+            for k in 0..n {
+                a[row * n + k] -= factor * a[column * n + k];
+                inverse[row * n + k] -= factor * inverse[column * n + k];
+            }
+        }
+    }
+    let x = (0..n)
+        .map(|row| (0..n).map(|k| inverse[row * n + k] * b[k]).sum())
+        .collect();
+    Some((x, inverse))
+}
+
+impl LeastSquares {
+    pub fn new(features: usize) -> LeastSquares {
+        let width = features + 1;
+        LeastSquares {
+            samples: 0,
+            means: vec![0.0; width],
+            comoments: vec![0.0; width * width],
+            delta: vec![0.0; width],
+        }
+    }
+
+    pub fn features(&self) -> usize {
+        self.means.len() - 1
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // Samples with a value that isn't finite would poison every sum, so they're
+    // dropped and false is returned
+    pub fn add(&mut self, features: &[f64], target: f64) -> bool {
+        assert_eq!(features.len(), self.features());
+        if !features.iter().all(|value| value.is_finite()) || !target.is_finite() {
+            return false;
+        }
+        self.samples += 1;
+        let count = self.samples as f64;
+        let values = features.iter().chain(std::iter::once(&target));
+        for ((mean, delta), value) in self.means.iter_mut().zip(self.delta.iter_mut()).zip(values) {
+            *delta = value - *mean;
+            *mean += *delta / count;
+        }
+        // The old difference times the new one, the same trick as for a single variance
+        let width = self.means.len();
+        let values = features.iter().chain(std::iter::once(&target));
+        for (row, value) in values.enumerate() {
+            let after = value - self.means[row];
+            for column in 0..width {
+                self.comoments[row * width + column] += self.delta[column] * after;
+            }
+        }
+        true
+    }
+
+    // Fits standardised features, so the result loads straight into a LinearModel and
+    // ridge penalises every feature alike. ridge is the penalty per sample
+    pub fn fit(&self, names: &[String], ridge: f64) -> Result<Fit, anyhow::Error> {
+        let features = self.features();
+        assert_eq!(names.len(), features);
+        if self.samples <= features as u64 + 1 {
+            anyhow::bail!(
+                "{} samples isn't enough to fit {} features",
+                self.samples,
+                features
+            );
+        }
+        let width = features + 1;
+        let count = self.samples as f64;
+        let comoment = |row: usize, column: usize| self.comoments[row * width + column];
+        let scales: Vec<f64> = (0..features)
+            .map(|feature| (comoment(feature, feature) / count).sqrt())
+            .collect();
+        if let Some(flat) = scales.iter().position(|scale| !(*scale > 0.0)) {
+            anyhow::bail!("Feature {} never changed", names[flat]);
+        }
+        let total = comoment(features, features);
+        if !(total > 0.0) {
+            anyhow::bail!("The target never changed");
+        }
+
+        // Products of the standardised features with each other and the target
+        let gram: Vec<f64> = (0..features * features)
+            .map(|i| {
+                let (row, column) = (i / features, i % features);
+                comoment(row, column) / (scales[row] * scales[column])
+            })
+            .collect();
+        let cross: Vec<f64> = (0..features)
+            .map(|row| comoment(row, features) / scales[row])
+            .collect();
+        let mut penalised = gram.clone();
+        for feature in 0..features {
+            penalised[feature * features + feature] += ridge * count;
+        }
+        let (weights, inverse) = solve(penalised, &cross)
+            .ok_or_else(|| anyhow::anyhow!("Features are collinear, try some ridge"))?;
+
+        let product = |left: &[f64], matrix: &[f64], right: &[f64]| -> f64 {
+            (0..features * features)
+                .map(|i| left[i / features] * matrix[i] * right[i % features])
+                .sum()
+        };
+        let explained: f64 = weights.iter().zip(cross.iter()).map(|(w, c)| w * c).sum();
+        let residual = (total - 2.0 * explained + product(&weights, &gram, &weights)).max(0.0);
+        let variance = residual / (count - features as f64 - 1.0);
+        // Sandwich form, which is the usual variance for plain least squares
+        let t_stats = (0..features)
+            .map(|feature| {
+                let row = &inverse[feature * features..(feature + 1) * features];
+                weights[feature] / (variance * product(row, &gram, row)).sqrt()
+            })
+            .collect();
+
+        Ok(Fit {
+            model: LinearModel {
+                intercept: self.means[features],
+                features: (0..features)
+                    .map(|feature| Feature {
+                        name: Some(names[feature].clone()),
+                        weight: weights[feature],
+                        mean: self.means[feature],
+                        scale: scales[feature],
+                        clip: None,
+                    })
+                    .collect(),
+            },
+            t_stats,
+            r_squared: 1.0 - residual / total,
+            samples: self.samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> LinearModel {
+        LinearModel::parse(
+            r#"{"intercept": 1.0, "features": [
+                {"name": "a", "weight": 2.0, "mean": 10.0, "scale": 5.0, "clip": 1.0},
+                {"weight": -1.0}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn predicts() {
+        let model = model();
+        assert_eq!(model.len(), 2);
+        // (15 - 10) / 5 = 1, and 1 - 2 * 1 = -1
+        assert_eq!(
+            model.predict(vec![Some(15.0), Some(4.0)].into_iter(), 2),
+            Some(-1.0)
+        );
+        // Clipped to one standard unit
+        assert_eq!(
+            model.predict(vec![Some(100.0), Some(0.0)].into_iter(), 2),
+            Some(3.0)
+        );
+        // Missing features count as their mean
+        assert_eq!(
+            model.predict(vec![None, Some(4.0)].into_iter(), 1),
+            Some(-3.0)
+        );
+        assert_eq!(model.predict(vec![None, Some(4.0)].into_iter(), 2), None);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(LinearModel::parse(
+            r#"{"intercept": 0.0, "features": [{"weight": 1.0, "scale": 0.0}]}"#
+        )
+        .is_err());
+        assert!(LinearModel::parse(
+            r#"{"intercept": 0.0, "features": [{"weight": 1.0, "clip": -1.0}]}"#
+        )
+        .is_err());
+        assert!(LinearModel::parse(r#"{"features": []}"#).is_err());
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let path = std::env::temp_dir().join(format!("model_watcher_{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, r#"{"intercept": 0.0, "features": []}"#).unwrap();
+        let watcher = ModelWatcher::spawn(path.clone(), Duration::from_millis(5), |model| {
+            if model.is_empty() {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("Expected no features"))
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(watcher.poll().is_none());
+
+        let reload = |json: &str| {
+            std::fs::write(&path, json).unwrap();
+            for _ in 0..200 {
+                if let Some(model) = watcher.poll() {
+                    return model;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            panic!("Model was never reloaded");
+        };
+        assert_eq!(
+            reload(r#"{"intercept": 1.25, "features": []}"#).intercept,
+            1.25
+        );
+
+        // Files which don't parse or fail the check are skipped
+        std::fs::write(&path, r#"{"intercept": 2.0"#).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        std::fs::write(
+            &path,
+            r#"{"intercept": 2.0, "features": [{"weight": 1.0}]}"#,
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(watcher.poll().is_none());
+        assert_eq!(
+            reload(r#"{"intercept": 3.5, "features": []}"#).intercept,
+            3.5
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Deterministic noise in [-0.5, 0.5)
+    fn noise(state: &mut u64) -> f64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    }
+
+    #[test]
+    fn fits_least_squares() {
+        let names = vec!["x".to_string(), "y".to_string()];
+        let mut state = 7;
+        let mut stats = LeastSquares::new(2);
+        let mut samples = Vec::new();
+        for _ in 0..2000 {
+            let x = 100.0 + noise(&mut state) * 10.0;
+            let y = noise(&mut state);
+            let target = 2.0 + 3.0 * x - y + noise(&mut state) * 0.1;
+            stats.add(&[x, y], target);
+            samples.push((x, y, target));
+        }
+        let fit = stats.fit(&names, 0.0).unwrap();
+        assert_eq!(fit.samples, 2000);
+        assert!(fit.r_squared > 0.99);
+
+        // The fitted model reproduces the coefficients it was generated from
+        let model = &fit.model;
+        let x_weight = model.features[0].weight / model.features[0].scale;
+        let y_weight = model.features[1].weight / model.features[1].scale;
+        assert!((x_weight - 3.0).abs() < 0.01);
+        assert!((y_weight + 1.0).abs() < 0.01);
+        let (x, y, target) = samples[0];
+        let predicted = model
+            .predict(vec![Some(x), Some(y)].into_iter(), 2)
+            .unwrap();
+        assert!((predicted - target).abs() < 0.1);
+        assert!(fit.t_stats[0] > 100.0 && fit.t_stats[1] < -100.0);
+
+        // Ridge shrinks every weight towards zero
+        let ridged = stats.fit(&names, 1.0).unwrap();
+        for (ridged, plain) in ridged.model.features.iter().zip(model.features.iter()) {
+            assert!(ridged.weight.abs() < plain.weight.abs());
+        }
+        assert!(ridged.r_squared < fit.r_squared);
+    }
+
+    #[test]
+    fn rejects_unfittable() {
+        let names = vec!["x".to_string(), "y".to_string()];
+        let mut stats = LeastSquares::new(2);
+        stats.add(&[1.0, 1.0], 1.0);
+        assert!(stats.fit(&names, 0.0).is_err());
+        for step in 0..10 {
+            stats.add(&[step as f64, 1.0], step as f64);
+        }
+        assert!(stats.fit(&names, 0.0).is_err());
+
+        // Non-finite samples are left out
+        assert!(!stats.add(&[f64::NAN, 1.0], 1.0));
+        assert!(!stats.add(&[1.0, 1.0], f64::INFINITY));
+        assert_eq!(stats.samples(), 11);
+
+        // Two copies of the same feature only fit with ridge
+        let mut stats = LeastSquares::new(2);
+        for step in 0..10 {
+            stats.add(&[step as f64, step as f64], (step % 3) as f64);
+        }
+        assert!(stats.fit(&names, 0.0).is_err());
+        assert!(stats.fit(&names, 0.1).is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+// Parameters of a signal, usually from #[derive(SignalParams)].
+// parse is used both when a signal is created and for live updates
+pub trait SignalParams: Sized {
+    fn parse(json: &str) -> Result<Self, anyhow::Error>;
+    fn schema() -> ParamSchema;
+}
+
+// Machine readable description of the parameters a signal takes
+#[derive(Serialize, Debug, Clone)]
+pub struct ParamSchema {
+    pub fields: Vec<ParamField>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ParamField {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    // None if the parameter is required
+    pub default: Option<serde_json::Value>,
+    // The expression a valid value must satisfy, as written in the signal
+    pub constraint: Option<&'static str>,
+    pub description: Option<&'static str>,
+}
@@ -0,0 +1,166 @@
+use super::security_index::Security;
+
+use serde::Serialize;
+
+use std::time::Duration;
+
+// Bucket i holds latencies in [2^(i-1), 2^i) nanoseconds, with bucket 0 holding zero.
+// 40 buckets is enough for anything under about 9 minutes
+const NUM_BUCKETS: usize = 40;
+
+// Power of two latency histogram, cheap enough to record on every call
+#[derive(Clone, Serialize, Debug)]
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total_nanos: u64,
+    max_nanos: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; NUM_BUCKETS],
+            count: 0,
+            total_nanos: 0,
+            max_nanos: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    #[inline]
+    pub fn record(&mut self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos().min(std::u64::MAX as u128) as u64;
+        let bucket = (64 - nanos.leading_zeros() as usize).min(NUM_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_nanos = self.total_nanos.saturating_add(nanos);
+        self.max_nanos = self.max_nanos.max(nanos);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max_nanos(&self) -> u64 {
+        self.max_nanos
+    }
+
+    pub fn mean_nanos(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_nanos as f64 / self.count as f64
+        }
+    }
+
+    // Upper bound of the bucket containing the given quantile, so this overestimates
+    // by at most a factor of two
+    pub fn quantile_nanos(&self, quantile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let target = ((self.count as f64) * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return if bucket == 0 {
+                    0
+                } else {
+                    (1u64 << bucket).min(self.max_nanos)
+                };
+            }
+        }
+        self.max_nanos
+    }
+}
+
+// Recorded alongside a GraphCallList, in the same order as its calls
+#[cfg(feature = "profile")]
+#[derive(Default)]
+pub(crate) struct CallListProfile {
+    pub(crate) trigger: LatencyHistogram,
+    pub(crate) calls: Vec<LatencyHistogram>,
+    // index into the calls of the signal owning each cleanup
+    pub(crate) cleanup: Vec<(usize, LatencyHistogram)>,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct SignalProfile {
+    pub name: String,
+    pub call: LatencyHistogram,
+    pub cleanup: Option<LatencyHistogram>,
+}
+
+// Timings for everything run when one security's book updates.
+// trigger covers all signal calls and cleanups, but not the user callback
+#[derive(Clone, Serialize, Debug)]
+pub struct SecurityProfile {
+    pub security: Security,
+    pub trigger: LatencyHistogram,
+    pub signals: Vec<SignalProfile>,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct GraphProfile {
+    pub securities: Vec<SecurityProfile>,
+}
+
+impl GraphProfile {
+    pub fn to_html(&self) -> String {
+        fn row(name: &str, hist: &LatencyHistogram) -> String {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.0}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                name,
+                hist.count(),
+                hist.mean_nanos(),
+                hist.quantile_nanos(0.5),
+                hist.quantile_nanos(0.99),
+                hist.max_nanos()
+            )
+        }
+        let mut html =
+            String::from("<h4 id=\"Profile\" class=\"title\">Signal latency (ns)</h4>\n");
+        for security in &self.securities {
+            html.push_str(&format!(
+                "<h5>{} {}</h5>\n<table>\n\
+                 <tr><th>call</th><th>count</th><th>mean</th><th>p50</th><th>p99</th><th>max</th></tr>\n",
+                security.security.exchange, security.security.product
+            ));
+            html.push_str(&row("total", &security.trigger));
+            for signal in &security.signals {
+                html.push_str(&row(&signal.name, &signal.call));
+                if let Some(cleanup) = &signal.cleanup {
+                    html.push_str(&row(&format!("{} (cleanup)", signal.name), cleanup));
+                }
+            }
+            html.push_str("</table>\n");
+        }
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_quantiles() {
+        let mut hist = LatencyHistogram::default();
+        assert_eq!(hist.quantile_nanos(0.5), 0);
+        for nanos in 1..=100 {
+            hist.record(Duration::from_nanos(nanos));
+        }
+        hist.record(Duration::from_nanos(10_000));
+        assert_eq!(hist.count(), 101);
+        assert_eq!(hist.max_nanos(), 10_000);
+        // 50 lands in [32, 64)
+        assert_eq!(hist.quantile_nanos(0.5), 64);
+        // 100 lands in [64, 128)
+        assert_eq!(hist.quantile_nanos(0.99), 128);
+        assert_eq!(hist.quantile_nanos(1.0), 10_000);
+        assert!((hist.mean_nanos() - 15050.0 / 101.0).abs() < 1e-9);
+    }
+}
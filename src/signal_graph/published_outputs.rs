@@ -0,0 +1,233 @@
+use super::graph::Graph;
+use super::graph_index::GraphIndex;
+use super::interface_types::ConsumerInput;
+
+use std::collections::HashMap;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Position of an output in a PublishedOutputs. Outputs are ordered by signal and output
+// name, so graphs built from the same layout give each output the same id
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputId(usize);
+
+impl OutputId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+// The f64 outputs of a graph and their valid flags, published by the graph thread and
+// readable from any other. Publishes are guarded by a sequence lock, so the graph thread
+// never waits on readers, and readers retry if a publish landed while they were copying.
+// Every read sees exactly one publish
+pub struct PublishedOutputs {
+    keys: Vec<(String, String)>,
+    key_to_id: HashMap<(String, String), OutputId>,
+    // Odd while a publish is in progress, otherwise twice the number of publishes
+    sequence: AtomicU64,
+    values: Vec<AtomicU64>,
+    valid: Vec<AtomicU64>,
+}
+
+// A reader's copy of the outputs. Reusing one across reads means reading never allocates
+pub struct OutputSnapshot {
+    values: Vec<f64>,
+    valid: Vec<u64>,
+    version: u64,
+}
+
+impl OutputSnapshot {
+    #[inline]
+    pub fn get(&self, id: OutputId) -> Option<f64> {
+        let index = id.index();
+        if self.valid[index / 64] & (1 << (index % 64)) != 0 {
+            Some(self.values[index])
+        } else {
+            None
+        }
+    }
+
+    // The number of publishes before this was read
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl PublishedOutputs {
+    fn new(keys: Vec<(String, String)>) -> PublishedOutputs {
+        PublishedOutputs {
+            key_to_id: keys
+                .iter()
+                .enumerate()
+                .map(|(index, key)| (key.clone(), OutputId(index)))
+                .collect(),
+            sequence: AtomicU64::new(0),
+            values: keys.iter().map(|_| AtomicU64::new(0)).collect(),
+            valid: (0..(keys.len() + 63) / 64)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            keys,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = OutputId> {
+        (0..self.len()).map(OutputId)
+    }
+
+    // The signal and output name of an output
+    pub fn key(&self, id: OutputId) -> &(String, String) {
+        &self.keys[id.index()]
+    }
+
+    pub fn find(&self, signal: &str, output: &str) -> Option<OutputId> {
+        self.key_to_id
+            .get(&(signal.to_string(), output.to_string()))
+            .copied()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.sequence.load(Ordering::Acquire) / 2
+    }
+
+    // Runs copy until it completes without a publish landing in the middle
+    #[inline]
+    fn read<T>(&self, mut copy: impl FnMut() -> T) -> (T, u64) {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let copied = copy();
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == before {
+                return (copied, before / 2);
+            }
+        }
+    }
+
+    // A single output, consistent with its own valid flag
+    pub fn get(&self, id: OutputId) -> Option<f64> {
+        let index = id.index();
+        let ((bits, valid), _) = self.read(|| {
+            (
+                self.values[index].load(Ordering::Relaxed),
+                self.valid[index / 64].load(Ordering::Relaxed),
+            )
+        });
+        if valid & (1 << (index % 64)) != 0 {
+            Some(f64::from_bits(bits))
+        } else {
+            None
+        }
+    }
+
+    pub fn snapshot(&self) -> OutputSnapshot {
+        let mut snapshot = OutputSnapshot {
+            values: vec![0.0; self.values.len()],
+            valid: vec![0; self.valid.len()],
+            version: 0,
+        };
+        self.read_into(&mut snapshot);
+        snapshot
+    }
+
+    // Copies every output into the snapshot, which must come from these outputs
+    pub fn read_into(&self, snapshot: &mut OutputSnapshot) {
+        assert_eq!(snapshot.values.len(), self.values.len());
+        let ((), version) = self.read(|| {
+            for (value, published) in snapshot.values.iter_mut().zip(self.values.iter()) {
+                *value = f64::from_bits(published.load(Ordering::Relaxed));
+            }
+            for (valid, published) in snapshot.valid.iter_mut().zip(self.valid.iter()) {
+                *valid = published.load(Ordering::Relaxed);
+            }
+        });
+        snapshot.version = version;
+    }
+
+    pub fn watcher(self: &Arc<Self>, signal: &str, output: &str) -> Option<OutputWatcher> {
+        self.find(signal, output).map(|id| OutputWatcher {
+            outputs: self.clone(),
+            id,
+        })
+    }
+}
+
+// Counterpart of ConsumerWatcher that can be handed to other threads
+#[derive(Clone)]
+pub struct OutputWatcher {
+    outputs: Arc<PublishedOutputs>,
+    id: OutputId,
+}
+
+impl OutputWatcher {
+    pub fn get(&self) -> Option<f64> {
+        self.outputs.get(self.id)
+    }
+}
+
+// The writing side of a PublishedOutputs, which stays with the graph
+pub struct OutputPublisher {
+    sources: Vec<ConsumerInput>,
+    outputs: Arc<PublishedOutputs>,
+}
+
+impl OutputPublisher {
+    pub fn new(graph: &Graph) -> OutputPublisher {
+        let mut indices: Vec<_> = graph
+            .mem
+            .signal_output_to_index
+            .iter()
+            .filter(|(_, index)| !graph.mem.typed_outputs.contains_key(index))
+            .map(|(key, index)| (key.clone(), *index))
+            .collect();
+        indices.sort();
+        let (keys, sources): (Vec<_>, Vec<GraphIndex>) = indices.into_iter().unzip();
+        let mut publisher = OutputPublisher {
+            sources: sources
+                .into_iter()
+                .map(|which| ConsumerInput { which })
+                .collect(),
+            outputs: Arc::new(PublishedOutputs::new(keys)),
+        };
+        publisher.publish(graph);
+        publisher
+    }
+
+    pub fn outputs(&self) -> &Arc<PublishedOutputs> {
+        &self.outputs
+    }
+
+    // Taking self mutably keeps this the only writer
+    pub fn publish(&mut self, graph: &Graph) {
+        let outputs = &*self.outputs;
+        let sequence = outputs.sequence.load(Ordering::Relaxed);
+        outputs.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        for (block, valid) in outputs.valid.iter().enumerate() {
+            let mut word = 0;
+            let start = block * 64;
+            let end = (start + 64).min(self.sources.len());
+            for index in start..end {
+                if let Some(value) = self.sources[index].get(&graph.mem) {
+                    outputs.values[index].store(value.to_bits(), Ordering::Relaxed);
+                    word |= 1 << (index - start);
+                }
+            }
+            valid.store(word, Ordering::Relaxed);
+        }
+
+        outputs.sequence.store(sequence + 2, Ordering::Release);
+    }
+}
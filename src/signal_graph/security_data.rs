@@ -1,9 +1,10 @@
-use crate::signal_graph::security_index::{Security, SecurityIndex, SecurityMap};
+use crate::signal_graph::security_index::{MapId, Security, SecurityIndex, SecurityMap};
 
 // A specialized vector interface that holds exactly one element per
-// security. Using a security index here will always be valid
+// security. Using a security index from another map panics
 pub struct SecurityVector<T> {
     elems: Vec<T>,
+    map: MapId,
 }
 
 impl<T: Default> SecurityVector<T> {
@@ -22,6 +23,7 @@ impl<T> SecurityVector<T> {
     pub fn new_with<F: FnMut(&Security, SecurityIndex) -> T>(map: &SecurityMap, mut f: F) -> Self {
         Self {
             elems: map.iter().map(|(sec, ind)| f(sec, *ind)).collect(),
+            map: map.id(),
         }
     }
 
@@ -33,19 +35,20 @@ impl<T> SecurityVector<T> {
         for (sec, ind) in map.iter() {
             elems.push(f(sec, *ind)?);
         }
-        Ok(Self { elems })
+        Ok(Self {
+            elems,
+            map: map.id(),
+        })
     }
 
     pub fn get(&self, index: SecurityIndex) -> &T {
-        let index = index.get();
-        debug_assert!(index < self.elems.len());
-        unsafe { self.elems.get_unchecked(index) }
+        assert_eq!(index.map_id(), self.map, "Security index from another map");
+        &self.elems[index.get()]
     }
 
     pub fn get_mut(&mut self, index: SecurityIndex) -> &mut T {
-        let index = index.get();
-        debug_assert!(index < self.elems.len());
-        unsafe { self.elems.get_unchecked_mut(index) }
+        assert_eq!(index.map_id(), self.map, "Security index from another map");
+        &mut self.elems[index.get()]
     }
 
     pub fn for_each<F: FnMut(&T)>(&self, mut f: F) {
@@ -53,4 +56,29 @@ impl<T> SecurityVector<T> {
             f(val);
         }
     }
+
+    // Iterates in the same order as the SecurityMap this was built from
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elems.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.elems.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "another map")]
+    fn index_from_larger_map_panics() {
+        let btc = Security::new("BITMEX", "XBTUSD");
+        let eth = Security::new("BITMEX", "ETHUSD");
+        let small = SecurityMap::new(&[btc]);
+        let large = SecurityMap::new(&[Security::new("BITMEX", "XBTUSD"), eth.clone()]);
+        let data = SecurityVector::<u64>::new(&small);
+        data.get(large.to_index(&eth).unwrap());
+    }
 }
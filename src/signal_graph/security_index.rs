@@ -1,12 +1,15 @@
+use super::graph_index::{GraphIndex, MAX_GRAPH_INDEX};
+
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicU32, Ordering},
     Arc,
 };
 
 pub type SmallString = smallstr::SmallString<[u8; 16]>;
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Security {
     pub product: SmallString,
     pub exchange: SmallString,
@@ -21,12 +24,18 @@ impl Security {
     }
 }
 
+// Identifies the map that handed out an index, so indices from one map can't
+// silently be used against data built from another
+pub type MapId = u32;
+
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub struct SecurityIndex {
-    index: u16,
+    index: GraphIndex,
+    map: MapId,
 }
 
 pub struct SecurityMap {
+    id: MapId,
     securities: HashMap<Security, SecurityIndex>,
     iter_order_securities: Vec<(Security, SecurityIndex)>,
 }
@@ -36,20 +45,25 @@ impl SecurityIndex {
     pub fn get(&self) -> usize {
         self.index as usize
     }
+
+    #[inline]
+    pub fn map_id(&self) -> MapId {
+        self.map
+    }
 }
 
-static MADE_MAP: AtomicBool = AtomicBool::new(false);
+static NEXT_MAP_ID: AtomicU32 = AtomicU32::new(0);
 
-// It is imperative that only one security map is ever built,
-// since otherwise, a different security map could get initialized
-// and give invalid indices. This is the reason for the unsafety of
-// raw creation
+// Any number of maps can exist at once, for example for live, shadow and replay graphs
+// in the same process. Each map brands its indices with its own id, and everything
+// indexed by security checks the brand
 
 impl SecurityMap {
-    fn _new(securities: &[Security]) -> (SecurityMap, bool) {
-        if securities.len() > std::u16::MAX as usize {
-            panic!("Can't trade more than {} securities", std::u16::MAX);
+    pub fn new(securities: &[Security]) -> SecurityMap {
+        if securities.len() > MAX_GRAPH_INDEX {
+            panic!("Can't trade more than {} securities", MAX_GRAPH_INDEX);
         }
+        let id = NEXT_MAP_ID.fetch_add(1, Ordering::Relaxed);
         let iter_order_securities: Vec<_> = securities
             .iter()
             .enumerate()
@@ -57,33 +71,35 @@ impl SecurityMap {
                 (
                     sec.clone(),
                     SecurityIndex {
-                        index: index as u16,
+                        index: index as GraphIndex,
+                        map: id,
                     },
                 )
             })
             .collect();
         let map = SecurityMap {
+            id,
             securities: iter_order_securities.iter().cloned().collect(),
             iter_order_securities,
         };
         if map.securities.len() != map.iter_order_securities.len() {
             panic!("Duplicate securities have been passed");
         }
-        (map, MADE_MAP.swap(true, Ordering::SeqCst))
+        map
     }
 
-    // Unsafe since you can create multiple maps this way.
-    // In general only useful for testing
-    pub unsafe fn new_unchecked(securities: &[Security]) -> SecurityMap {
-        Self::_new(securities).0
+    pub fn create(securities: &[Security]) -> Arc<SecurityMap> {
+        Arc::new(Self::new(securities))
     }
 
-    pub fn create(securities: &[Security]) -> Arc<SecurityMap> {
-        let (map_box, has_built) = Self::_new(securities);
-        if has_built {
-            panic!("A second global map construction attempt happened")
-        }
-        Arc::new(map_box)
+    #[inline]
+    pub fn id(&self) -> MapId {
+        self.id
+    }
+
+    #[inline]
+    pub fn owns(&self, security: SecurityIndex) -> bool {
+        security.map == self.id
     }
 
     pub fn to_index(&self, security: &Security) -> Option<SecurityIndex> {
@@ -91,6 +107,12 @@ impl SecurityMap {
     }
 
     pub fn to_security(&self, security: SecurityIndex) -> &Security {
+        assert!(
+            self.owns(security),
+            "Security index from map {} used with map {}",
+            security.map,
+            self.id
+        );
         let sec = &self.iter_order_securities[security.index as usize].0;
         debug_assert_eq!(self.to_index(sec), Some(security));
         sec
@@ -106,4 +128,32 @@ impl SecurityMap {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_brand_their_indices() {
+        let btc = Security::new("BITMEX", "XBTUSD");
+        let eth = Security::new("BITMEX", "ETHUSD");
+        let live = SecurityMap::new(&[btc.clone(), eth.clone()]);
+        let replay = SecurityMap::new(&[eth.clone(), btc.clone()]);
+        assert_ne!(live.id(), replay.id());
+
+        let live_btc = live.to_index(&btc).unwrap();
+        let replay_btc = replay.to_index(&btc).unwrap();
+        assert_ne!(live_btc, replay_btc);
+        assert!(live.owns(live_btc));
+        assert!(!live.owns(replay_btc));
+        assert_eq!(live.to_security(live_btc), &btc);
+        assert_eq!(replay.to_security(replay_btc), &btc);
+    }
+
+    #[test]
+    #[should_panic(expected = "used with map")]
+    fn foreign_index_panics() {
+        let btc = Security::new("BITMEX", "XBTUSD");
+        let live = SecurityMap::new(&[btc.clone()]);
+        let replay = SecurityMap::new(&[btc.clone()]);
+        live.to_security(replay.to_index(&btc).unwrap());
+    }
+}
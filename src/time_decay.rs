@@ -0,0 +1,224 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use serde::{Deserialize, Serialize};
+
+// Exponentially decayed statistics whose horizon is set in time instead of messages,
+// so a venue sending twice as many updates doesn't halve the lookback.
+// Each value is weighted by how long it was held, the way a price is between updates,
+// so several updates at the same time only count the last one.
+// Times are graph trigger times, in microseconds
+
+pub const MICROS_PER_SECOND: f64 = 1_000_000.0;
+
+// Weight a value held for elapsed seconds takes from everything before it
+pub fn decay_weight(elapsed: f64, half_life: f64) -> f64 {
+    1.0 - (-elapsed * std::f64::consts::LN_2 / half_life).exp()
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct DecayedStats {
+    mean: Option<f64>,
+    variance: f64,
+    // The value since the last update, and when it was taken
+    held: Option<(f64, u64)>,
+    // Seconds of valid input seen so far
+    observed: f64,
+}
+
+impl DecayedStats {
+    // Accounts for the held value up to time, then holds value from then on.
+    // Time spent with no valid value doesn't count towards the decay
+    pub fn update(&mut self, value: Option<f64>, time: u64, half_life: f64) {
+        // Out of order times count as no time passing
+        let elapsed = self
+            .held
+            .map(|(_, since)| time.saturating_sub(since) as f64 / MICROS_PER_SECOND);
+        if let (Some((held, _)), Some(elapsed)) = (self.held, elapsed.filter(|e| *e > 0.0)) {
+            let weight = decay_weight(elapsed, half_life);
+            let mean = self.mean.unwrap_or(held);
+            let diff = held - mean;
+            self.mean = Some(mean + weight * diff);
+            self.variance = (1.0 - weight) * (self.variance + weight * diff * diff);
+            self.observed += elapsed;
+        }
+        self.held = value.map(|value| (value, time));
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.mean
+    }
+
+    pub fn variance(&self) -> Option<f64> {
+        self.mean.map(|_| self.variance)
+    }
+
+    pub fn observed(&self) -> f64 {
+        self.observed
+    }
+
+    // The latest value relative to the decayed distribution of those before it
+    pub fn zscore(&self) -> Option<f64> {
+        let (held, _) = self.held?;
+        let std = self.variance().map(f64::sqrt).filter(|std| *std > 0.0)?;
+        Some((held - self.mean?) / std)
+    }
+}
+
+#[derive(SignalInputs)]
+struct DecayInputs {
+    input: ConsumerInput,
+}
+
+#[derive(SignalParams)]
+struct DecayParams {
+    /// Seconds for an old value to lose half its weight
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+}
+
+#[derive(SignalParams)]
+struct ZScoreParams {
+    /// Seconds for an old value to lose half its weight
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+    /// Seconds of input to see before giving a score
+    #[param(default = "0.0", validate = "warmup >= 0.0")]
+    warmup: f64,
+}
+
+// Moving average of the time-weighted input.
+// Follows the input until any time has passed, and is invalid while the input is.
+// New parameters keep the history, only changing the decay of new updates
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct TimeEma {
+    #[inputs]
+    inputs: DecayInputs,
+    #[output(name = "output")]
+    value: ConsumerOutput,
+    #[params]
+    params: DecayParams,
+    #[state(checkpoint)]
+    stats: DecayedStats,
+}
+
+impl CallSignal for TimeEma {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let input = self.inputs.input.get(graph);
+        self.stats.update(input, time, self.params.half_life);
+        self.value.set_from(
+            input.and_then(|input| self.stats.mean().or(Some(input))),
+            graph,
+        );
+    }
+}
+
+// Moving average and variance of the time-weighted input
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct TimeEwmv {
+    #[inputs]
+    inputs: DecayInputs,
+    #[output(name = "mean")]
+    mean: ConsumerOutput,
+    #[output(name = "variance")]
+    variance: ConsumerOutput,
+    #[params]
+    params: DecayParams,
+    #[state(checkpoint)]
+    stats: DecayedStats,
+}
+
+impl CallSignal for TimeEwmv {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let input = self.inputs.input.get(graph);
+        self.stats.update(input, time, self.params.half_life);
+        let valid = input.is_some();
+        self.mean
+            .set_from(self.stats.mean().filter(|_| valid), graph);
+        self.variance
+            .set_from(self.stats.variance().filter(|_| valid), graph);
+    }
+}
+
+// How many decayed standard deviations the input is from its decayed mean
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct TimeZScore {
+    #[inputs]
+    inputs: DecayInputs,
+    #[output(name = "output")]
+    score: ConsumerOutput,
+    #[params]
+    params: ZScoreParams,
+    #[state(checkpoint)]
+    stats: DecayedStats,
+}
+
+impl CallSignal for TimeZScore {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let input = self.inputs.input.get(graph);
+        self.stats.update(input, time, self.params.half_life);
+        let warm = self.stats.observed() >= self.params.warmup;
+        self.score
+            .set_from(self.stats.zscore().filter(|_| warm), graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    #[test]
+    fn decay_follows_time_not_updates() {
+        // The same path sampled once a second and ten times a second decays the same
+        let mut slow = DecayedStats::default();
+        let mut fast = DecayedStats::default();
+        for step in 0..=100 {
+            let value = if step < 50 { 1.0 } else { 2.0 };
+            slow.update(Some(value), step * SECOND, 10.0);
+            for tick in 0..10 {
+                fast.update(Some(value), step * SECOND + tick * SECOND / 10, 10.0);
+            }
+        }
+        assert!((slow.mean().unwrap() - fast.mean().unwrap()).abs() < 1e-2);
+        // After five half-lives of 2.0, 1/32 of the weight is still on 1.0
+        assert!((slow.mean().unwrap() - (2.0 - 1.0 / 32.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn same_time_updates_replace() {
+        let mut stats = DecayedStats::default();
+        stats.update(Some(1.0), 0, 1.0);
+        stats.update(Some(100.0), 0, 1.0);
+        stats.update(Some(3.0), 0, 1.0);
+        assert_eq!(stats.mean(), None);
+        stats.update(Some(3.0), SECOND, 1.0);
+        assert_eq!(stats.mean(), Some(3.0));
+        assert_eq!(stats.variance(), Some(0.0));
+        assert_eq!(stats.zscore(), None);
+    }
+
+    #[test]
+    fn half_life_halves_weight() {
+        let mut stats = DecayedStats::default();
+        stats.update(Some(0.0), 0, 2.0);
+        stats.update(Some(4.0), SECOND, 2.0);
+        stats.update(Some(4.0), 3 * SECOND, 2.0);
+        // Half the weight moved from 0.0 to 4.0
+        assert!((stats.mean().unwrap() - 2.0).abs() < 1e-9);
+        assert!((stats.variance().unwrap() - 4.0).abs() < 1e-9);
+        assert!((stats.zscore().unwrap() - 1.0).abs() < 1e-9);
+        assert!((stats.observed() - 3.0).abs() < 1e-9);
+
+        // Invalid stretches are skipped entirely
+        stats.update(None, 4 * SECOND, 2.0);
+        let mean = stats.mean();
+        stats.update(Some(4.0), 100 * SECOND, 2.0);
+        assert_eq!(stats.mean(), mean);
+        assert!((stats.observed() - 4.0).abs() < 1e-9);
+    }
+}
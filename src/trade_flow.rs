@@ -0,0 +1,263 @@
+use crate::exchange::normalized::{MarketUpdates, Side, Trade};
+use crate::order_book::cents_to_dollars;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::time_decay::MICROS_PER_SECOND;
+
+use std::collections::VecDeque;
+
+// Signals on the trades of a book's security. They take the book as their input so they
+// are called on its market data, and read any trades straight from the updates.
+// Trade sides are the aggressor's, and times are graph trigger times in microseconds
+
+fn trades(updates: &MarketUpdates) -> &[Trade] {
+    match updates {
+        MarketUpdates::Trades(trades) => trades,
+        _ => &[],
+    }
+}
+
+// Trades seen within the last window, with running totals of them
+#[derive(Default)]
+pub struct TradeWindow {
+    trades: VecDeque<(u64, f64, f64, Side)>,
+    buy_volume: f64,
+    sell_volume: f64,
+    notional: f64,
+}
+
+impl TradeWindow {
+    // Adds the trades, and drops any older than window seconds before time
+    pub fn update(&mut self, trades: &[Trade], time: u64, window: f64) {
+        for trade in trades {
+            let price = cents_to_dollars(trade.cents);
+            self.trades.push_back((time, price, trade.size, trade.side));
+            self.add(price, trade.size, trade.side, 1.0);
+        }
+        let window = (window * MICROS_PER_SECOND) as u64;
+        while let Some((traded, price, size, side)) = self.trades.front().copied() {
+            if traded + window > time {
+                break;
+            }
+            self.trades.pop_front();
+            self.add(price, size, side, -1.0);
+        }
+        // Removing trades leaves rounding error behind, but an empty window is exactly zero
+        if self.trades.is_empty() {
+            self.buy_volume = 0.0;
+            self.sell_volume = 0.0;
+            self.notional = 0.0;
+        }
+    }
+
+    fn add(&mut self, price: f64, size: f64, side: Side, sign: f64) {
+        match side {
+            Side::Buy => self.buy_volume += sign * size,
+            Side::Sell => self.sell_volume += sign * size,
+        }
+        self.notional += sign * price * size;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.buy_volume + self.sell_volume
+    }
+
+    // Buy volume less sell volume
+    pub fn signed_volume(&self) -> f64 {
+        self.buy_volume - self.sell_volume
+    }
+
+    pub fn vwap(&self) -> Option<f64> {
+        let volume = self.volume();
+        if volume > 0.0 {
+            Some(self.notional / volume)
+        } else {
+            None
+        }
+    }
+
+    // Signed volume as a fraction of volume, from -1 for all sells to 1 for all buys
+    pub fn imbalance(&self) -> Option<f64> {
+        let volume = self.volume();
+        if volume > 0.0 {
+            Some(self.signed_volume() / volume)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(SignalInputs)]
+struct TradeInputs {
+    book: BookViewer,
+}
+
+#[derive(SignalParams)]
+struct WindowParams {
+    /// Seconds of trades to look back over
+    #[param(validate = "window > 0.0")]
+    window: f64,
+}
+
+// Buy volume less sell volume over the window, zero when there were no trades
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct SignedVolume {
+    #[inputs]
+    inputs: TradeInputs,
+    #[output(name = "output")]
+    volume: ConsumerOutput,
+    #[params]
+    params: WindowParams,
+    window: TradeWindow,
+}
+
+impl CallSignal for SignedVolume {
+    fn call_signal(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        self.window
+            .update(trades(updates), time, self.params.window);
+        self.volume.set(self.window.signed_volume(), graph);
+    }
+}
+
+// Volume weighted price of trades over the window, invalid without any
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct TradeVwap {
+    #[inputs]
+    inputs: TradeInputs,
+    #[output(name = "vwap")]
+    vwap: ConsumerOutput,
+    #[output(name = "volume")]
+    volume: ConsumerOutput,
+    #[params]
+    params: WindowParams,
+    window: TradeWindow,
+}
+
+impl CallSignal for TradeVwap {
+    fn call_signal(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        self.window
+            .update(trades(updates), time, self.params.window);
+        self.vwap.set_from(self.window.vwap(), graph);
+        self.volume.set(self.window.volume(), graph);
+    }
+}
+
+// Net aggressor volume as a fraction of volume over the window, invalid without trades
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct AggressorImbalance {
+    #[inputs]
+    inputs: TradeInputs,
+    #[output(name = "output")]
+    imbalance: ConsumerOutput,
+    #[params]
+    params: WindowParams,
+    window: TradeWindow,
+}
+
+impl CallSignal for AggressorImbalance {
+    fn call_signal(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        self.window
+            .update(trades(updates), time, self.params.window);
+        self.imbalance.set_from(self.window.imbalance(), graph);
+    }
+}
+
+// How far trades went through the touch, in dollars. Trades don't touch the book,
+// so it still holds the BBO from before them. Like bbo_improved, these are only
+// valid on the update where it happened
+#[derive(Signal)]
+#[signal(cleanup)]
+pub struct TradeThrough {
+    #[inputs]
+    inputs: TradeInputs,
+    #[output(name = "through_ask")]
+    through_ask: ConsumerOutput,
+    #[output(name = "through_bid")]
+    through_bid: ConsumerOutput,
+}
+
+impl CallSignal for TradeThrough {
+    fn call_signal(&mut self, _: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        let (best_bid, best_ask) = self.inputs.book.book().bbo_price();
+        let mut through_ask: Option<usize> = None;
+        let mut through_bid: Option<usize> = None;
+        for trade in trades(updates) {
+            match (trade.side, best_bid, best_ask) {
+                (Side::Buy, _, Some(ask)) if trade.cents > ask => {
+                    through_ask = through_ask.max(Some(trade.cents - ask));
+                }
+                (Side::Sell, Some(bid), _) if trade.cents < bid => {
+                    through_bid = through_bid.max(Some(bid - trade.cents));
+                }
+                _ => (),
+            }
+        }
+        if let Some(through) = through_ask {
+            self.through_ask.set(cents_to_dollars(through), graph);
+        }
+        if let Some(through) = through_bid {
+            self.through_bid.set(cents_to_dollars(through), graph);
+        }
+    }
+
+    fn cleanup(&mut self, _: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.through_ask.mark_invalid(graph);
+        self.through_bid.mark_invalid(graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    fn trade(cents: usize, side: Side, size: f64) -> Trade {
+        Trade {
+            cents,
+            side,
+            size,
+            exchange_time: 0,
+        }
+    }
+
+    #[test]
+    fn window_totals() {
+        let mut window = TradeWindow::default();
+        window.update(&[], 0, 10.0);
+        assert_eq!(window.vwap(), None);
+        assert_eq!(window.imbalance(), None);
+        assert_eq!(window.signed_volume(), 0.0);
+
+        window.update(
+            &[trade(10000, Side::Buy, 3.0), trade(10100, Side::Sell, 1.0)],
+            0,
+            10.0,
+        );
+        assert_eq!(window.signed_volume(), 2.0);
+        assert_eq!(window.imbalance(), Some(0.5));
+        assert_eq!(window.vwap(), Some(100.25));
+
+        window.update(&[trade(10200, Side::Sell, 4.0)], 5 * SECOND, 10.0);
+        assert_eq!(window.signed_volume(), -2.0);
+        assert_eq!(window.volume(), 8.0);
+
+        // The first two trades expire
+        window.update(&[], 10 * SECOND, 10.0);
+        assert_eq!(window.signed_volume(), -4.0);
+        assert_eq!(window.vwap(), Some(102.0));
+        assert_eq!(window.imbalance(), Some(-1.0));
+
+        window.update(&[], 15 * SECOND, 10.0);
+        assert!(window.is_empty());
+        assert_eq!(window.volume(), 0.0);
+        assert_eq!(window.vwap(), None);
+    }
+}
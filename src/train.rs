@@ -0,0 +1,163 @@
+use crate::exchange::normalized::MarketEventBlock;
+use crate::signal_graph::graph::Graph;
+use crate::signal_graph::interface_types::ConsumerWatcher;
+use crate::signal_graph::model::{Fit, LeastSquares};
+use crate::signal_graph::security_index::SecurityIndex;
+use crate::time_decay::MICROS_PER_SECOND;
+
+use std::collections::VecDeque;
+
+// Fits a linear model of graph outputs to the forward return of a fair price by replaying
+// recorded market data through a graph. Features and the fair are sampled on a fixed
+// grid of receive times, and each sample's target is the log return of the fair over
+// the horizon after it, in basis points
+
+pub struct TrainOptions {
+    // Signal and output of each feature
+    pub features: Vec<(String, String)>,
+    pub target: (String, String),
+    // All in seconds
+    pub horizon: f64,
+    pub interval: f64,
+    // Sampling restarts after a gap this long, rather than filling it with stale values
+    pub max_gap: f64,
+}
+
+// Holds samples until the price a horizon later is known
+struct ForwardReturns {
+    horizon: usize,
+    // The features and price at each grid point, None if any of them were invalid
+    pending: VecDeque<Option<(Vec<f64>, f64)>>,
+}
+
+impl ForwardReturns {
+    fn new(horizon: usize) -> ForwardReturns {
+        ForwardReturns {
+            horizon,
+            pending: VecDeque::with_capacity(horizon + 1),
+        }
+    }
+
+    // Returns the sample from a horizon ago with its return up to this price
+    fn push(&mut self, features: Option<Vec<f64>>, price: Option<f64>) -> Option<(Vec<f64>, f64)> {
+        let price = price.filter(|price| *price > 0.0);
+        self.pending
+            .push_back(features.and_then(|features| price.map(|price| (features, price))));
+        if self.pending.len() <= self.horizon {
+            return None;
+        }
+        let (features, start) = self.pending.pop_front().unwrap()?;
+        price.map(|price| (features, (price / start).ln() * 10_000.0))
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+fn listener(
+    graph: &Graph,
+    (signal, output): &(String, String),
+) -> Result<ConsumerWatcher, anyhow::Error> {
+    graph
+        .signal_listener(signal, output)
+        .ok_or_else(|| anyhow::anyhow!("Graph has no output {} of {}", output, signal))
+}
+
+pub struct Trainer {
+    names: Vec<String>,
+    features: Vec<ConsumerWatcher>,
+    target: ConsumerWatcher,
+    returns: ForwardReturns,
+    stats: LeastSquares,
+    interval: u64,
+    max_gap: u64,
+    next_sample: Option<u64>,
+}
+
+impl Trainer {
+    pub fn new(graph: &Graph, options: &TrainOptions) -> Result<Trainer, anyhow::Error> {
+        if !(options.interval > 0.0 && options.horizon >= options.interval) {
+            anyhow::bail!("The horizon must be at least one positive interval");
+        }
+        // Sample times are taken modulo the interval, so it can't round down to nothing
+        let interval = (options.interval * MICROS_PER_SECOND) as u64;
+        if interval == 0 {
+            anyhow::bail!("The interval must be at least a microsecond");
+        }
+        let features = options
+            .features
+            .iter()
+            .map(|feature| listener(graph, feature))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Trainer {
+            names: options
+                .features
+                .iter()
+                .map(|(signal, output)| format!("{}:{}", signal, output))
+                .collect(),
+            target: listener(graph, &options.target)?,
+            returns: ForwardReturns::new((options.horizon / options.interval).round() as usize),
+            stats: LeastSquares::new(features.len()),
+            features,
+            interval,
+            max_gap: (options.max_gap * MICROS_PER_SECOND) as u64,
+            next_sample: None,
+        })
+    }
+
+    fn sample(&mut self) {
+        let features = self
+            .features
+            .iter()
+            .map(ConsumerWatcher::get)
+            .collect::<Option<Vec<_>>>();
+        if let Some((features, target)) = self.returns.push(features, self.target.get()) {
+            self.stats.add(&features, target);
+        }
+    }
+
+    // Grid points before the block see the graph as it was before it
+    pub fn replay(&mut self, graph: &mut Graph, security: SecurityIndex, block: &MarketEventBlock) {
+        let time = block.received_time;
+        let mut next_sample = match self.next_sample {
+            Some(next_sample) if time <= next_sample + self.max_gap => next_sample,
+            _ => {
+                self.returns.clear();
+                time - time % self.interval + self.interval
+            }
+        };
+        while next_sample <= time {
+            self.sample();
+            next_sample += self.interval;
+        }
+        self.next_sample = Some(next_sample);
+        graph.trigger_book(security, &block.events, time, |_, _| ());
+    }
+
+    pub fn fit(&self, ridge: f64) -> Result<Fit, anyhow::Error> {
+        self.stats.fit(&self.names, ridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_after_horizon() {
+        let mut returns = ForwardReturns::new(2);
+        assert_eq!(returns.push(Some(vec![1.0]), Some(100.0)), None);
+        assert_eq!(returns.push(None, Some(100.0)), None);
+        let (features, target) = returns.push(Some(vec![3.0]), Some(101.0)).unwrap();
+        assert_eq!(features, vec![1.0]);
+        assert!((target - (1.01f64).ln() * 10_000.0).abs() < 1e-9);
+        // The sample with invalid features gives nothing, as does an invalid price later on
+        assert_eq!(returns.push(Some(vec![4.0]), Some(102.0)), None);
+        assert_eq!(returns.push(Some(vec![5.0]), None), None);
+
+        returns.clear();
+        assert_eq!(returns.push(Some(vec![6.0]), Some(100.0)), None);
+        assert_eq!(returns.push(Some(vec![7.0]), Some(100.0)), None);
+    }
+}
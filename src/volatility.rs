@@ -0,0 +1,370 @@
+use crate::exchange::normalized::MarketUpdates;
+use crate::signal_graph::graph_registrar::*;
+use crate::signal_graph::interface_types::*;
+use crate::time_decay::{DecayedStats, MICROS_PER_SECOND};
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+
+// Volatility and spread regime signals, for scaling thresholds with the market.
+// Volatilities are in basis points over horizon seconds, and times are graph trigger
+// times in microseconds
+
+pub fn interval_micros(interval: f64) -> u64 {
+    ((interval * MICROS_PER_SECOND) as u64).max(1)
+}
+
+// Scales a per-interval standard deviation of log returns to bps over the horizon
+fn to_bps(std: f64, interval: f64, horizon: f64) -> f64 {
+    std * (horizon / interval).sqrt() * 10_000.0
+}
+
+// Samples a value on a fixed time grid. A grid point takes the value held at that time,
+// which is only known once the next update arrives, so samples are handed out late
+#[derive(Default)]
+pub struct GridSampler<T = f64> {
+    held: Option<T>,
+    next_sample: Option<u64>,
+}
+
+impl<T: Copy> GridSampler<T> {
+    // Returns the value held before this update and how many grid points saw it.
+    // An invalid value restarts the grid at the next valid one
+    pub fn update(&mut self, value: Option<T>, time: u64, interval: u64) -> Option<(T, u64)> {
+        let mut sampled = None;
+        if let (Some(held), Some(next_sample)) = (self.held, self.next_sample) {
+            if next_sample <= time {
+                let due = (time - next_sample) / interval + 1;
+                self.next_sample = Some(next_sample + due * interval);
+                sampled = Some((held, due));
+            }
+        }
+        self.held = value;
+        match value {
+            Some(_) if self.next_sample.is_none() => self.next_sample = Some(time),
+            Some(_) => (),
+            None => self.next_sample = None,
+        }
+        sampled
+    }
+}
+
+// Root mean square of the returns, so drift over the window isn't taken out
+pub fn realized_volatility(returns: &VecDeque<f64>) -> Option<f64> {
+    if returns.is_empty() {
+        return None;
+    }
+    let sum: f64 = returns.iter().map(|r| r * r).sum();
+    Some((sum / returns.len() as f64).sqrt())
+}
+
+// Parkinson's estimate of the standard deviation from the high and low of each period
+pub fn parkinson_volatility(ranges: &VecDeque<(f64, f64)>) -> Option<f64> {
+    if ranges.is_empty() {
+        return None;
+    }
+    let sum: f64 = ranges
+        .iter()
+        .map(|(high, low)| (high / low).ln().powi(2))
+        .sum();
+    Some((sum / (ranges.len() as f64 * 4.0 * std::f64::consts::LN_2)).sqrt())
+}
+
+#[derive(SignalInputs)]
+struct VolInputs {
+    input: ConsumerInput,
+}
+
+#[derive(SignalParams)]
+struct VolParams {
+    /// Seconds between samples
+    #[param(validate = "interval > 0.0")]
+    interval: f64,
+    /// Samples the volatility is taken over
+    #[param(validate = "window > 1")]
+    window: usize,
+    /// Seconds the volatility is scaled to
+    #[param(default = "1.0", validate = "horizon > 0.0")]
+    horizon: f64,
+}
+
+// Realized volatility of log returns of the input, sampled every interval seconds.
+// Invalid until a full window of returns has been seen, and while the input is
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct RealizedVol {
+    #[inputs]
+    inputs: VolInputs,
+    #[output(name = "volatility")]
+    volatility: ConsumerOutput,
+    #[params]
+    params: VolParams,
+    sampler: GridSampler,
+    last_sample: Option<f64>,
+    returns: VecDeque<f64>,
+}
+
+impl CallSignal for RealizedVol {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let input = self.inputs.input.get(graph).filter(|input| *input > 0.0);
+        let interval = interval_micros(self.params.interval);
+        if let Some((held, due)) = self.sampler.update(input, time, interval) {
+            // Past a full window, more samples of the same value change nothing
+            let due = due.min(self.params.window as u64 + 1);
+            for _ in 0..due {
+                if let Some(last) = self.last_sample {
+                    self.returns.push_back((held / last).ln());
+                }
+                self.last_sample = Some(held);
+            }
+            while self.returns.len() > self.params.window {
+                self.returns.pop_front();
+            }
+        }
+        // Returns aren't taken across gaps
+        if input.is_none() {
+            self.last_sample = None;
+        }
+
+        let full = self.returns.len() >= self.params.window;
+        let volatility = realized_volatility(&self.returns)
+            .filter(|_| full && input.is_some())
+            .map(|std| to_bps(std, self.params.interval, self.params.horizon));
+        self.volatility.set_from(volatility, graph);
+    }
+}
+
+// Parkinson volatility from the high and low of the input in each interval, and the
+// range of the input over the window including the current interval
+#[derive(Signal)]
+#[signal(update_params)]
+pub struct RangeVol {
+    #[inputs]
+    inputs: VolInputs,
+    #[output(name = "volatility")]
+    volatility: ConsumerOutput,
+    #[output(name = "range")]
+    range: ConsumerOutput,
+    #[params]
+    params: VolParams,
+    // Start, high and low of the current interval
+    current: Option<(u64, f64, f64)>,
+    last: Option<f64>,
+    ranges: VecDeque<(f64, f64)>,
+}
+
+impl RangeVol {
+    fn close(&mut self, high: f64, low: f64) {
+        self.ranges.push_back((high, low));
+        while self.ranges.len() > self.params.window {
+            self.ranges.pop_front();
+        }
+    }
+}
+
+impl CallSignal for RangeVol {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let input = self.inputs.input.get(graph).filter(|input| *input > 0.0);
+        let interval = interval_micros(self.params.interval);
+        let start = time - time % interval;
+        if let Some((current, high, low)) = self.current {
+            if current != start {
+                self.close(high, low);
+                // Intervals with no updates held the last value throughout
+                if let Some(last) = self.last {
+                    let skipped = (start.saturating_sub(current) / interval).saturating_sub(1);
+                    for _ in 0..skipped.min(self.params.window as u64) {
+                        self.close(last, last);
+                    }
+                    self.current = Some((start, last, last));
+                } else {
+                    self.current = None;
+                }
+            }
+        }
+        if let Some(input) = input {
+            let (_, high, low) = self.current.get_or_insert((start, input, input));
+            *high = high.max(input);
+            *low = low.min(input);
+        }
+        self.last = input;
+
+        let full = self.ranges.len() >= self.params.window;
+        let volatility = parkinson_volatility(&self.ranges)
+            .filter(|_| full && input.is_some())
+            .map(|std| to_bps(std, self.params.interval, self.params.horizon));
+        let range = self
+            .current
+            .filter(|_| input.is_some())
+            .map(|(_, high, low)| {
+                let (high, low) = self.ranges.iter().fold((high, low), |(h, l), (high, low)| {
+                    (h.max(*high), l.min(*low))
+                });
+                high - low
+            });
+        self.volatility.set_from(volatility, graph);
+        self.range.set_from(range, graph);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum Regime {
+    Tight,
+    Normal,
+    Wide,
+}
+
+impl Default for Regime {
+    fn default() -> Regime {
+        Regime::Normal
+    }
+}
+
+impl Regime {
+    fn as_output(self) -> f64 {
+        match self {
+            Regime::Tight => -1.0,
+            Regime::Normal => 0.0,
+            Regime::Wide => 1.0,
+        }
+    }
+
+    // Leaving a regime takes moving hysteresis past the threshold that entered it,
+    // so a ratio sitting on a threshold doesn't flap between regimes
+    pub fn next(self, ratio: f64, tight: f64, wide: f64, hysteresis: f64) -> Regime {
+        match self {
+            _ if ratio > wide => Regime::Wide,
+            _ if ratio < tight => Regime::Tight,
+            Regime::Wide if ratio > wide - hysteresis => Regime::Wide,
+            Regime::Tight if ratio < tight + hysteresis => Regime::Tight,
+            _ => Regime::Normal,
+        }
+    }
+}
+
+#[derive(SignalInputs)]
+struct RegimeInputs {
+    spread: ConsumerInput,
+}
+
+#[derive(SignalParams)]
+struct RegimeParams {
+    /// Half-life in seconds of the average spread
+    #[param(validate = "half_life > 0.0")]
+    half_life: f64,
+    /// Spread as a multiple of its average below which it is tight
+    #[param(default = "0.75", validate = "tight > 0.0 && tight < 1.0")]
+    tight: f64,
+    /// Spread as a multiple of its average above which it is wide
+    #[param(default = "1.5", validate = "wide > 1.0")]
+    wide: f64,
+    /// How far back past a threshold the spread must go to leave a regime
+    #[param(default = "0.1", validate = "hysteresis >= 0.0")]
+    hysteresis: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RegimeState {
+    stats: DecayedStats,
+    regime: Regime,
+}
+
+// Classifies a spread against its own time-decayed average. The regime output is -1 when
+// tight, 0 when normal and 1 when wide, and ratio is the spread over its average
+#[derive(Signal)]
+#[signal(update_params, checkpoint)]
+pub struct SpreadRegime {
+    #[inputs]
+    inputs: RegimeInputs,
+    #[output(name = "regime")]
+    regime: ConsumerOutput,
+    #[output(name = "ratio")]
+    ratio: ConsumerOutput,
+    #[params]
+    params: RegimeParams,
+    #[state(checkpoint)]
+    state: RegimeState,
+}
+
+impl CallSignal for SpreadRegime {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let spread = self.inputs.spread.get(graph);
+        let state = &mut self.state;
+        state.stats.update(spread, time, self.params.half_life);
+        let ratio = spread.and_then(|spread| {
+            state
+                .stats
+                .mean()
+                .filter(|mean| *mean > 0.0)
+                .map(|mean| spread / mean)
+        });
+        match ratio {
+            Some(ratio) => {
+                let params = &self.params;
+                state.regime =
+                    state
+                        .regime
+                        .next(ratio, params.tight, params.wide, params.hysteresis);
+                self.regime.set(state.regime.as_output(), graph);
+                self.ratio.set(ratio, graph);
+            }
+            None => {
+                self.regime.mark_invalid(graph);
+                self.ratio.mark_invalid(graph);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    #[test]
+    fn samples_held_values() {
+        let mut sampler = GridSampler::default();
+        assert_eq!(sampler.update(Some(1.0), 0, SECOND), None);
+        // Grid points at 0, 1 and 2 seconds all held 1.0
+        assert_eq!(
+            sampler.update(Some(2.0), 2 * SECOND, SECOND),
+            Some((1.0, 3))
+        );
+        assert_eq!(sampler.update(Some(3.0), 2 * SECOND, SECOND), None);
+        assert_eq!(sampler.update(None, 3 * SECOND + 1, SECOND), Some((3.0, 1)));
+        // The grid restarts from the next valid value
+        assert_eq!(sampler.update(Some(4.0), 10 * SECOND + 1, SECOND), None);
+        assert_eq!(
+            sampler.update(Some(4.0), 10 * SECOND + 1, SECOND),
+            Some((4.0, 1))
+        );
+    }
+
+    #[test]
+    fn volatility_estimates() {
+        let returns: VecDeque<_> = vec![0.01, -0.01, 0.01, -0.01].into_iter().collect();
+        assert!((realized_volatility(&returns).unwrap() - 0.01).abs() < 1e-12);
+        assert_eq!(realized_volatility(&VecDeque::new()), None);
+
+        let ranges: VecDeque<_> = vec![(101.0, 100.0); 3].into_iter().collect();
+        let expected = (1.01f64).ln() / (4.0 * std::f64::consts::LN_2).sqrt();
+        assert!((parkinson_volatility(&ranges).unwrap() - expected).abs() < 1e-12);
+
+        // One percent a second is a hundred bps, and two hundred over four seconds
+        assert!((to_bps(0.01, 1.0, 4.0) - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn regime_hysteresis() {
+        let step = |regime: Regime, ratio| regime.next(ratio, 0.75, 1.5, 0.1);
+        assert_eq!(step(Regime::Normal, 1.0), Regime::Normal);
+        assert_eq!(step(Regime::Normal, 1.6), Regime::Wide);
+        assert_eq!(step(Regime::Wide, 1.45), Regime::Wide);
+        assert_eq!(step(Regime::Wide, 1.35), Regime::Normal);
+        assert_eq!(step(Regime::Normal, 0.7), Regime::Tight);
+        assert_eq!(step(Regime::Tight, 0.8), Regime::Tight);
+        assert_eq!(step(Regime::Tight, 0.9), Regime::Normal);
+        assert_eq!(step(Regime::Tight, 2.0), Regime::Wide);
+    }
+}
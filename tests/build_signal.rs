@@ -3,6 +3,7 @@
 use arby::exchange::normalized::MarketUpdates;
 use arby::order_book::*;
 use arby::signal_graph::graph_registrar::*;
+use arby::signal_graph::graph_spec::GraphSpec;
 use arby::signal_graph::interface_types::*;
 use arby::signal_graph::security_index::{Security, SecurityMap, SmallString};
 
@@ -18,7 +19,7 @@ struct DummyConsumerSignal {
 }
 
 impl CallSignal for DummyBookSignal {
-    fn call_signal(&mut self, time: u128, _: &MarketUpdates, graph: &GraphHandle) {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
         assert!(!self.output.was_written(graph));
         self.output
             .set(1.0 + self.output.get(graph).unwrap_or(1.0), graph)
@@ -26,7 +27,7 @@ impl CallSignal for DummyBookSignal {
 }
 
 impl CallSignal for DummyConsumerSignal {
-    fn call_signal(&mut self, time: u128, _: &MarketUpdates, graph: &GraphHandle) {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
         assert!(!self.output.was_written(graph));
         self.output.set(self.input.get(graph).unwrap(), graph);
     }
@@ -47,10 +48,11 @@ impl RegisterSignal for DummyBookSignal {
 
     fn create(
         mut outs: HashMap<&'static str, ConsumerOutput>,
-        _: InputLoader,
+        mut input: InputLoader,
         json: Option<&str>,
     ) -> Result<DummyBookSignal, anyhow::Error> {
         assert_eq!(json, None);
+        input.load_input::<BookViewer>("input")?;
         Ok(DummyBookSignal {
             output: outs.remove("out").unwrap(),
         })
@@ -81,6 +83,85 @@ impl RegisterSignal for DummyConsumerSignal {
     }
 }
 
+fn consumer_call(parent: &str) -> SignalCall {
+    SignalCall {
+        signal_name: "dummy_signal".to_string(),
+        inputs: vec![(
+            "input".to_string(),
+            NamedSignalType::Consumer((parent.to_string(), "out".to_string())),
+        )]
+        .into_iter()
+        .collect(),
+    }
+}
+
+#[test]
+fn describe_graph() {
+    let signals = vec![
+        ("dummy_book", make_signal_for::<DummyBookSignal>()),
+        ("dummy_signal", make_signal_for::<DummyConsumerSignal>()),
+    ];
+
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let btc = Security::new("BITMEX", "BTCXBT");
+    let eth = Security::new("BITMEX", "ETHXBT");
+
+    let sec_map = SecurityMap::new(&[btc.clone(), eth.clone()]);
+
+    let layout_vec = vec![
+        (
+            "book".to_string(),
+            SignalCall {
+                signal_name: "dummy_book".to_string(),
+                inputs: vec![("input".to_string(), NamedSignalType::Book(btc.clone()))]
+                    .into_iter()
+                    .collect(),
+            },
+        ),
+        ("consumer2".to_string(), consumer_call("consumer1")),
+        ("consumer1".to_string(), consumer_call("book")),
+    ];
+
+    // Round trip through the file representation first
+    let spec = GraphSpec::new(&[btc.clone(), eth.clone()], &layout_vec, &HashMap::new()).unwrap();
+    let spec: GraphSpec = serde_json::from_str(&serde_json::to_string(&spec).unwrap()).unwrap();
+    assert_eq!(spec.securities, vec![btc.clone(), eth.clone()]);
+
+    let mut graph = registrar
+        .generate_graph(&spec.layout(), &sec_map, &spec.params())
+        .unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(sec_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+
+    let description = graph.describe(&sec_map);
+
+    assert_eq!(description.books.len(), 1);
+    assert_eq!(description.books[0].security, btc);
+    assert_eq!(
+        description.books[0].call_order,
+        vec!["book", "consumer1", "consumer2"]
+    );
+
+    let names: Vec<_> = description
+        .signals
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["book", "consumer1", "consumer2"]);
+    assert_eq!(description.signals[1].definition, "dummy_signal");
+    assert_eq!(description.signals[2].outputs[0].value, Some(2.0));
+
+    let dot = description.to_dot();
+    assert!(dot.contains("\"book:BITMEX:BTCXBT\" -> \"book\" [label=\"input\"];"));
+    assert!(dot.contains("\"consumer1\" -> \"consumer2\" [label=\"input: out\"];"));
+    assert!(!dot.contains("ETHXBT"));
+
+    let json: serde_json::Value = serde_json::from_str(&description.to_json().unwrap()).unwrap();
+    assert_eq!(json["signals"][0]["inputs"][0]["name"], "input");
+}
+
 #[test]
 fn construct_graph() {
     let signals = vec![
@@ -95,7 +176,7 @@ fn construct_graph() {
         exchange: SmallString::from_str("BITMEX"),
     };
 
-    let sec_map = unsafe { SecurityMap::new_unchecked(&[btc.clone()]) };
+    let sec_map = SecurityMap::new(&[btc.clone()]);
 
     let layout_vec = vec![
         (
@@ -158,3 +239,122 @@ fn construct_graph() {
     let data = MarketUpdates::Book(data);
     graph.trigger_book(sec_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
 }
+
+// Live and shadow graphs over differently ordered maps, side by side in one process
+#[test]
+fn independent_graphs() {
+    let signals = vec![
+        ("dummy_book", make_signal_for::<DummyBookSignal>()),
+        ("dummy_signal", make_signal_for::<DummyConsumerSignal>()),
+    ];
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let btc = Security::new("BITMEX", "BTCXBT");
+    let eth = Security::new("BITMEX", "ETHXBT");
+    let live_map = SecurityMap::create(&[btc.clone(), eth.clone()]);
+    let shadow_map = SecurityMap::create(&[eth.clone(), btc.clone()]);
+
+    let layout_vec = vec![
+        (
+            "book".to_string(),
+            SignalCall {
+                signal_name: "dummy_book".to_string(),
+                inputs: vec![("input".to_string(), NamedSignalType::Book(btc.clone()))]
+                    .into_iter()
+                    .collect(),
+            },
+        ),
+        ("consumer".to_string(), consumer_call("book")),
+    ];
+    let mut live = registrar
+        .generate_graph(&layout_vec, &live_map, &HashMap::new())
+        .unwrap();
+    let mut shadow = registrar
+        .generate_graph(&layout_vec, &shadow_map, &HashMap::new())
+        .unwrap();
+    let live_out = live.signal_listener("consumer", "out").unwrap();
+    let shadow_out = shadow.signal_listener("consumer", "out").unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    live.trigger_book(live_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+    live.trigger_book(live_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+    shadow.trigger_book(shadow_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+    assert_eq!(live_out.get(), Some(3.0));
+    assert_eq!(shadow_out.get(), Some(2.0));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "Security index from another map")]
+fn foreign_security_index() {
+    let signals = vec![("dummy_book", make_signal_for::<DummyBookSignal>())];
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let btc = Security::new("BITMEX", "BTCXBT");
+    let live_map = SecurityMap::new(&[btc.clone()]);
+    let replay_map = SecurityMap::new(&[btc.clone()]);
+    let layout_vec = vec![(
+        "book".to_string(),
+        SignalCall {
+            signal_name: "dummy_book".to_string(),
+            inputs: vec![("input".to_string(), NamedSignalType::Book(btc.clone()))]
+                .into_iter()
+                .collect(),
+        },
+    )];
+    let mut graph = registrar
+        .generate_graph(&layout_vec, &live_map, &HashMap::new())
+        .unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(replay_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+}
+
+#[cfg(feature = "profile")]
+#[test]
+fn profile_graph() {
+    let signals = vec![
+        ("dummy_book", make_signal_for::<DummyBookSignal>()),
+        ("dummy_signal", make_signal_for::<DummyConsumerSignal>()),
+    ];
+
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let btc = Security::new("BITMEX", "BTCXBT");
+    let sec_map = SecurityMap::new(&[btc.clone()]);
+
+    let layout_vec = vec![
+        (
+            "book".to_string(),
+            SignalCall {
+                signal_name: "dummy_book".to_string(),
+                inputs: vec![("input".to_string(), NamedSignalType::Book(btc.clone()))]
+                    .into_iter()
+                    .collect(),
+            },
+        ),
+        ("consumer".to_string(), consumer_call("book")),
+    ];
+
+    let mut graph = registrar
+        .generate_graph(&layout_vec, &sec_map, &HashMap::new())
+        .unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    for _ in 0..5 {
+        graph.trigger_book(sec_map.to_index(&btc).unwrap(), &data, 0, |_, _| ());
+    }
+
+    let profile = graph.profile(&sec_map);
+    assert_eq!(profile.securities.len(), 1);
+    let security = &profile.securities[0];
+    assert_eq!(security.trigger.count(), 5);
+    let names: Vec<_> = security.signals.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["book", "consumer"]);
+    assert!(security.signals.iter().all(|s| s.call.count() == 5));
+    assert!(security.signals.iter().all(|s| s.cleanup.is_none()));
+    assert!(profile.to_html().contains("consumer"));
+
+    graph.reset_profile();
+    assert_eq!(graph.profile(&sec_map).securities[0].trigger.count(), 0);
+}
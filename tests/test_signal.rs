@@ -1,11 +1,27 @@
 #![allow(warnings)]
 #[macro_use]
 mod common;
+use arby::cross_venue::{LeadLag, PairSpread};
+use arby::displacement::Displacement;
+use arby::exchange::normalized::{
+    BookUpdate, Exchange, MarketEventBlock, MarketUpdates, Side, Trade,
+};
+use arby::fair_value::FairValue;
+use arby::linear_model::Model;
 use arby::order_book::*;
+use arby::remote_venue_aggregator::RemoteVenueAggregator;
+use arby::signal_graph::aggregate_ops::WeightedSum;
+use arby::signal_graph::graph::Graph;
 use arby::signal_graph::graph_error::*;
+use arby::signal_graph::graph_index::MAX_GRAPH_INDEX;
+use arby::signal_graph::graph_patch::GraphPatch;
 use arby::signal_graph::graph_registrar::*;
+use arby::signal_graph::graph_runner::{GraphRunner, RunnerConfig};
 use arby::signal_graph::interface_types::*;
+use arby::signal_graph::params::*;
+use arby::signal_graph::published_outputs::{OutputPublisher, PublishedOutputs};
 use arby::signal_graph::security_index::{Security, SecurityMap, SmallString};
+use arby::trade_flow::TradeThrough;
 
 use std::collections::{HashMap, HashSet};
 
@@ -18,8 +34,204 @@ struct DummyConsumerSignal {
     input: ConsumerInput,
 }
 
+struct DummyAggregateSignal {
+    output: ConsumerOutput,
+    input: AggregateInput,
+}
+
+struct DummyParamSignal {
+    output: ConsumerOutput,
+    input: ConsumerInput,
+    scale: f64,
+}
+
+// Counts how many times it's been called, and checkpoints the count
+struct DummyCounterSignal {
+    output: ConsumerOutput,
+    input: ConsumerInput,
+    calls: u64,
+}
+
+// Declares an input that it never loads
+struct DummyLazySignal {
+    output: ConsumerOutput,
+}
+
+// Publishes [n, 2n, 3n] on the nth call
+struct DummyLadderSignal {
+    output: TypedOutput<[f64; 3]>,
+    calls: u64,
+}
+
+struct DummyLadderSumSignal {
+    output: ConsumerOutput,
+    input: TypedInput<[f64; 3]>,
+}
+
+// Declares the same output name as both f64 and typed
+struct DummyClashSignal;
+
+// Checkpoints its call count but also has a typed output, which can't be saved
+#[derive(Signal)]
+#[signal(crate = "arby", checkpoint)]
+struct DummyTypedCounterSignal {
+    #[output]
+    out: TypedOutput<[f64; 2]>,
+    #[state(checkpoint)]
+    calls: u64,
+}
+
+#[derive(SignalInputs)]
+#[signal_inputs(crate = "arby")]
+struct DummyDerivedInputs {
+    #[input(name = "book")]
+    viewer: BookViewer,
+    input: ConsumerInput,
+    many: Vec<ConsumerInput>,
+    agg: AggregateInput,
+    ladder: TypedInput<[f64; 3]>,
+}
+
+// Publishes whether all its inputs are valid and whether any changed
+struct DummyDerivedSignal {
+    inputs: DummyDerivedInputs,
+    all_valid: ConsumerOutput,
+    any_changed: ConsumerOutput,
+}
+
+#[derive(SignalParams)]
+#[signal_params(crate = "arby")]
+struct DummyScaleParams {
+    /// Multiplies the input
+    #[param(validate = "scale != 0.0")]
+    scale: f64,
+    #[param(default = "1.5", validate = "offset >= 0.0")]
+    offset: f64,
+}
+
+#[derive(SignalInputs)]
+#[signal_inputs(crate = "arby")]
+struct DummyScaleInputs {
+    input: ConsumerInput,
+}
+
+// Scales its input, and publishes the input alongside the result
+#[derive(Signal)]
+#[signal(crate = "arby")]
+struct DummyScaleSignal {
+    #[inputs]
+    inputs: DummyScaleInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[output(name = "pair")]
+    both: TypedOutput<[f64; 2]>,
+    #[params]
+    params: DummyScaleParams,
+    #[state(init = "7")]
+    calls: u64,
+}
+
+// Size weighted mean of its values, counting how many entries changed each call
+#[derive(Signal)]
+#[signal(crate = "arby")]
+struct DummyWeightedSignal {
+    #[inputs]
+    inputs: DummyWeightedInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[output]
+    visited: ConsumerOutput,
+    #[state(init = "WeightedSum::new(inputs.values.len())")]
+    sum: WeightedSum,
+}
+
+#[derive(SignalInputs)]
+#[signal_inputs(crate = "arby")]
+struct DummyWeightedInputs {
+    values: AggregateInput,
+    weights: AggregateInput,
+}
+
+#[derive(SignalParams)]
+#[signal_params(crate = "arby")]
+struct DummyTotalParams {
+    scale: f64,
+}
+
+// Running total of its scaled input, leaving parameter updates and checkpoints to the derive
+#[derive(Signal)]
+#[signal(
+    crate = "arby",
+    update_params,
+    checkpoint,
+    validate = "DummyTotalSignal::check_scale"
+)]
+struct DummyTotalSignal {
+    #[inputs]
+    inputs: DummyScaleInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[params]
+    params: DummyTotalParams,
+    #[state(checkpoint)]
+    total: f64,
+}
+
+#[derive(SignalInputs)]
+#[signal_inputs(crate = "arby")]
+struct DummyQuoteInputs {
+    book: BookViewer,
+}
+
+// Publishes the price and size of the last update it's called with, invalid on no updates
+#[derive(Signal)]
+#[signal(crate = "arby")]
+struct DummyQuoteSignal {
+    #[inputs]
+    inputs: DummyQuoteInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[output]
+    size: ConsumerOutput,
+}
+
+#[derive(SignalInputs)]
+#[signal_inputs(crate = "arby")]
+struct DummyFlickerInputs {
+    input: BookViewer,
+}
+
+// Publishes the call count on odd calls and is invalid on even ones
+#[derive(Signal)]
+#[signal(crate = "arby")]
+struct DummyFlickerSignal {
+    #[inputs]
+    inputs: DummyFlickerInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[state(init = "0")]
+    calls: u64,
+}
+
+// Publishes on its first call, then goes invalid without notifying consumers
+#[derive(Signal)]
+#[signal(crate = "arby")]
+struct DummyFadeSignal {
+    #[inputs]
+    inputs: DummyFlickerInputs,
+    #[output]
+    out: ConsumerOutput,
+    #[state(init = "0")]
+    calls: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct DummyParams {
+    scale: f64,
+}
+
 impl CallSignal for DummyBookSignal {
-    fn call_signal(&mut self, time: u128, graph: &GraphHandle) {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
         assert!(!self.output.was_written(graph));
         self.output
             .set(1.0 + self.output.get(graph).unwrap_or(1.0), graph)
@@ -27,14 +239,170 @@ impl CallSignal for DummyBookSignal {
 }
 
 impl CallSignal for DummyConsumerSignal {
-    fn call_signal(&mut self, time: u128, graph: &GraphHandle) {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
         assert!(!self.output.was_written(graph));
         self.output.set(self.input.get(graph).unwrap(), graph);
     }
 }
 
+impl CallSignal for DummyAggregateSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let total = self.input.iter_changed(graph).filter_map(|(_, v)| v).sum();
+        self.output.set(total, graph);
+    }
+}
+
+impl CallSignal for DummyParamSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.output
+            .set_from(self.input.get(graph).map(|v| v * self.scale), graph);
+    }
+
+    fn update_params(&mut self, json: &str) -> Result<(), anyhow::Error> {
+        let DummyParams { scale } = serde_json::from_str(json)?;
+        self.scale = scale;
+        Ok(())
+    }
+}
+
+impl CallSignal for DummyCounterSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.calls += 1;
+        let last = self.output.get(graph).unwrap_or(0.0);
+        self.output
+            .set(last + self.input.get(graph).unwrap(), graph);
+    }
+
+    fn save_state(&self) -> Result<serde_json::Value, anyhow::Error> {
+        Ok(serde_json::json!({ "calls": self.calls }))
+    }
+
+    fn load_state(&mut self, state: serde_json::Value) -> Result<(), anyhow::Error> {
+        self.calls = state["calls"]
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("Missing calls"))?;
+        Ok(())
+    }
+}
+
+impl CallSignal for DummyLazySignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.output.set(1.0, graph);
+    }
+}
+
+impl CallSignal for DummyLadderSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        assert!(!self.output.was_written(graph));
+        self.calls += 1;
+        let n = self.calls as f64;
+        self.output.set([n, 2.0 * n, 3.0 * n], graph);
+    }
+}
+
+impl CallSignal for DummyLadderSumSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        assert!(self.input.was_written(graph));
+        self.output
+            .set_from(self.input.get(graph).map(|l| l.iter().sum()), graph);
+    }
+}
+
+impl CallSignal for DummyClashSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {}
+}
+
+impl CallSignal for DummyTypedCounterSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.calls += 1;
+    }
+}
+
+impl CallSignal for DummyDerivedSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let all_valid = self.inputs.all_valid(graph) as u64 as f64;
+        let any_changed = self.inputs.any_changed(graph) as u64 as f64;
+        self.all_valid.set(all_valid, graph);
+        self.any_changed.set(any_changed, graph);
+    }
+}
+
+impl CallSignal for DummyScaleSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.calls += 1;
+        if let Some(input) = self.inputs.input.get(graph) {
+            let scaled = input * self.params.scale + self.params.offset;
+            self.out.set(scaled, graph);
+            self.both.set([input, scaled], graph);
+        }
+    }
+}
+
+impl DummyTotalSignal {
+    fn check_scale(&self) -> Result<(), anyhow::Error> {
+        if self.params.scale < 0.0 {
+            return Err(anyhow::anyhow!("Negative scale {}", self.params.scale));
+        }
+        Ok(())
+    }
+}
+
+impl CallSignal for DummyTotalSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        if let Some(input) = self.inputs.input.get(graph) {
+            self.total += input * self.params.scale;
+            self.out.set(self.total, graph);
+        }
+    }
+}
+
+impl CallSignal for DummyQuoteSignal {
+    fn call_signal(&mut self, time: u64, updates: &MarketUpdates, graph: &GraphHandle) {
+        let last = updates.as_book().and_then(|updates| updates.last());
+        self.out
+            .set_from(last.map(|update| cents_to_dollars(update.cents)), graph);
+        self.size.set_from(last.map(|update| update.size), graph);
+    }
+}
+
+impl CallSignal for DummyFlickerSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.calls += 1;
+        if self.calls % 2 == 1 {
+            self.out.set(self.calls as f64, graph);
+        } else {
+            self.out.invalidate_and_notify(graph);
+        }
+    }
+}
+
+impl CallSignal for DummyFadeSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        self.calls += 1;
+        if self.calls == 1 {
+            self.out.set(1.0, graph);
+        } else {
+            self.out.mark_invalid(graph);
+        }
+    }
+}
+
+impl CallSignal for DummyWeightedSignal {
+    fn call_signal(&mut self, time: u64, _: &MarketUpdates, graph: &GraphHandle) {
+        let DummyWeightedInputs { values, weights } = &self.inputs;
+        let mut visited = 0;
+        for (index, zipped) in AggregateInput::zip_changed(&[values, weights], graph) {
+            self.sum.update(index, zipped[0], zipped[1]);
+            visited += 1;
+        }
+        self.out.set_from(self.sum.mean(), graph);
+        self.visited.set(visited as f64, graph);
+    }
+}
+
 impl RegisterSignal for DummyBookSignal {
     type Child = DummyBookSignal;
+    const PARAMS: bool = false;
 
     fn get_inputs() -> HashMap<&'static str, SignalType> {
         let signals = vec![("input", SignalType::Book)];
@@ -47,10 +415,11 @@ impl RegisterSignal for DummyBookSignal {
 
     fn create(
         mut outs: HashMap<&'static str, ConsumerOutput>,
-        _: InputLoader,
+        mut ins: InputLoader,
         json: Option<&str>,
     ) -> Result<DummyBookSignal, anyhow::Error> {
         assert_eq!(json, None);
+        ins.load_input::<BookViewer>("input")?;
         Ok(DummyBookSignal {
             output: outs.remove("out").unwrap(),
         })
@@ -59,6 +428,7 @@ impl RegisterSignal for DummyBookSignal {
 
 impl RegisterSignal for DummyConsumerSignal {
     type Child = DummyConsumerSignal;
+    const PARAMS: bool = false;
     fn get_inputs() -> HashMap<&'static str, SignalType> {
         vec![("input", SignalType::Consumer)].into_iter().collect()
     }
@@ -80,104 +450,372 @@ impl RegisterSignal for DummyConsumerSignal {
     }
 }
 
-#[test]
-fn test_duplicate_registry() {
-    let signals = vec![
-        ("dummy", make_signal_for::<DummyBookSignal>()),
-        ("dummy", make_signal_for::<DummyConsumerSignal>()),
-    ];
+impl RegisterSignal for DummyAggregateSignal {
+    type Child = DummyAggregateSignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("input", SignalType::Aggregate)].into_iter().collect()
+    }
 
-    let registrar = GraphRegistrar::new(&signals);
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
+    }
 
-    check_error!(registrar,
-    GraphError::DuplicateSignalName(name) => {
-        assert_eq!(name, "dummy")
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        json: Option<&str>,
+    ) -> Result<DummyAggregateSignal, anyhow::Error> {
+        assert_eq!(json, None);
+        Ok(DummyAggregateSignal {
+            output: outs.remove("out").unwrap(),
+            input: ins
+                .load_input::<AggregateInputGenerator>("input")?
+                .as_update(),
+        })
     }
-    );
 }
 
-fn get_sec_map() -> SecurityMap {
-    let btc = Security {
-        product: SmallString::from_str("BTCXBT"),
-        exchange: SmallString::from_str("BITMEX"),
-    };
+impl RegisterSignal for DummyParamSignal {
+    type Child = DummyParamSignal;
+    const UPDATE_PARAMS: bool = true;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("input", SignalType::Consumer)].into_iter().collect()
+    }
 
-    let sec_map = unsafe { SecurityMap::new_unchecked(&[btc.clone()]) };
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
+    }
 
-    sec_map
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        json: Option<&str>,
+    ) -> Result<DummyParamSignal, anyhow::Error> {
+        let DummyParams { scale } = serde_json::from_str(json.unwrap())?;
+        Ok(DummyParamSignal {
+            output: outs.remove("out").unwrap(),
+            input: ins.load_input("input")?,
+            scale,
+        })
+    }
 }
 
-#[test]
-fn test_not_found() {
-    let signals = vec![("dummy", make_signal_for::<DummyBookSignal>())];
+impl RegisterSignal for DummyCounterSignal {
+    type Child = DummyCounterSignal;
+    const PARAMS: bool = false;
+    const CHECKPOINT: bool = true;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("input", SignalType::Consumer)].into_iter().collect()
+    }
 
-    let registrar = GraphRegistrar::new(&signals).unwrap();
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
+    }
 
-    let sec_map = get_sec_map();
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyCounterSignal, anyhow::Error> {
+        Ok(DummyCounterSignal {
+            output: outs.remove("out").unwrap(),
+            input: ins.load_input("input")?,
+            calls: 0,
+        })
+    }
+}
 
-    let layout = [(
-        "wont_exist".to_string(),
-        SignalCall {
-            signal_name: "nonexistant".to_string(),
-            inputs: HashMap::new(),
-        },
-    )];
+impl RegisterSignal for DummyLazySignal {
+    type Child = DummyLazySignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("book", SignalType::Book), ("unused", SignalType::Consumer)]
+            .into_iter()
+            .collect()
+    }
 
-    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
-    GraphError::DefinitionNotFound{definition, signal} => {
-        assert_eq!(definition, "nonexistant");
-        assert_eq!(signal, "wont_exist");
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
     }
-    );
-}
 
-#[test]
-fn test_input_not_exist() {
-    let signals = vec![("dummy", make_signal_for::<DummyConsumerSignal>())];
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyLazySignal, anyhow::Error> {
+        ins.load_input::<BookViewer>("book")?;
+        Ok(DummyLazySignal {
+            output: outs.remove("out").unwrap(),
+        })
+    }
+}
 
-    let registrar = GraphRegistrar::new(&signals).unwrap();
+impl RegisterSignal for DummyLadderSignal {
+    type Child = DummyLadderSignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("book", SignalType::Book)].into_iter().collect()
+    }
 
-    let sec_map = get_sec_map();
+    fn get_outputs() -> HashSet<&'static str> {
+        HashSet::new()
+    }
 
-    let layout = vec![(
-        "dummy_sig".to_string(),
-        SignalCall {
-            signal_name: "dummy".to_string(),
-            inputs: vec![
-                (
-                    "input".to_string(),
-                    NamedSignalType::Consumer(("doesnt_exist_also".to_string(), "out".to_string())),
-                ),
-                (
-                    "not_found".to_string(),
-                    NamedSignalType::Consumer(("doesnt_exist".to_string(), "out".to_string())),
-                ),
-            ]
+    fn get_typed_outputs() -> HashMap<&'static str, OutputType> {
+        vec![("ladder", OutputType::of::<[f64; 3]>())]
             .into_iter()
-            .collect(),
-        },
-    )];
+            .collect()
+    }
 
-    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
-    GraphError::InputNotExist{signal, input} => {
-        assert_eq!(signal, "dummy_sig");
-        assert_eq!(input, "not_found");
+    fn create(
+        _: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyLadderSignal, anyhow::Error> {
+        ins.load_input::<BookViewer>("book")?;
+        // Asking for the wrong type is caught when loading
+        assert!(ins.load_typed_output::<[f64; 2]>("ladder").is_err());
+        Ok(DummyLadderSignal {
+            output: ins.load_typed_output("ladder")?,
+            calls: 0,
+        })
     }
-    );
 }
 
-#[test]
-fn test_input_type_mismatch() {
-    let signals = vec![("dummy", make_signal_for::<DummyBookSignal>())];
+impl RegisterSignal for DummyLadderSumSignal {
+    type Child = DummyLadderSumSignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        vec![("input", SignalType::Typed(OutputType::of::<[f64; 3]>()))]
+            .into_iter()
+            .collect()
+    }
 
-    let registrar = GraphRegistrar::new(&signals).unwrap();
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
+    }
 
-    let sec_map = get_sec_map();
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyLadderSumSignal, anyhow::Error> {
+        Ok(DummyLadderSumSignal {
+            output: outs.remove("out").unwrap(),
+            input: ins.load_typed_input("input")?,
+        })
+    }
+}
 
-    let layout = vec![(
-        "dummy_sig".to_string(),
-        SignalCall {
-            signal_name: "dummy".to_string(),
+impl RegisterSignal for DummyClashSignal {
+    type Child = DummyClashSignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        HashMap::new()
+    }
+
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["out"].into_iter().collect()
+    }
+
+    fn get_typed_outputs() -> HashMap<&'static str, OutputType> {
+        vec![("out", OutputType::of::<(f64, f64)>())]
+            .into_iter()
+            .collect()
+    }
+
+    fn create(
+        _: HashMap<&'static str, ConsumerOutput>,
+        _: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyClashSignal, anyhow::Error> {
+        Ok(DummyClashSignal)
+    }
+}
+
+impl RegisterSignal for DummyDerivedSignal {
+    type Child = DummyDerivedSignal;
+    const PARAMS: bool = false;
+    fn get_inputs() -> HashMap<&'static str, SignalType> {
+        DummyDerivedInputs::get_inputs()
+    }
+
+    fn get_outputs() -> HashSet<&'static str> {
+        vec!["all_valid", "any_changed"].into_iter().collect()
+    }
+
+    fn create(
+        mut outs: HashMap<&'static str, ConsumerOutput>,
+        mut ins: InputLoader,
+        _: Option<&str>,
+    ) -> Result<DummyDerivedSignal, anyhow::Error> {
+        Ok(DummyDerivedSignal {
+            inputs: DummyDerivedInputs::load(&mut ins)?,
+            all_valid: outs.remove("all_valid").unwrap(),
+            any_changed: outs.remove("any_changed").unwrap(),
+        })
+    }
+}
+
+#[test]
+fn test_duplicate_registry() {
+    let signals = vec![
+        ("dummy", make_signal_for::<DummyBookSignal>()),
+        ("dummy", make_signal_for::<DummyConsumerSignal>()),
+    ];
+
+    let registrar = GraphRegistrar::new(&signals);
+
+    check_error!(registrar,
+    GraphError::DuplicateSignalName(name) => {
+        assert_eq!(name, "dummy")
+    }
+    );
+}
+
+fn get_btc() -> Security {
+    Security {
+        product: SmallString::from_str("BTCXBT"),
+        exchange: SmallString::from_str("BITMEX"),
+    }
+}
+
+fn get_sec_map() -> SecurityMap {
+    let sec_map = SecurityMap::new(&[get_btc()]);
+
+    sec_map
+}
+
+fn get_all_registrar() -> GraphRegistrar {
+    let signals = vec![
+        ("book", make_signal_for::<DummyBookSignal>()),
+        ("consumer", make_signal_for::<DummyConsumerSignal>()),
+        ("aggregate", make_signal_for::<DummyAggregateSignal>()),
+        ("param", make_signal_for::<DummyParamSignal>()),
+        ("lazy", make_signal_for::<DummyLazySignal>()),
+        ("counter", make_signal_for::<DummyCounterSignal>()),
+        ("ladder", make_signal_for::<DummyLadderSignal>()),
+        ("ladder_sum", make_signal_for::<DummyLadderSumSignal>()),
+        ("derived", make_signal_for::<DummyDerivedSignal>()),
+        ("scaled", make_signal_for::<DummyScaleSignal>()),
+        ("weighted", make_signal_for::<DummyWeightedSignal>()),
+        ("flicker", make_signal_for::<DummyFlickerSignal>()),
+        ("fade", make_signal_for::<DummyFadeSignal>()),
+        ("venues", make_signal_for::<RemoteVenueAggregator>()),
+    ];
+    GraphRegistrar::new(&signals).unwrap()
+}
+
+fn book_call(security: Security) -> SignalCall {
+    SignalCall {
+        signal_name: "book".to_string(),
+        inputs: vec![("input".to_string(), NamedSignalType::Book(security))]
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn consumer_call(definition: &str, parent: &str) -> SignalCall {
+    SignalCall {
+        signal_name: definition.to_string(),
+        inputs: vec![(
+            "input".to_string(),
+            NamedSignalType::Consumer((parent.to_string(), "out".to_string())),
+        )]
+        .into_iter()
+        .collect(),
+    }
+}
+
+fn aggregate_call(parents: &[&str]) -> SignalCall {
+    SignalCall {
+        signal_name: "aggregate".to_string(),
+        inputs: vec![(
+            "input".to_string(),
+            NamedSignalType::Aggregate(
+                parents
+                    .iter()
+                    .map(|p| (p.to_string(), "out".to_string()))
+                    .collect(),
+            ),
+        )]
+        .into_iter()
+        .collect(),
+    }
+}
+
+#[test]
+fn test_not_found() {
+    let signals = vec![("dummy", make_signal_for::<DummyBookSignal>())];
+
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let sec_map = get_sec_map();
+
+    let layout = [(
+        "wont_exist".to_string(),
+        SignalCall {
+            signal_name: "nonexistant".to_string(),
+            inputs: HashMap::new(),
+        },
+    )];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::DefinitionNotFound{definition, signal} => {
+        assert_eq!(definition, "nonexistant");
+        assert_eq!(signal, "wont_exist");
+    }
+    );
+}
+
+#[test]
+fn test_input_not_exist() {
+    let signals = vec![("dummy", make_signal_for::<DummyConsumerSignal>())];
+
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![(
+        "dummy_sig".to_string(),
+        SignalCall {
+            signal_name: "dummy".to_string(),
+            inputs: vec![
+                (
+                    "input".to_string(),
+                    NamedSignalType::Consumer(("doesnt_exist_also".to_string(), "out".to_string())),
+                ),
+                (
+                    "not_found".to_string(),
+                    NamedSignalType::Consumer(("doesnt_exist".to_string(), "out".to_string())),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        },
+    )];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::InputNotExist{signal, input} => {
+        assert_eq!(signal, "dummy_sig");
+        assert_eq!(input, "not_found");
+    }
+    );
+}
+
+#[test]
+fn test_input_type_mismatch() {
+    let signals = vec![("dummy", make_signal_for::<DummyBookSignal>())];
+
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![(
+        "dummy_sig".to_string(),
+        SignalCall {
+            signal_name: "dummy".to_string(),
             inputs: vec![(
                 "input".to_string(),
                 NamedSignalType::Consumer(("wrong_type".to_string(), "out".to_string())),
@@ -292,3 +930,1997 @@ fn test_parent_not_found() {
     }
     );
 }
+
+#[test]
+fn test_book_not_found() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let missing = Security::new("nowhere", "BTC");
+
+    let layout = vec![("book_sig".to_string(), book_call(missing.clone()))];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::BookNotFound{security} => {
+        assert_eq!(security, missing);
+    }
+    );
+}
+
+#[test]
+fn test_missing_subscription() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        (
+            "lazy_sig".to_string(),
+            SignalCall {
+                signal_name: "lazy".to_string(),
+                inputs: vec![
+                    ("book".to_string(), NamedSignalType::Book(get_btc())),
+                    (
+                        "unused".to_string(),
+                        NamedSignalType::Consumer(("book_sig".to_string(), "out".to_string())),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            },
+        ),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::MissingSubscription{signal, inputs} => {
+        assert_eq!(signal, "lazy_sig");
+        assert_eq!(inputs, vec!["unused"]);
+    }
+    );
+}
+
+#[test]
+fn test_aggregate_no_inputs() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![("agg".to_string(), aggregate_call(&[]))];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::AggregateNoInputs{signal, name} => {
+        assert_eq!(signal, "agg");
+        assert_eq!(name, "input");
+    }
+    );
+}
+
+#[test]
+fn test_large_aggregate() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    // Spans several mask words, with the last one partially filled
+    let parents = vec!["book_sig"; 150];
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("agg".to_string(), aggregate_call(&parents)),
+    ];
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let agg = graph.signal_listener("agg", "out").unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(sec_map.to_index(&get_btc()).unwrap(), &data, 0, |_, _| ());
+    assert_eq!(agg.get(), Some(300.0));
+}
+
+#[test]
+#[cfg(not(feature = "wide-index"))]
+fn test_too_many_signals() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout: Vec<_> = (0..MAX_GRAPH_INDEX)
+        .map(|i| (format!("book_{}", i), book_call(get_btc())))
+        .collect();
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::TooManySignals(count) => {
+        assert_eq!(count, MAX_GRAPH_INDEX);
+    }
+    );
+}
+
+#[test]
+#[cfg(not(feature = "wide-index"))]
+fn test_too_many_outputs() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    // Few enough signals, but each scaled signal has a typed output as well
+    let scaled = MAX_GRAPH_INDEX / 2;
+    let mut layout = vec![("book_sig".to_string(), book_call(get_btc()))];
+    for i in 0..scaled {
+        layout.push((format!("scaled_{}", i), consumer_call("scaled", "book_sig")));
+    }
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::TooManyOutputs(count) => {
+        assert_eq!(count, 2 * scaled + 1);
+    }
+    );
+}
+
+#[test]
+#[cfg(not(feature = "wide-index"))]
+fn test_too_many_aggregate_references() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let parents = vec!["book_sig"; 64];
+    let mut layout = vec![("book_sig".to_string(), book_call(get_btc()))];
+    // 1024 full aggregates is exactly 2^16 references
+    for i in 0..1024 {
+        layout.push((format!("agg_{}", i), aggregate_call(&parents)));
+    }
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::TooManyAggregateReferences(count) => {
+        assert_eq!(count, 1 << 16);
+    }
+    );
+}
+
+#[test]
+fn test_graph_cycle() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("agg".to_string(), aggregate_call(&["book_sig", "c2"])),
+        ("c1".to_string(), consumer_call("consumer", "agg")),
+        ("c2".to_string(), consumer_call("consumer", "c1")),
+        (
+            "unrelated".to_string(),
+            consumer_call("consumer", "book_sig"),
+        ),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::GraphCycle{call, signals, security} => {
+        assert_eq!(call, "agg");
+        assert_eq!(signals, vec!["agg", "c1", "c2"]);
+        assert_eq!(security, Some(get_btc()));
+    }
+    );
+}
+
+#[test]
+fn test_graph_self_cycle() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("agg".to_string(), aggregate_call(&["book_sig", "agg"])),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::GraphCycle{call, signals, security} => {
+        assert_eq!(call, "agg");
+        assert_eq!(signals, vec!["agg"]);
+        assert_eq!(security, Some(get_btc()));
+    }
+    );
+}
+
+#[test]
+fn test_graph_cycle_without_book() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "c2")),
+        ("c2".to_string(), consumer_call("consumer", "c1")),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::GraphCycle{call, signals, security} => {
+        assert_eq!(call, "c1");
+        assert_eq!(signals, vec!["c1", "c2"]);
+        assert_eq!(security, None);
+    }
+    );
+}
+
+#[test]
+fn test_node_no_params() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("param_sig".to_string(), consumer_call("param", "book_sig")),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::NodeNoParams(signal) => {
+        assert_eq!(signal, "param_sig");
+    }
+    );
+}
+
+#[test]
+fn test_node_got_params() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![("book_sig".to_string(), book_call(get_btc()))];
+    let params = maplit::hashmap! {
+        "book_sig".to_string() => "{}".to_string(),
+    };
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &params),
+    GraphError::NodeGotParams(signal) => {
+        assert_eq!(signal, "book_sig");
+    }
+    );
+}
+
+#[test]
+fn test_node_init_error() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("param_sig".to_string(), consumer_call("param", "book_sig")),
+    ];
+    let params = maplit::hashmap! {
+        "param_sig".to_string() => "{\"not_scale\": 1.0}".to_string(),
+    };
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &params),
+    GraphError::NodeInitError(err) => {
+        assert!(err.to_string().contains("scale"));
+    }
+    );
+}
+
+#[test]
+fn test_valid_graph() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "book_sig")),
+        ("param_sig".to_string(), consumer_call("param", "c1")),
+        ("agg".to_string(), aggregate_call(&["param_sig", "c1"])),
+    ];
+    let params = maplit::hashmap! {
+        "param_sig".to_string() => "{\"scale\": 2.0}".to_string(),
+    };
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+
+    let agg = graph.signal_listener("agg", "out").unwrap();
+
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(sec_map.to_index(&get_btc()).unwrap(), &data, 0, |_, _| ());
+    assert_eq!(agg.get(), Some(6.0));
+}
+
+#[test]
+fn test_update_params() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("param_sig".to_string(), consumer_call("param", "book_sig")),
+    ];
+    let params = maplit::hashmap! {
+        "param_sig".to_string() => "{\"scale\": 2.0}".to_string(),
+    };
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+
+    let param_sig = graph.signal_listener("param_sig", "out").unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(param_sig.get(), Some(4.0));
+
+    graph
+        .update_params("param_sig", "{\"scale\": 3.0}")
+        .unwrap();
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(param_sig.get(), Some(9.0));
+
+    check_error!(graph.update_params("param_sig", "{\"bad\": 3.0}"),
+    GraphError::NodeUpdateError{signal, ..} => {
+        assert_eq!(signal, "param_sig");
+    }
+    );
+
+    check_error!(graph.update_params("book_sig", "{}"),
+    GraphError::NodeNotUpdatable(signal) => {
+        assert_eq!(signal, "book_sig");
+    }
+    );
+
+    check_error!(graph.update_params("missing", "{}"),
+    GraphError::SignalNotFound(signal) => {
+        assert_eq!(signal, "missing");
+    }
+    );
+
+    // A rejected update leaves the old parameters in place
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(param_sig.get(), Some(12.0));
+    let description = graph.describe(&sec_map);
+    let described = description
+        .signals
+        .iter()
+        .find(|s| s.name == "param_sig")
+        .unwrap();
+    assert_eq!(described.params.as_deref(), Some("{\"scale\": 3.0}"));
+}
+
+#[test]
+fn test_checkpoint_restore() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "book_sig")),
+        ("counter_sig".to_string(), consumer_call("counter", "c1")),
+    ];
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    for _ in 0..3 {
+        graph.trigger_book(btc, &data, 0, |_, _| ());
+    }
+    // book outputs 2, 3, 4 so the counter has summed to 9
+    assert_eq!(
+        graph.signal_listener("counter_sig", "out").unwrap().get(),
+        Some(9.0)
+    );
+
+    let filename = std::env::temp_dir()
+        .join(format!("test_checkpoint_{}.json", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    graph.checkpoint(&filename).unwrap();
+
+    let checkpoint = arby::signal_graph::checkpoint::GraphCheckpoint::read(&filename).unwrap();
+    // Only signals which opt in are saved
+    assert_eq!(checkpoint.signals.len(), 1);
+    assert_eq!(checkpoint.signals["counter_sig"].state["calls"], 3);
+
+    let mut restored = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    restored.restore(&filename).unwrap();
+    let counter = restored.signal_listener("counter_sig", "out").unwrap();
+    assert_eq!(counter.get(), Some(9.0));
+    assert!(!counter.was_written());
+
+    // The fresh book signal restarts at 2
+    restored.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(counter.get(), Some(11.0));
+
+    let moved_layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "book_sig")),
+        (
+            "counter_sig".to_string(),
+            consumer_call("counter", "book_sig"),
+        ),
+    ];
+    let mut moved = registrar
+        .generate_graph(&moved_layout, &sec_map, &HashMap::new())
+        .unwrap();
+    check_error!(moved.restore(&filename),
+    GraphError::CheckpointMismatch{signal, ..} => {
+        assert_eq!(signal, "counter_sig");
+    }
+    );
+    assert_eq!(
+        moved.signal_listener("counter_sig", "out").unwrap().get(),
+        None
+    );
+
+    std::fs::remove_file(&filename).unwrap();
+}
+
+#[test]
+fn test_restore_failure() {
+    let registrar = get_all_registrar();
+    let sec_map = get_sec_map();
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("first".to_string(), consumer_call("counter", "book_sig")),
+        ("second".to_string(), consumer_call("counter", "book_sig")),
+    ];
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    for _ in 0..3 {
+        graph.trigger_book(btc, &data, 0, |_, _| ());
+    }
+    let mut checkpoint = graph.checkpoint_state().unwrap();
+
+    // Signals load in name order, so first is put back when second fails
+    checkpoint.signals.get_mut("second").unwrap().state = serde_json::json!({});
+    let mut restored = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    check_error!(restored.restore_state(&checkpoint),
+    GraphError::NodeStateError{signal, ..} => {
+        assert_eq!(signal, "second");
+    }
+    );
+    let state = restored.checkpoint_state().unwrap();
+    assert_eq!(state.signals["first"].state["calls"], 0);
+    assert_eq!(state.signals["second"].state["calls"], 0);
+    assert_eq!(
+        restored.signal_listener("first", "out").unwrap().get(),
+        None
+    );
+}
+
+#[test]
+fn test_restore_marks_written() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    let out = |parent: &str| (parent.to_string(), "out".to_string());
+    let venues = vec![out("counter_sig"), out("eth_book")];
+    let mut layout = two_book_layout(&eth);
+    layout.push((
+        "counter_sig".to_string(),
+        consumer_call("counter", "btc_book"),
+    ));
+    layout.push((
+        "venues_sig".to_string(),
+        SignalCall {
+            signal_name: "venues".to_string(),
+            inputs: vec![
+                (
+                    "fair_mids".to_string(),
+                    NamedSignalType::Aggregate(venues.clone()),
+                ),
+                ("fair_sizes".to_string(), NamedSignalType::Aggregate(venues)),
+            ]
+            .into_iter()
+            .collect(),
+        },
+    ));
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    for _ in 0..3 {
+        graph.trigger_book(btc, &data, 0, |_, _| ());
+    }
+    let checkpoint = graph.checkpoint_state().unwrap();
+
+    // The aggregator doesn't checkpoint, and only sees the restored counter
+    // on the eth trigger because it's marked written
+    let mut restored = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    restored.restore_state(&checkpoint).unwrap();
+    let fair = restored.signal_listener("venues_sig", "fair").unwrap();
+    restored.trigger_book(eth, &data, 0, |_, _| ());
+    // (9 * 9 + 2 * 2) / 11
+    assert_eq!(fair.get(), Some(85.0 / 11.0));
+}
+
+#[test]
+fn test_typed_checkpoint() {
+    let signals = vec![("typed", make_signal_for::<DummyTypedCounterSignal>())];
+
+    check_error!(GraphRegistrar::new(&signals),
+    GraphError::TypedCheckpoint{definition, output} => {
+        assert_eq!(definition, "typed");
+        assert_eq!(output, "out");
+    }
+    );
+}
+
+fn ladder_call(security: Security) -> SignalCall {
+    SignalCall {
+        signal_name: "ladder".to_string(),
+        inputs: vec![("book".to_string(), NamedSignalType::Book(security))]
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn ladder_sum_call(parent: &str, output: &str) -> SignalCall {
+    SignalCall {
+        signal_name: "ladder_sum".to_string(),
+        inputs: vec![(
+            "input".to_string(),
+            NamedSignalType::Consumer((parent.to_string(), output.to_string())),
+        )]
+        .into_iter()
+        .collect(),
+    }
+}
+
+#[test]
+fn test_typed_outputs() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("ladder_sig".to_string(), ladder_call(get_btc())),
+        ("sum".to_string(), ladder_sum_call("ladder_sig", "ladder")),
+        ("agg".to_string(), aggregate_call(&["book_sig", "sum"])),
+    ];
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+
+    let ladder = graph
+        .typed_listener::<[f64; 3]>("ladder_sig", "ladder")
+        .unwrap();
+    let sum = graph.signal_listener("sum", "out").unwrap();
+    assert_eq!(ladder.get(), None);
+    assert!(graph
+        .typed_listener::<[f64; 2]>("ladder_sig", "ladder")
+        .is_none());
+    assert!(graph.signal_listener("ladder_sig", "ladder").is_none());
+    assert!(graph.typed_listener::<f64>("sum", "out").is_none());
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(ladder.get(), Some([1.0, 2.0, 3.0]));
+    assert_eq!(sum.get(), Some(6.0));
+
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(ladder.get(), Some([2.0, 4.0, 6.0]));
+    assert!(!ladder.was_written());
+    assert_eq!(sum.get(), Some(12.0));
+    // book_sig is at 3, and typed values don't overlap the f64 outputs
+    assert_eq!(
+        graph.signal_listener("agg", "out").unwrap().get(),
+        Some(15.0)
+    );
+
+    let outputs = graph.load_outputs();
+    assert!(outputs
+        .iter()
+        .all(|((signal, _), _)| signal != "ladder_sig"));
+
+    let description = graph.describe(&sec_map);
+    let described = description
+        .signals
+        .iter()
+        .find(|s| s.name == "ladder_sig")
+        .unwrap();
+    assert_eq!(described.outputs[0].value_type, "[f64; 3]");
+    assert_eq!(described.outputs[0].value, None);
+}
+
+#[test]
+fn test_output_wrong_type() {
+    let registrar = get_all_registrar();
+
+    let sec_map = get_sec_map();
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("ladder_sig".to_string(), ladder_call(get_btc())),
+        ("sum".to_string(), ladder_sum_call("book_sig", "out")),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::OutputWrongType{input, signal, parent, output, wants, given} => {
+        assert_eq!(input, "input");
+        assert_eq!(signal, "sum");
+        assert_eq!(parent, "book_sig");
+        assert_eq!(output, "out");
+        assert_eq!(wants, "[f64; 3]");
+        assert_eq!(given, "f64");
+    }
+    );
+
+    let layout = vec![
+        ("ladder_sig".to_string(), ladder_call(get_btc())),
+        (
+            "c1".to_string(),
+            SignalCall {
+                signal_name: "consumer".to_string(),
+                inputs: vec![(
+                    "input".to_string(),
+                    NamedSignalType::Consumer(("ladder_sig".to_string(), "ladder".to_string())),
+                )]
+                .into_iter()
+                .collect(),
+            },
+        ),
+    ];
+
+    check_error!(registrar.generate_graph(&layout, &sec_map, &HashMap::new()),
+    GraphError::OutputWrongType{signal, wants, given, ..} => {
+        assert_eq!(signal, "c1");
+        assert_eq!(wants, "f64");
+        assert_eq!(given, "[f64; 3]");
+    }
+    );
+}
+
+#[test]
+fn test_duplicate_output_name() {
+    let signals = vec![("clash", make_signal_for::<DummyClashSignal>())];
+
+    check_error!(GraphRegistrar::new(&signals),
+    GraphError::DuplicateOutputName{definition, output} => {
+        assert_eq!(definition, "clash");
+        assert_eq!(output, "out");
+    }
+    );
+}
+
+#[test]
+fn test_derived_inputs() {
+    let inputs = DummyDerivedInputs::get_inputs();
+    assert_eq!(inputs.len(), 5);
+    assert!(matches!(inputs["book"], SignalType::Book));
+    assert!(matches!(inputs["input"], SignalType::Consumer));
+    assert!(matches!(inputs["many"], SignalType::Aggregate));
+    assert!(matches!(inputs["agg"], SignalType::Aggregate));
+    match inputs["ladder"] {
+        SignalType::Typed(output_type) => assert_eq!(output_type.name(), "[f64; 3]"),
+        other => panic!("Wrong signal type {:?}", other),
+    }
+
+    let registrar = get_all_registrar();
+
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    // The derived signal watches the eth book, but all its other inputs come from btc
+    let out = |parent: &str| (parent.to_string(), "out".to_string());
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("ladder_sig".to_string(), ladder_call(get_btc())),
+        (
+            "derived_sig".to_string(),
+            SignalCall {
+                signal_name: "derived".to_string(),
+                inputs: vec![
+                    ("book".to_string(), NamedSignalType::Book(eth.clone())),
+                    (
+                        "input".to_string(),
+                        NamedSignalType::Consumer(out("book_sig")),
+                    ),
+                    (
+                        "many".to_string(),
+                        NamedSignalType::Aggregate(vec![out("book_sig"), out("book_sig")]),
+                    ),
+                    (
+                        "agg".to_string(),
+                        NamedSignalType::Aggregate(vec![out("book_sig")]),
+                    ),
+                    (
+                        "ladder".to_string(),
+                        NamedSignalType::Consumer(("ladder_sig".to_string(), "ladder".to_string())),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            },
+        ),
+    ];
+
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let all_valid = graph.signal_listener("derived_sig", "all_valid").unwrap();
+    let any_changed = graph.signal_listener("derived_sig", "any_changed").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(all_valid.get(), Some(0.0));
+    assert_eq!(any_changed.get(), Some(0.0));
+
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(all_valid.get(), Some(1.0));
+    assert_eq!(any_changed.get(), Some(1.0));
+
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(all_valid.get(), Some(1.0));
+    assert_eq!(any_changed.get(), Some(0.0));
+}
+
+#[test]
+fn test_derived_signal() {
+    assert!(DummyScaleSignal::PARAMS);
+    assert!(!DummyScaleSignal::CLEANUP);
+    assert!(!DummyScaleSignal::UPDATE_PARAMS);
+    assert!(!DummyScaleSignal::CHECKPOINT);
+    let inputs = DummyScaleSignal::get_inputs();
+    assert_eq!(inputs.len(), 1);
+    assert!(matches!(inputs["input"], SignalType::Consumer));
+    assert_eq!(
+        DummyScaleSignal::get_outputs(),
+        vec!["out"].into_iter().collect()
+    );
+    assert_eq!(
+        DummyScaleSignal::get_typed_outputs()["pair"].name(),
+        "[f64; 2]"
+    );
+
+    let registrar = get_all_registrar();
+    let sec_map = get_sec_map();
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        (
+            "scaled_sig".to_string(),
+            consumer_call("scaled", "book_sig"),
+        ),
+    ];
+
+    // Required parameters must be given
+    let mut params = HashMap::new();
+    params.insert("scaled_sig".to_string(), "{}".to_string());
+    check_error!(registrar.generate_graph(&layout, &sec_map, &params),
+    GraphError::NodeInitError(err) => {
+        assert_eq!(err.to_string(), "Missing parameter scale");
+    }
+    );
+
+    params.insert("scaled_sig".to_string(), r#"{"scale": 2.0}"#.to_string());
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let out = graph.signal_listener("scaled_sig", "out").unwrap();
+    let pair = graph
+        .typed_listener::<[f64; 2]>("scaled_sig", "pair")
+        .unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(out.get(), Some(5.5));
+    assert_eq!(pair.get(), Some([2.0, 5.5]));
+}
+
+#[test]
+fn test_derived_hooks() {
+    assert!(DummyTotalSignal::UPDATE_PARAMS);
+    assert!(DummyTotalSignal::CHECKPOINT);
+
+    let signals = vec![
+        ("book", make_signal_for::<DummyBookSignal>()),
+        ("total", make_signal_for::<DummyTotalSignal>()),
+    ];
+    let registrar = GraphRegistrar::new(&signals).unwrap();
+    let sec_map = get_sec_map();
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("total_sig".to_string(), consumer_call("total", "book_sig")),
+    ];
+    let params = maplit::hashmap! {
+        "total_sig".to_string() => r#"{"scale": 2.0}"#.to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let total = graph.signal_listener("total_sig", "out").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(total.get(), Some(4.0));
+
+    // The derived update replaces the params field, and leaves it alone on failure
+    // to parse or validate
+    graph
+        .update_params("total_sig", r#"{"scale": 3.0}"#)
+        .unwrap();
+    check_error!(graph.update_params("total_sig", r#"{"scale": "big"}"#),
+    GraphError::NodeUpdateError{signal, ..} => {
+        assert_eq!(signal, "total_sig");
+    }
+    );
+    check_error!(graph.update_params("total_sig", r#"{"scale": -1.0}"#),
+    GraphError::NodeUpdateError{signal, ..} => {
+        assert_eq!(signal, "total_sig");
+    }
+    );
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(total.get(), Some(13.0));
+
+    // Only the checkpoint field is saved, as its own value
+    let checkpoint = graph.checkpoint_state().unwrap();
+    assert_eq!(checkpoint.signals["total_sig"].state, 13.0);
+
+    let filename = std::env::temp_dir()
+        .join(format!("test_derived_hooks_{}.json", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    graph.checkpoint(&filename).unwrap();
+    let mut restored = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    restored.restore(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+
+    // The fresh book signal restarts at 2, scaled by the original parameters
+    let total = restored.signal_listener("total_sig", "out").unwrap();
+    restored.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(total.get(), Some(17.0));
+}
+
+#[test]
+fn test_signal_params() {
+    let params = DummyScaleParams::parse(r#"{"scale": 2.0, "offset": 0.5}"#).unwrap();
+    assert_eq!(params.scale, 2.0);
+    assert_eq!(params.offset, 0.5);
+    assert_eq!(
+        DummyScaleParams::parse(r#"{"scale": 2.0}"#).unwrap().offset,
+        1.5
+    );
+
+    let error = |json: &str| DummyScaleParams::parse(json).err().unwrap().to_string();
+    assert_eq!(error(r#"{"offset": 0.5}"#), "Missing parameter scale");
+    assert_eq!(
+        error(r#"{"scale": 2.0, "sclae": 1.0}"#),
+        "Unknown parameter sclae"
+    );
+    assert_eq!(
+        error(r#"{"scale": 0.0}"#),
+        "Parameter scale must satisfy scale != 0.0"
+    );
+    assert_eq!(
+        error(r#"{"scale": 1.0, "offset": -1.0}"#),
+        "Parameter offset must satisfy offset >= 0.0"
+    );
+    assert!(error(r#"{"scale": "big"}"#).starts_with("Parameter scale:"));
+}
+
+#[test]
+fn test_param_schemas() {
+    let schemas = get_all_registrar().param_schemas();
+    assert_eq!(schemas.keys().cloned().collect::<Vec<_>>(), vec!["scaled"]);
+    assert_eq!(
+        serde_json::to_value(&schemas["scaled"]).unwrap(),
+        serde_json::json!({
+            "fields": [
+                {
+                    "name": "scale",
+                    "type": "f64",
+                    "default": null,
+                    "constraint": "scale != 0.0",
+                    "description": "Multiplies the input",
+                },
+                {
+                    "name": "offset",
+                    "type": "f64",
+                    "default": 1.5,
+                    "constraint": "offset >= 0.0",
+                    "description": null,
+                },
+            ]
+        })
+    );
+}
+
+// Lays out book signals for btc and eth, which only write when their own book triggers
+fn two_book_layout(eth: &Security) -> Vec<(String, SignalCall)> {
+    vec![
+        ("btc_book".to_string(), book_call(get_btc())),
+        ("eth_book".to_string(), book_call(eth.clone())),
+    ]
+}
+
+#[test]
+fn test_aggregate_iter_changed() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    // The second aggregate doesn't start at the front of the aggregate mappings
+    let mut layout = two_book_layout(&eth);
+    layout.push(("agg1".to_string(), aggregate_call(&["btc_book"])));
+    layout.push((
+        "agg2".to_string(),
+        aggregate_call(&["eth_book", "btc_book"]),
+    ));
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let agg2 = graph.signal_listener("agg2", "out").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    // Only changed entries are summed
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(agg2.get(), Some(3.0));
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(agg2.get(), Some(2.0));
+}
+
+#[test]
+fn test_aggregate_zip_changed() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    let out = |parent: &str| (parent.to_string(), "out".to_string());
+    let mut layout = two_book_layout(&eth);
+    layout.push((
+        "weighted_sig".to_string(),
+        SignalCall {
+            signal_name: "weighted".to_string(),
+            inputs: vec![
+                (
+                    "values".to_string(),
+                    NamedSignalType::Aggregate(vec![out("btc_book"), out("eth_book")]),
+                ),
+                (
+                    "weights".to_string(),
+                    NamedSignalType::Aggregate(vec![out("eth_book"), out("eth_book")]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        },
+    ));
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let mean = graph.signal_listener("weighted_sig", "out").unwrap();
+    let visited = graph.signal_listener("weighted_sig", "visited").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    // The btc value is still invalid
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(visited.get(), Some(2.0));
+    assert_eq!(mean.get(), None);
+
+    // Only the btc value changed, eth's weights are unchanged
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(visited.get(), Some(1.0));
+    assert_eq!(mean.get(), Some(2.0));
+
+    // Both indices change through the eth weights: (2 * 3 + 3 * 3) / 6
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(visited.get(), Some(2.0));
+    assert_eq!(mean.get(), Some(2.5));
+}
+
+#[test]
+fn test_aggregate_sees_invalidation() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    let out = |parent: &str| (parent.to_string(), "out".to_string());
+    let mut layout = two_book_layout(&eth);
+    layout.push((
+        "flicker_sig".to_string(),
+        SignalCall {
+            signal_name: "flicker".to_string(),
+            inputs: vec![("input".to_string(), NamedSignalType::Book(eth.clone()))]
+                .into_iter()
+                .collect(),
+        },
+    ));
+    layout.push((
+        "venues_sig".to_string(),
+        SignalCall {
+            signal_name: "venues".to_string(),
+            inputs: vec![
+                (
+                    "fair_mids".to_string(),
+                    NamedSignalType::Aggregate(vec![out("flicker_sig"), out("btc_book")]),
+                ),
+                (
+                    "fair_sizes".to_string(),
+                    NamedSignalType::Aggregate(vec![out("btc_book"), out("btc_book")]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        },
+    ));
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let fair = graph.signal_listener("venues_sig", "fair").unwrap();
+    let size = graph.signal_listener("venues_sig", "size").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(fair.get(), None);
+
+    // (1 * 2 + 2 * 2) / 4
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(fair.get(), Some(1.5));
+    assert_eq!(size.get(), Some(4.0));
+
+    // The venue going invalid is a change, and takes the aggregate with it
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(fair.get(), None);
+    assert_eq!(size.get(), None);
+
+    // (3 * 2 + 2 * 2) / 4
+    graph.trigger_book(eth, &data, 0, |_, _| ());
+    assert_eq!(fair.get(), Some(2.5));
+}
+
+#[test]
+fn test_quiet_invalidation() {
+    let registrar = get_all_registrar();
+    let sec_map = get_sec_map();
+    let book_input = |definition: &str| SignalCall {
+        signal_name: definition.to_string(),
+        inputs: vec![("input".to_string(), NamedSignalType::Book(get_btc()))]
+            .into_iter()
+            .collect(),
+    };
+    let layout = vec![
+        ("fade_sig".to_string(), book_input("fade")),
+        ("flicker_sig".to_string(), book_input("flicker")),
+    ];
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let fade = graph.signal_listener("fade_sig", "out").unwrap();
+    let flicker = graph.signal_listener("flicker_sig", "out").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(btc, &data, 0, |_, _| {
+        assert!(fade.was_written() && flicker.was_written());
+    });
+
+    // Only invalidate_and_notify marks the invalidation as written
+    graph.trigger_book(btc, &data, 0, |_, _| {
+        assert_eq!(fade.get(), None);
+        assert!(!fade.was_written());
+        assert_eq!(flicker.get(), None);
+        assert!(flicker.was_written());
+    });
+}
+
+#[test]
+fn test_patch_graph() {
+    let registrar = get_all_registrar();
+    let sec_map = get_sec_map();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "book_sig")),
+        ("counter_sig".to_string(), consumer_call("counter", "c1")),
+        ("param_sig".to_string(), consumer_call("param", "c1")),
+    ];
+    let params = maplit::hashmap! {
+        "param_sig".to_string() => "{\"scale\": 2.0}".to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    for _ in 0..2 {
+        graph.trigger_book(btc, &data, 0, |_, _| ());
+    }
+    let calls = |graph: &Graph| {
+        graph.checkpoint_state().unwrap().signals["counter_sig"].state["calls"].clone()
+    };
+    assert_eq!(calls(&graph), 2);
+
+    // List eth, aggregating it with the existing btc signals
+    let patch = GraphPatch {
+        add_securities: vec![eth.clone()],
+        add_signals: vec![
+            ("eth_book".to_string(), book_call(eth.clone())),
+            ("agg".to_string(), aggregate_call(&["c1", "eth_book"])),
+        ],
+        ..Default::default()
+    };
+    let sec_map = registrar.patch_graph(&mut graph, &sec_map, &patch).unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth_index = sec_map.to_index(&eth).unwrap();
+
+    // Untouched signals kept their values and state
+    let counter = graph.signal_listener("counter_sig", "out").unwrap();
+    let agg = graph.signal_listener("agg", "out").unwrap();
+    assert_eq!(counter.get(), Some(5.0));
+    assert_eq!(calls(&graph), 2);
+    assert_eq!(agg.get(), None);
+
+    // The new aggregate sees the retained c1 as written, along with eth's first value
+    graph.trigger_book(eth_index, &data, 0, |_, _| ());
+    assert_eq!(agg.get(), Some(5.0));
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(counter.get(), Some(9.0));
+    assert_eq!(calls(&graph), 3);
+    assert_eq!(agg.get(), Some(4.0));
+
+    // A failed patch leaves the graph alone
+    let patch = GraphPatch {
+        remove_securities: vec![eth.clone()],
+        ..Default::default()
+    };
+    check_error!(registrar.patch_graph(&mut graph, &sec_map, &patch),
+    GraphError::BookNotFound{security} => {
+        assert_eq!(security, eth);
+    }
+    );
+    let patch = GraphPatch {
+        add_securities: vec![get_btc()],
+        ..Default::default()
+    };
+    check_error!(registrar.patch_graph(&mut graph, &sec_map, &patch),
+    GraphError::DuplicateSecurity(security) => {
+        assert_eq!(security, get_btc());
+    }
+    );
+    let patch = GraphPatch {
+        remove_signals: vec!["missing".to_string()],
+        ..Default::default()
+    };
+    check_error!(registrar.patch_graph(&mut graph, &sec_map, &patch),
+    GraphError::SignalNotFound(signal) => {
+        assert_eq!(signal, "missing");
+    }
+    );
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(counter.get(), Some(14.0));
+
+    // Drop eth again, and rebuild the param signal with new parameters
+    let patch = GraphPatch {
+        remove_securities: vec![eth.clone()],
+        remove_signals: vec!["eth_book".to_string(), "agg".to_string()],
+        params: maplit::hashmap! {
+            "param_sig".to_string() => "{\"scale\": 3.0}".to_string(),
+        },
+        ..Default::default()
+    };
+    let sec_map = registrar.patch_graph(&mut graph, &sec_map, &patch).unwrap();
+    assert_eq!(sec_map.len(), 1);
+    assert!(graph.signal_listener("agg", "out").is_none());
+    let counter = graph.signal_listener("counter_sig", "out").unwrap();
+    let param = graph.signal_listener("param_sig", "out").unwrap();
+    assert_eq!(counter.get(), Some(14.0));
+    assert_eq!(param.get(), None);
+
+    graph.trigger_book(sec_map.to_index(&get_btc()).unwrap(), &data, 0, |_, _| ());
+    assert_eq!(counter.get(), Some(20.0));
+    assert_eq!(param.get(), Some(18.0));
+    assert_eq!(calls(&graph), 5);
+}
+
+#[test]
+fn test_patch_rebuilt_aggregator() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+
+    let venues_call = |venues: &[&str]| {
+        let venues: Vec<_> = venues
+            .iter()
+            .map(|parent| (parent.to_string(), "out".to_string()))
+            .collect();
+        SignalCall {
+            signal_name: "venues".to_string(),
+            inputs: vec![
+                (
+                    "fair_mids".to_string(),
+                    NamedSignalType::Aggregate(venues.clone()),
+                ),
+                ("fair_sizes".to_string(), NamedSignalType::Aggregate(venues)),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    };
+    let mut layout = two_book_layout(&eth);
+    layout.push((
+        "venues_sig".to_string(),
+        venues_call(&["btc_book", "eth_book"]),
+    ));
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth_index = sec_map.to_index(&eth).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    graph.trigger_book(eth_index, &data, 0, |_, _| ());
+    let fair = graph.signal_listener("venues_sig", "fair").unwrap();
+    assert_eq!(fair.get(), Some(2.0));
+
+    // Reordering the venues rebuilds the aggregator with an empty sum, which is
+    // seeded from btc even though only eth updates
+    let patch = GraphPatch {
+        add_signals: vec![(
+            "venues_sig".to_string(),
+            venues_call(&["eth_book", "btc_book"]),
+        )],
+        ..Default::default()
+    };
+    let sec_map = registrar.patch_graph(&mut graph, &sec_map, &patch).unwrap();
+    let fair = graph.signal_listener("venues_sig", "fair").unwrap();
+    assert_eq!(fair.get(), None);
+    graph.trigger_book(sec_map.to_index(&eth).unwrap(), &data, 0, |_, _| ());
+    // (2 * 2 + 3 * 3) / 5
+    assert_eq!(fair.get(), Some(2.6));
+}
+
+#[test]
+#[should_panic(expected = "another map")]
+fn test_patch_stale_index() {
+    let registrar = get_all_registrar();
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+
+    let mut graph = registrar
+        .generate_graph(&two_book_layout(&eth), &sec_map, &HashMap::new())
+        .unwrap();
+    let eth_index = sec_map.to_index(&eth).unwrap();
+
+    // The patched graph is smaller, and the old eth index is past the end of it
+    let patch = GraphPatch {
+        remove_securities: vec![eth.clone()],
+        remove_signals: vec!["eth_book".to_string()],
+        ..Default::default()
+    };
+    let patched = registrar.patch_graph(&mut graph, &sec_map, &patch).unwrap();
+    assert_eq!(patched.len(), 1);
+    graph.trigger_book(eth_index, &data, 0, |_, _| ());
+}
+
+fn wait_for_publish(outputs: &PublishedOutputs, version: u64) -> u64 {
+    let start = std::time::Instant::now();
+    while outputs.version() == version {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        std::thread::yield_now();
+    }
+    outputs.version()
+}
+
+#[test]
+fn test_graph_runner() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<GraphRunner>();
+    is_send_sync::<PublishedOutputs>();
+
+    let sec_map = SecurityMap::create(&[get_btc()]);
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("param_sig".to_string(), consumer_call("param", "book_sig")),
+    ];
+    let params = maplit::hashmap! {
+        "param_sig".to_string() => "{\"scale\": 2.0}".to_string(),
+    };
+    let (md_sender, md_receiver) = crossbeam_channel::bounded(10);
+    let build_map = sec_map.clone();
+    let runner = GraphRunner::spawn(
+        RunnerConfig::default(),
+        move || get_all_registrar().generate_graph(&layout, &build_map, &params),
+        md_receiver,
+    )
+    .unwrap();
+    assert!(runner.is_running());
+
+    let outputs = runner.outputs().clone();
+    let book = outputs.find("book_sig", "out").unwrap();
+    let param = outputs.find("param_sig", "out").unwrap();
+    assert!(outputs.find("book_sig", "missing").is_none());
+    assert_eq!(outputs.get(param), None);
+
+    let block = || MarketEventBlock {
+        received_time: 0,
+        exchange: Exchange::Bitmex,
+        events: MarketUpdates::Book(vec![].into_iter().collect()),
+    };
+    let version = outputs.version();
+    md_sender.send((btc, block())).unwrap();
+    let version = wait_for_publish(&outputs, version);
+    assert_eq!(outputs.get(book), Some(2.0));
+    assert_eq!(outputs.get(param), Some(4.0));
+
+    runner
+        .update_params("param_sig", "{\"scale\": 3.0}")
+        .unwrap();
+    check_error!(runner.update_params("missing", "{}"),
+    GraphError::SignalNotFound(signal) => {
+        assert_eq!(signal, "missing");
+    }
+    );
+    md_sender.send((btc, block())).unwrap();
+    wait_for_publish(&outputs, version);
+    assert_eq!(outputs.get(param), Some(9.0));
+    let snapshot = outputs.snapshot();
+    assert_eq!(snapshot.get(book), Some(3.0));
+    assert_eq!(snapshot.get(param), Some(9.0));
+    assert_eq!(snapshot.version(), outputs.version());
+
+    // The runner winds down once market data goes away
+    drop(md_sender);
+    let start = std::time::Instant::now();
+    while runner.is_running() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        std::thread::yield_now();
+    }
+    check_error!(runner.update_params("param_sig", "{\"scale\": 1.0}"),
+    GraphError::RunnerStopped => ()
+    );
+    runner.stop();
+
+    // Build errors come back from spawn
+    let (_md_sender, md_receiver) = crossbeam_channel::bounded(10);
+    let layout = vec![("param_sig".to_string(), consumer_call("param", "missing"))];
+    check_error!(GraphRunner::spawn(
+        RunnerConfig::default(),
+        move || get_all_registrar().generate_graph(&layout, &SecurityMap::create(&[get_btc()]), &HashMap::new()),
+        md_receiver,
+    ),
+    GraphError::ParentNotFound{..} => ()
+    );
+}
+
+#[test]
+fn test_published_outputs() {
+    let registrar = get_all_registrar();
+    let sec_map = get_sec_map();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let data = MarketUpdates::Book(vec![].into_iter().collect());
+    let layout = vec![
+        ("book_sig".to_string(), book_call(get_btc())),
+        ("c1".to_string(), consumer_call("consumer", "book_sig")),
+        ("c2".to_string(), consumer_call("consumer", "c1")),
+    ];
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let mut publisher = OutputPublisher::new(&graph);
+    let outputs = publisher.outputs().clone();
+
+    // Ids follow the names, not the graph layout
+    assert_eq!(outputs.len(), 3);
+    let ids: Vec<_> = outputs.ids().collect();
+    assert_eq!(
+        outputs.key(ids[0]),
+        &("book_sig".to_string(), "out".to_string())
+    );
+    assert_eq!(outputs.key(ids[2]), &("c2".to_string(), "out".to_string()));
+    let book = outputs.find("book_sig", "out").unwrap();
+    let c2 = outputs.find("c2", "out").unwrap();
+    let watcher = outputs.watcher("c2", "out").unwrap();
+    assert!(outputs.watcher("c2", "missing").is_none());
+    assert_eq!(outputs.version(), 1);
+    assert_eq!(watcher.get(), None);
+
+    graph.trigger_book(btc, &data, 0, |_, _| ());
+    assert_eq!(watcher.get(), None);
+    publisher.publish(&graph);
+    assert_eq!(outputs.version(), 2);
+    assert_eq!(outputs.get(book), Some(2.0));
+    assert_eq!(watcher.get(), Some(2.0));
+
+    // Every output is the same in any one publish, so a reader can spot torn reads
+    let reader = {
+        let outputs = outputs.clone();
+        std::thread::spawn(move || {
+            let mut snapshot = outputs.snapshot();
+            let mut last_version = snapshot.version();
+            while last_version < 10_000 {
+                outputs.read_into(&mut snapshot);
+                assert!(snapshot.version() >= last_version);
+                last_version = snapshot.version();
+                let value = snapshot.get(book);
+                assert!(value.is_some());
+                for id in outputs.ids() {
+                    assert_eq!(snapshot.get(id), value);
+                }
+            }
+        })
+    };
+    while outputs.version() < 10_000 {
+        graph.trigger_book(btc, &data, 0, |_, _| ());
+        publisher.publish(&graph);
+    }
+    reader.join().unwrap();
+    assert_eq!(outputs.get(c2), outputs.get(book));
+}
+
+fn book_events(levels: &[(usize, Side, f64)]) -> MarketUpdates {
+    MarketUpdates::Book(
+        levels
+            .iter()
+            .map(|(cents, side, size)| BookUpdate {
+                cents: *cents,
+                side: *side,
+                size: *size,
+                exchange_time: 0,
+            })
+            .collect(),
+    )
+}
+
+fn assert_close(value: Option<f64>, expected: f64) {
+    let value = value.expect("value should be valid");
+    assert!(
+        (value - expected).abs() < 1e-9,
+        "{} is not {}",
+        value,
+        expected
+    );
+}
+
+// Fair and total score from the (dollars, size * score) of each level on both sides
+fn scored_fair(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> (f64, f64) {
+    let side = |levels: &[(f64, f64)]| {
+        let shares: f64 = levels.iter().map(|(_, shares)| shares).sum();
+        let price: f64 = levels.iter().map(|(price, shares)| price * shares).sum();
+        (price / shares, shares)
+    };
+    let (bid, bid_shares) = side(bids);
+    let (ask, ask_shares) = side(asks);
+    let shares = bid_shares + ask_shares;
+    ((bid * ask_shares + ask * bid_shares) / shares, shares)
+}
+
+fn trade_events(trades: &[(usize, Side)]) -> MarketUpdates {
+    MarketUpdates::Trades(
+        trades
+            .iter()
+            .map(|(cents, side)| Trade {
+                cents: *cents,
+                side: *side,
+                size: 1.0,
+                exchange_time: 0,
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn test_trade_through() {
+    let registrar =
+        GraphRegistrar::new(&[("through", make_signal_for::<TradeThrough>())]).unwrap();
+    let sec_map = get_sec_map();
+    let layout = vec![(
+        "through_sig".to_string(),
+        SignalCall {
+            signal_name: "through".to_string(),
+            inputs: vec![("book".to_string(), NamedSignalType::Book(get_btc()))]
+                .into_iter()
+                .collect(),
+        },
+    )];
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &HashMap::new())
+        .unwrap();
+    let ask = graph.signal_listener("through_sig", "through_ask").unwrap();
+    let bid = graph.signal_listener("through_sig", "through_bid").unwrap();
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let book = book_events(&[(10000, Side::Buy, 1.0), (10100, Side::Sell, 1.0)]);
+    graph.trigger_book(btc, &book, 0, |_, _| {
+        assert_eq!(ask.get(), None);
+        assert_eq!(bid.get(), None);
+    });
+
+    // Each side only goes through on its own aggressors
+    let buys = trade_events(&[(10150, Side::Buy), (9950, Side::Buy)]);
+    graph.trigger_book(btc, &buys, 0, |_, _| {
+        assert_eq!(ask.get(), Some(0.5));
+        assert_eq!(bid.get(), None);
+    });
+    let sells = trade_events(&[(9900, Side::Sell), (10200, Side::Sell)]);
+    graph.trigger_book(btc, &sells, 0, |_, _| {
+        assert_eq!(ask.get(), None);
+        assert_eq!(bid.get(), Some(1.0));
+    });
+
+    // The furthest trade through on each side wins
+    let both = trade_events(&[
+        (10150, Side::Buy),
+        (10300, Side::Buy),
+        (10050, Side::Buy),
+        (9950, Side::Sell),
+        (9975, Side::Sell),
+    ]);
+    graph.trigger_book(btc, &both, 0, |_, _| {
+        assert_eq!(ask.get(), Some(2.0));
+        assert_eq!(bid.get(), Some(0.5));
+    });
+
+    // Cleanup invalidates them once the update is done
+    assert_eq!(ask.get(), None);
+    assert_eq!(bid.get(), None);
+    let inside = trade_events(&[(10050, Side::Buy), (10050, Side::Sell)]);
+    graph.trigger_book(btc, &inside, 0, |_, _| {
+        assert_eq!(ask.get(), None);
+        assert_eq!(bid.get(), None);
+    });
+}
+
+#[test]
+fn test_fair_value() {
+    let registrar = GraphRegistrar::new(&[("fair", make_signal_for::<FairValue>())]).unwrap();
+    let sec_map = get_sec_map();
+    let fair_call = || SignalCall {
+        signal_name: "fair".to_string(),
+        inputs: vec![("book".to_string(), NamedSignalType::Book(get_btc()))]
+            .into_iter()
+            .collect(),
+    };
+    let cutoff = |cutoff: f64, rest: &str| {
+        format!(
+            r#"{{"kernel": {{"kind": "linear_cutoff", "cutoff": {}}}, "score_offset": 0.0,
+                "dollars_out": 10, "levels_out": 10, {}}}"#,
+            cutoff, rest
+        )
+    };
+    let names = ["dollars", "ticks", "bps", "cumulative", "no_touch"];
+    let layout: Vec<_> = names
+        .iter()
+        .map(|name| (name.to_string(), fair_call()))
+        .collect();
+    let params = maplit::hashmap! {
+        "dollars".to_string() => cutoff(4.0, r#""distance": "dollars""#),
+        "ticks".to_string() => cutoff(4.0, r#""distance": "ticks", "tick_cents": 50"#),
+        "bps".to_string() => cutoff(200.0, r#""distance": "bps""#),
+        "cumulative".to_string() => cutoff(4.0, r#""cumulative": true"#),
+        // Nothing at the touch scores, so a side with only its touch has no score
+        "no_touch".to_string() => r#"{"kernel": {"kind": "tabulated", "weights": [0.0, 1.0]},
+            "score_offset": 0.0, "dollars_out": 10, "levels_out": 10}"#.to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let listen = |name: &str| {
+        (
+            graph.signal_listener(name, "fair").unwrap(),
+            graph.signal_listener(name, "size").unwrap(),
+        )
+    };
+    let (dollars, ticks, bps, cumulative, no_touch) = (
+        listen("dollars"),
+        listen("ticks"),
+        listen("bps"),
+        listen("cumulative"),
+        listen("no_touch"),
+    );
+
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let book = book_events(&[
+        (10000, Side::Buy, 1.0),
+        (9900, Side::Buy, 3.0),
+        (10100, Side::Sell, 2.0),
+        (10300, Side::Sell, 2.0),
+    ]);
+    graph.trigger_book(btc, &book, 0, |_, _| ());
+
+    // The deeper levels are $1 and $2 out, of a $4 cutoff
+    let (fair, size) = scored_fair(
+        &[(100.0, 1.0), (99.0, 3.0 * 0.75)],
+        &[(101.0, 2.0), (103.0, 1.0)],
+    );
+    assert_close(dollars.0.get(), fair);
+    assert_close(dollars.1.get(), size);
+
+    // 2 and 4 ticks of 50 cents
+    let (fair, size) = scored_fair(&[(100.0, 1.0), (99.0, 1.5)], &[(101.0, 2.0), (103.0, 0.0)]);
+    assert_close(ticks.0.get(), fair);
+    assert_close(ticks.1.get(), size);
+
+    // Distances are relative to each side's touch
+    let ask_bps = 200.0 / 10100.0 * 10_000.0;
+    let (fair, size) = scored_fair(
+        &[(100.0, 1.0), (99.0, 1.5)],
+        &[(101.0, 2.0), (103.0, 2.0 * (1.0 - ask_bps / 200.0))],
+    );
+    assert_close(bps.0.get(), fair);
+    assert_close(bps.1.get(), size);
+
+    // Deeper levels are scored by the size up to and including them
+    let (fair, size) = scored_fair(
+        &[(100.0, 1.0), (99.0, 4.0 * 0.75)],
+        &[(101.0, 2.0), (103.0, 2.0)],
+    );
+    assert_close(cumulative.0.get(), fair);
+    assert_close(cumulative.1.get(), size);
+
+    // Only the bids have a level $1 out, until an ask level joins and leaves again
+    assert_eq!(no_touch.0.get(), None);
+    assert_eq!(no_touch.1.get(), None);
+    graph.trigger_book(btc, &book_events(&[(10200, Side::Sell, 1.0)]), 0, |_, _| ());
+    let (fair, size) = scored_fair(&[(99.0, 3.0)], &[(102.0, 1.0)]);
+    assert_close(no_touch.0.get(), fair);
+    assert_close(no_touch.1.get(), size);
+    graph.trigger_book(btc, &book_events(&[(10200, Side::Sell, 0.0)]), 0, |_, _| ());
+    assert_eq!(no_touch.0.get(), None);
+    assert_eq!(no_touch.1.get(), None);
+}
+
+const SECOND: u64 = 1_000_000;
+
+// Quotes for btc and eth, published as btc_quote and eth_quote
+fn quote_registrar(signals: &[(&'static str, SignalDefinition)]) -> GraphRegistrar {
+    let mut signals = signals.to_vec();
+    signals.push(("quote", make_signal_for::<DummyQuoteSignal>()));
+    GraphRegistrar::new(&signals).unwrap()
+}
+
+fn quote_layout(eth: &Security) -> Vec<(String, SignalCall)> {
+    vec![("btc_quote", get_btc()), ("eth_quote", eth.clone())]
+        .into_iter()
+        .map(|(name, security)| {
+            let call = SignalCall {
+                signal_name: "quote".to_string(),
+                inputs: vec![("book".to_string(), NamedSignalType::Book(security))]
+                    .into_iter()
+                    .collect(),
+            };
+            (name.to_string(), call)
+        })
+        .collect()
+}
+
+// Each input is (name, parent signal, parent output)
+fn consumers_call(signal_name: &str, inputs: &[(&str, &str, &str)]) -> SignalCall {
+    SignalCall {
+        signal_name: signal_name.to_string(),
+        inputs: inputs
+            .iter()
+            .map(|(name, parent, output)| {
+                (
+                    name.to_string(),
+                    NamedSignalType::Consumer((parent.to_string(), output.to_string())),
+                )
+            })
+            .collect(),
+    }
+}
+
+fn quote(cents: usize, size: f64) -> MarketUpdates {
+    book_events(&[(cents, Side::Buy, size)])
+}
+
+#[test]
+fn test_displacement() {
+    let registrar = quote_registrar(&[("displacement", make_signal_for::<Displacement>())]);
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+    let mut layout = quote_layout(&eth);
+    layout.push((
+        "displacement_sig".to_string(),
+        consumers_call(
+            "displacement",
+            &[
+                ("local_fair", "btc_quote", "out"),
+                ("local_size", "btc_quote", "size"),
+                ("remote_fair", "eth_quote", "out"),
+                ("remote_size", "eth_quote", "size"),
+            ],
+        ),
+    ));
+    // Fast averages take on a held price as soon as any time passes, and slow ones barely move
+    let params = maplit::hashmap! {
+        "displacement_sig".to_string() => r#"{"fast_half_life": 1e-6, "slow_half_life": 1e12,
+            "size_half_life": 1e-6, "premium_half_life": 1e-6}"#.to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let displacement = graph
+        .signal_listener("displacement_sig", "displacement")
+        .unwrap();
+    let premium = graph
+        .signal_listener("displacement_sig", "premium")
+        .unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+
+    graph.trigger_book(btc, &quote(10000, 1.0), 0, |_, _| ());
+    assert_eq!(displacement.get(), None);
+    assert_eq!(premium.get(), None);
+
+    // With no time passed the averages follow the prices
+    graph.trigger_book(eth, &quote(20000, 3.0), 0, |_, _| ());
+    assert_close(displacement.get(), 0.0);
+    assert_close(premium.get(), -100.0);
+
+    // The remote move only reaches the averages once it's been held
+    graph.trigger_book(eth, &quote(20400, 3.0), SECOND, |_, _| ());
+    assert_close(displacement.get(), 0.0);
+    graph.trigger_book(btc, &quote(10000, 1.0), 2 * SECOND, |_, _| ());
+    // The remote premium of 4 is discounted by the local share of size: 4 * 3 / 4
+    assert_close(displacement.get(), 3.0);
+    assert_close(premium.get(), -100.0);
+    graph.trigger_book(btc, &quote(10000, 1.0), 3 * SECOND, |_, _| ());
+    assert_close(displacement.get(), 3.0);
+    assert_close(premium.get(), -104.0);
+
+    // The remote size is read directly, so losing it invalidates the displacement
+    graph.trigger_book(eth, &book_events(&[]), 4 * SECOND, |_, _| ());
+    assert_eq!(displacement.get(), None);
+    assert_close(premium.get(), -104.0);
+}
+
+#[test]
+fn test_lead_lag() {
+    let registrar = quote_registrar(&[("lead_lag", make_signal_for::<LeadLag>())]);
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+    let mut layout = quote_layout(&eth);
+    layout.push((
+        "lead_lag_sig".to_string(),
+        consumers_call(
+            "lead_lag",
+            &[
+                ("leader", "btc_quote", "out"),
+                ("follower", "eth_quote", "out"),
+            ],
+        ),
+    ));
+    let params = maplit::hashmap! {
+        "lead_lag_sig".to_string() => r#"{"interval": 1.0, "max_lag": 1, "window": 4}"#.to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let lag = graph.signal_listener("lead_lag_sig", "lag").unwrap();
+    let correlation = graph
+        .signal_listener("lead_lag_sig", "correlation")
+        .unwrap();
+    let zero_lag = graph.signal_listener("lead_lag_sig", "zero_lag").unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+
+    // The follower takes each leader price a second later. The grid starts when both
+    // are valid, and prices change half way between grid points
+    let leader = [10000, 10100, 9900, 10200, 10000, 10300, 10100, 10400];
+    let update = |graph: &mut Graph, index: usize, time: u64| {
+        let follower = if index == 0 { 10000 } else { leader[index - 1] };
+        graph.trigger_book(btc, &quote(leader[index], 1.0), time, |_, _| ());
+        graph.trigger_book(eth, &quote(follower, 1.0), time, |_, _| ());
+    };
+    update(&mut graph, 0, 0);
+    for index in 1..6 {
+        update(&mut graph, index, index as u64 * SECOND - SECOND / 2);
+        // Samples 0 to index - 1 are in, and window + max_lag returns need 6 samples
+        assert_eq!(lag.get(), None);
+    }
+    update(&mut graph, 6, 6 * SECOND - SECOND / 2);
+    assert_close(lag.get(), 1.0);
+    assert_close(correlation.get(), 1.0);
+    assert!(zero_lag.get().unwrap().abs() < 1.0);
+
+    // A late call fills every missed grid point with the held prices, which don't move
+    update(&mut graph, 7, 7 * SECOND - SECOND / 2);
+    graph.trigger_book(btc, &quote(leader[7], 1.0), 100 * SECOND, |_, _| ());
+    assert_eq!(lag.get(), None);
+    assert_eq!(correlation.get(), None);
+
+    // Losing a price invalidates everything and restarts the grid when it's back
+    graph.trigger_book(eth, &book_events(&[]), 101 * SECOND, |_, _| ());
+    assert_eq!(lag.get(), None);
+    assert_eq!(zero_lag.get(), None);
+    for index in 0..7 {
+        let time = 200 * SECOND + index as u64 * SECOND - SECOND / 2;
+        update(&mut graph, index, time.max(200 * SECOND));
+    }
+    assert_close(lag.get(), 1.0);
+    assert_close(correlation.get(), 1.0);
+
+    // A new interval drops the samples taken on the old grid
+    graph
+        .update_params(
+            "lead_lag_sig",
+            r#"{"interval": 2.0, "max_lag": 1, "window": 4}"#,
+        )
+        .unwrap();
+    update(&mut graph, 7, 207 * SECOND);
+    update(&mut graph, 6, 209 * SECOND);
+    assert_eq!(lag.get(), None);
+    assert_eq!(correlation.get(), None);
+}
+
+#[test]
+fn test_pair_spread() {
+    let registrar = quote_registrar(&[("pair_spread", make_signal_for::<PairSpread>())]);
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+    let mut layout = quote_layout(&eth);
+    layout.push((
+        "spread_sig".to_string(),
+        consumers_call(
+            "pair_spread",
+            &[
+                ("leader", "btc_quote", "out"),
+                ("follower", "eth_quote", "out"),
+            ],
+        ),
+    ));
+    let params = maplit::hashmap! {
+        "spread_sig".to_string() => r#"{"half_life": 1.0}"#.to_string(),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let beta = graph.signal_listener("spread_sig", "beta").unwrap();
+    let spread = graph.signal_listener("spread_sig", "spread").unwrap();
+    let zscore = graph.signal_listener("spread_sig", "zscore").unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+
+    // The leader is always twice the follower plus 10
+    let update = |graph: &mut Graph, follower: usize, time: u64| {
+        graph.trigger_book(eth, &quote(follower, 1.0), time, |_, _| ());
+        graph.trigger_book(btc, &quote(2 * follower + 1000, 1.0), time, |_, _| ());
+    };
+    graph.trigger_book(btc, &quote(3000, 1.0), 0, |_, _| ());
+    assert_eq!(beta.get(), None);
+
+    // Beta needs the follower to have moved while held
+    update(&mut graph, 1000, 0);
+    update(&mut graph, 1200, SECOND);
+    assert_eq!(beta.get(), None);
+    update(&mut graph, 900, 2 * SECOND);
+    update(&mut graph, 1100, 3 * SECOND);
+    assert!((beta.get().unwrap() - 2.0).abs() < 1e-6);
+    assert!((spread.get().unwrap() - 10.0).abs() < 1e-5);
+    // The spread never varies, so there's no deviation to score it by
+    assert_eq!(zscore.get(), None);
+
+    graph.trigger_book(btc, &book_events(&[]), 4 * SECOND, |_, _| ());
+    assert_eq!(beta.get(), None);
+    assert_eq!(spread.get(), None);
+}
+
+#[test]
+fn test_model() {
+    let registrar = quote_registrar(&[("model", make_signal_for::<Model>())]);
+    let eth = Security::new("BITMEX", "ETHUSD");
+    let sec_map = SecurityMap::new(&[get_btc(), eth.clone()]);
+    let filename = std::env::temp_dir()
+        .join(format!("test_model_{}.json", std::process::id()))
+        .to_str()
+        .unwrap()
+        .to_string();
+    let write_model = |json: &str| std::fs::write(&filename, json).unwrap();
+    write_model(r#"{"intercept": 1.0, "features": [{"weight": 2.0}, {"weight": 3.0}]}"#);
+
+    let model_call = || SignalCall {
+        signal_name: "model".to_string(),
+        inputs: vec![(
+            "features".to_string(),
+            NamedSignalType::Aggregate(vec![
+                ("btc_quote".to_string(), "out".to_string()),
+                ("eth_quote".to_string(), "out".to_string()),
+            ]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    let mut layout = quote_layout(&eth);
+    layout.push(("model_sig".to_string(), model_call()));
+    layout.push(("partial_sig".to_string(), model_call()));
+    let params = maplit::hashmap! {
+        "model_sig".to_string() =>
+            format!(r#"{{"coefficients": {:?}, "reload": 0.01}}"#, filename),
+        "partial_sig".to_string() =>
+            format!(r#"{{"coefficients": {:?}, "min_valid": 1, "reload": 0.0}}"#, filename),
+    };
+    let mut graph = registrar
+        .generate_graph(&layout, &sec_map, &params)
+        .unwrap();
+    let prediction = graph.signal_listener("model_sig", "prediction").unwrap();
+    let valid = graph.signal_listener("model_sig", "valid").unwrap();
+    let partial = graph.signal_listener("partial_sig", "prediction").unwrap();
+    let btc = sec_map.to_index(&get_btc()).unwrap();
+    let eth = sec_map.to_index(&eth).unwrap();
+
+    // Every feature is needed unless min_valid says otherwise
+    graph.trigger_book(btc, &quote(100, 1.0), 0, |_, _| ());
+    assert_eq!(prediction.get(), None);
+    assert_eq!(valid.get(), Some(0.0));
+    assert_close(partial.get(), 3.0);
+
+    graph.trigger_book(eth, &quote(200, 1.0), 0, |_, _| ());
+    assert_close(prediction.get(), 9.0);
+    assert_eq!(valid.get(), Some(1.0));
+
+    graph.trigger_book(eth, &book_events(&[]), 0, |_, _| ());
+    assert_eq!(prediction.get(), None);
+    assert_eq!(valid.get(), Some(0.0));
+    graph.trigger_book(eth, &quote(200, 1.0), 0, |_, _| ());
+    assert_eq!(valid.get(), Some(1.0));
+
+    // A model with the wrong number of features is dropped by the watcher
+    write_model(
+        r#"{"intercept": 0.0, "features": [{"weight": 1.0}, {"weight": 1.0}, {"weight": 1.0}]}"#,
+    );
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    graph.trigger_book(eth, &quote(200, 1.0), 0, |_, _| ());
+    assert_close(prediction.get(), 9.0);
+
+    // While one with the right number replaces the old one
+    write_model(r#"{"intercept": 0.0, "features": [{"weight": 1.0}, {"weight": 1.0}]}"#);
+    let mut waited = 0;
+    while prediction.get() != Some(3.0) {
+        assert!(waited < 500, "Model was never reloaded");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        graph.trigger_book(eth, &quote(200, 1.0), 0, |_, _| ());
+        waited += 1;
+    }
+    std::fs::remove_file(&filename).unwrap();
+}